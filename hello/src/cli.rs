@@ -1,14 +1,26 @@
+use std::env;
 use std::str::FromStr;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use serde::{Serialize, Deserialize};
-use std::path::Path;
-use std::fs::File;
+use std::cell::OnceCell;
+use std::path::{Path, PathBuf};
+use std::fmt;
+use std::fs::{self, File};
 use std::io::{Read, Write};
-use llm_int::Provider;
+use std::process::{self, Stdio};
+use getopts::{Matches, Options, ParsingStyle};
+use llm_int::{GenerationOptions, Provider, ollama, openai};
+use llm_int::models::{self, Pricing};
+use crate::request::retry::RetryPolicy;
+use crate::mcp;
+use crate::vault::{self, Vault};
+use zeroize::Zeroizing;
 
 #[derive(Debug)]
 pub enum Error {
     ArgParse,
+    /// The command line doesn't make sense, the message is meant for the user
+    InvalidArgument(String),
     ReadConfigAction(String),
     FileRW,
     ConfigParse,
@@ -16,18 +28,285 @@ pub enum Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// What hello was asked to do
+#[derive(Debug)]
+pub enum Command {
+    Help,
+    Usage,
+    /// The arguments following --configure, they apply to the settings of `profile` if one was given
+    Configure { profile: Option<String>, args: Vec<String> },
+    Prompt(Box<Args>),
+}
+
+/// Everything given on the command line for a prompt
+#[derive(Debug)]
+pub struct Args {
+    /// Falls back to the profile's provider, the configured one, then openai
+    pub provider: Option<String>,
+    pub profile: Option<String>,
+    pub model: Option<String>,
+    /// Replaces the built-in instructions
+    pub system: Option<String>,
+    pub max_cost: Option<f64>,
+    pub tools: bool,
+    /// Paths or URLs
+    pub images: Vec<String>,
+    /// Path of the JSON Schema the answer must match
+    pub schema: Option<String>,
+    /// Only what was given, the defaults of the config file are applied later
+    pub generation: GenerationOptions,
+    pub prompt: Vec<String>,
+}
+
 pub enum Verb {
     Get,
-    Set
+    Set,
+    Unset,
+    List,
+    Create,
+    Copy,
+    Delete,
 }
 
+#[derive(Clone, Copy)]
 pub enum What {
     Key,
+    Host,
+    Header,
+    Model,
+    Resource,
+    Deployment,
+    ApiVersion,
+    /// A shell command printing the key
+    KeyCommand,
+    /// A file holding the key
+    KeyFile,
+    /// Used when --provider is not given
+    Provider,
+    /// Used when -p is not given
+    DefaultProfile,
+    SystemPrompt,
+    Generation(GenerationOption),
+}
+
+/// Generation options that can be given a default, named like their flag
+#[derive(Clone, Copy, Debug)]
+pub enum GenerationOption {
+    Temperature,
+    TopP,
+    MaxTokens,
+    Stop,
+    Seed,
+    PresencePenalty,
+    FrequencyPenalty,
+    ReasoningEffort,
+}
+
+impl GenerationOption {
+    const ALL: [GenerationOption; 8] = [
+        GenerationOption::Temperature,
+        GenerationOption::TopP,
+        GenerationOption::MaxTokens,
+        GenerationOption::Stop,
+        GenerationOption::Seed,
+        GenerationOption::PresencePenalty,
+        GenerationOption::FrequencyPenalty,
+        GenerationOption::ReasoningEffort,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            GenerationOption::Temperature => "temperature",
+            GenerationOption::TopP => "top-p",
+            GenerationOption::MaxTokens => "max-tokens",
+            GenerationOption::Stop => "stop",
+            GenerationOption::Seed => "seed",
+            GenerationOption::PresencePenalty => "presence-penalty",
+            GenerationOption::FrequencyPenalty => "frequency-penalty",
+            GenerationOption::ReasoningEffort => "reasoning-effort",
+        }
+    }
+}
+
+impl What {
+    fn name(&self) -> &'static str {
+        match self {
+            What::Key => "key",
+            What::Host => "host",
+            What::Header => "header",
+            What::Model => "model",
+            What::Resource => "resource",
+            What::Deployment => "deployment",
+            What::ApiVersion => "api-version",
+            What::KeyCommand => "key-command",
+            What::KeyFile => "key-file",
+            What::Provider => "provider",
+            What::DefaultProfile => "default-profile",
+            What::SystemPrompt => "system-prompt",
+            What::Generation(option) => option.name(),
+        }
+    }
+
+    /// Whether the setting belongs to a provider or endpoint rather than to hello as a whole
+    fn needs_who(&self) -> bool {
+        !matches!(self, What::Provider | What::DefaultProfile | What::SystemPrompt | What::Generation(_))
+    }
+
+    fn in_profile(&self) -> bool {
+        matches!(self, What::Provider | What::Model | What::Key | What::SystemPrompt | What::Generation(_))
+    }
+}
+
+/// What `--configure` was asked to do, `who` is only there for the settings that need it
+pub enum ConfigAction {
+    List,
+    Get { what: What, who: Option<Who> },
+    Set { what: What, who: Option<Who>, value: String },
+    Unset { what: What, who: Option<Who> },
+    CreateProfile(String),
+    /// Moves the keys into an encrypted vault
+    CreateVault,
+    CopyProfile { from: String, to: String },
+    DeleteProfile(String),
+}
+
+/// Either one of the built-in providers or a user named OpenAI compatible endpoint
+pub enum Who {
+    Provider(ProviderKind),
+    Endpoint(String),
+}
+
+impl Who {
+    fn name(&self) -> &str {
+        match self {
+            Who::Provider(provider) => provider.name(),
+            Who::Endpoint(name) => name,
+        }
+    }
+}
+
+/// Where a key was found, from the first place looked at to the last
+#[derive(Debug, PartialEq)]
+pub enum KeySource {
+    /// Named after the variable
+    Env(String),
+    Command,
+    File,
+    Vault,
+    Config,
+}
+
+impl fmt::Display for KeySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeySource::Env(var) => write!(f, "from ${var}"),
+            KeySource::Command => write!(f, "from the key command"),
+            KeySource::File => write!(f, "from the key file"),
+            KeySource::Vault => write!(f, "from the vault"),
+            KeySource::Config => write!(f, "from the config file"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Endpoint {
+    base_url: String,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    key: Option<String>,
+    model: Option<String>,
+    /// Set by hand for servers that report the usage when streaming like OpenAI does
+    #[serde(default)]
+    stream_usage: bool,
+}
+
+/// A named set of defaults selected with -p, whatever it leaves unset comes from the rest of the config
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Profile {
+    #[serde(default)]
+    pub provider: Option<String>,
+    /// Like the key, only used when --provider doesn't pick another provider
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Used instead of the key configured for the provider, ie: to bill another account.
+    /// It wins over every source `Config::get_key` looks at, the environment included.
+    #[serde(default)]
+    pub key: Option<String>,
+    /// Name of the vault entry holding the key instead
+    #[serde(default)]
+    vault_key: Option<String>,
+    #[serde(default)]
+    pub system_prompt: Option<String>,
+    #[serde(default)]
+    pub generation: GenerationOptions,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct AzureDeployment {
+    resource: Option<String>,
+    deployment: Option<String>,
+    api_version: Option<String>,
+    key: Option<String>,
+    model: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Config {
-    keys: HashMap<Provider, String>
+    /// Used when --provider is not given, openai if unset
+    #[serde(default)]
+    pub provider: Option<String>,
+    keys: BTreeMap<ProviderKind, String>,
+    /// Default model of the built-in providers, by their name
+    #[serde(default)]
+    models: BTreeMap<String, String>,
+    /// Shell commands printing a key, by provider or endpoint name
+    #[serde(default)]
+    key_commands: BTreeMap<String, String>,
+    /// Files holding a key, by provider or endpoint name
+    #[serde(default)]
+    key_files: BTreeMap<String, String>,
+    /// Names of the vault entries holding a key, by provider or endpoint name
+    #[serde(default)]
+    vault_keys: BTreeMap<String, String>,
+    #[serde(skip)]
+    vault_path: PathBuf,
+    /// Where the keys of the environment are looked up, the process environment but for the tests
+    #[serde(skip, default = "process_env")]
+    env_var: fn(&str) -> Option<String>,
+    /// Asks for the vault passphrase, told whether it is a new one, replaced by the tests
+    #[serde(skip, default = "passphrase_prompt")]
+    read_passphrase: fn(bool) -> std::result::Result<Zeroizing<String>, String>,
+    /// Unlocked on first use
+    #[serde(skip)]
+    vault: OnceCell<Vault>,
+    #[serde(default)]
+    ollama_host: Option<String>,
+    #[serde(default)]
+    endpoints: BTreeMap<String, Endpoint>,
+    #[serde(default)]
+    azure: AzureDeployment,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// Per model prices taking precedence over the built-in ones
+    #[serde(default)]
+    pricing: BTreeMap<String, Pricing>,
+    /// MCP servers started along with hello, their tools are handed to the model
+    #[serde(default)]
+    pub mcp_servers: BTreeMap<String, mcp::ServerConfig>,
+    /// Used for every request unless overridden by flags
+    #[serde(default)]
+    pub generation: GenerationOptions,
+    /// Replaces the built-in instructions unless overridden by --system
+    #[serde(default)]
+    pub system_prompt: Option<String>,
+    /// Per model choice between chat completions and the Responses API, for OpenAI and compatible endpoints
+    #[serde(default)]
+    openai_api: BTreeMap<String, openai::Api>,
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+    /// Used when -p is not given
+    #[serde(default)]
+    pub default_profile: Option<String>,
 }
 
 impl FromStr for Verb {
@@ -37,6 +316,11 @@ impl FromStr for Verb {
         match s {
             "get" => Ok(Verb::Get),
             "set" => Ok(Verb::Set),
+            "unset" => Ok(Verb::Unset),
+            "list" => Ok(Verb::List),
+            "create" => Ok(Verb::Create),
+            "copy" => Ok(Verb::Copy),
+            "delete" => Ok(Verb::Delete),
             _ => Err(Error::ArgParse),
         }
     }
@@ -48,72 +332,473 @@ impl FromStr for What {
     fn from_str(s: &str) -> std::result::Result<What, Self::Err> {
         match s {
             "key" => Ok(What::Key),
-            _ => Err(Error::ArgParse),
+            "host" => Ok(What::Host),
+            "header" => Ok(What::Header),
+            "model" => Ok(What::Model),
+            "resource" => Ok(What::Resource),
+            "deployment" => Ok(What::Deployment),
+            "api-version" => Ok(What::ApiVersion),
+            "key-command" => Ok(What::KeyCommand),
+            "key-file" => Ok(What::KeyFile),
+            "provider" => Ok(What::Provider),
+            "default-profile" => Ok(What::DefaultProfile),
+            "system-prompt" => Ok(What::SystemPrompt),
+            _ => GenerationOption::ALL.into_iter().find(|o| o.name() == s).map(What::Generation).ok_or(Error::ArgParse),
+        }
+    }
+}
+
+/// The built-in providers, without what locates them so that they can be compared and used as keys.
+/// Named like the unit variants of `Provider` so that the keys of older config files still read.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum ProviderKind {
+    OpenAi,
+    Anthropic,
+    Gemini,
+    Ollama,
+    Azure,
+}
+
+impl ProviderKind {
+    /// As given on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProviderKind::OpenAi => "openai",
+            ProviderKind::Anthropic => "anthropic",
+            ProviderKind::Gemini => "gemini",
+            ProviderKind::Ollama => "ollama",
+            ProviderKind::Azure => "azure",
         }
     }
+
+    fn requires_key(&self) -> bool {
+        !matches!(self, ProviderKind::Ollama)
+    }
 }
 
-// this is the dumbest shit ive done today
-struct ProviderExt(pub Provider);
-impl FromStr for ProviderExt {
+impl FromStr for ProviderKind {
     type Err = Error;
 
-    fn from_str(s: &str) -> std::result::Result<ProviderExt, Self::Err> {
+    fn from_str(s: &str) -> std::result::Result<ProviderKind, Self::Err> {
         match s {
-            "openai" => Ok(ProviderExt(Provider::OpenAi)),
+            "openai" => Ok(ProviderKind::OpenAi),
+            "anthropic" => Ok(ProviderKind::Anthropic),
+            "gemini" => Ok(ProviderKind::Gemini),
+            "ollama" => Ok(ProviderKind::Ollama),
+            "azure" => Ok(ProviderKind::Azure),
             _ => Err(Error::ArgParse),
         }
     }
 }
 
+impl FromStr for Who {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Who, Self::Err> {
+        match ProviderKind::from_str(s) {
+            Ok(kind) => Ok(Who::Provider(kind)),
+            Err(_) if s.is_empty() || s.starts_with('-') => Err(Error::ArgParse),
+            Err(_) => Ok(Who::Endpoint(s.to_string())),
+        }
+    }
+}
+
+/// Prompt mode flags, they must come before the prompt
+fn general_options(opts: &mut Options) {
+    opts.optflag("h", "help", "Print this help.")
+        .optflag("", "configure", "Execute the command in configuration mode, expects <verb> <what> <who> [value] after it.")
+        .optflag("", "usage", "Print the tokens spent so far, per provider and model.")
+        .optopt("p", "profile", "Use the defaults of this profile instead of the default one. \
+            With --configure, act on the settings of this profile.", "NAME")
+        .optopt("", "provider", "Send the prompt to the given provider or custom endpoint instead of openai.", "WHO")
+        .optopt("m", "model", "Use this model instead of the provider's default or the one configured for the endpoint, ie: o4-mini.", "NAME")
+        .optopt("s", "system", "Replace the built-in instructions given to the model.", "PROMPT")
+        .optopt("", "max-cost", "Refuse to send a request whose estimated prompt cost in dollars exceeds this limit.", "DOLLARS")
+        .optflag("", "no-tools", "Don't let the model read files, search and run commands in the working directory, nor use the tools of MCP servers. \
+            Commands and MCP tools not flagged as read only by their server are only ever run after you approve them with y.")
+        .optmulti("", "image", "Attach a PNG, JPEG, GIF or WebP image to the prompt, or the URL of one for providers that fetch it themselves. \
+            Can be repeated. An image or a PDF piped to stdin is attached as well, \
+            anything else piped must be text.", "FILE|URL")
+        .optopt("", "schema", "Ask for an answer matching the JSON Schema in this file and print only that JSON to stdout, without the interactive session. \
+            An answer that doesn't validate is sent back to the model with the errors, up to twice. Exits with 3 if no valid answer came. \
+            pattern and format are not checked. Tool calls that need approval are refused.", "FILE");
+}
+
+fn generation_options(opts: &mut Options) {
+    opts.optopt("", "temperature", "Randomness of the answer, usually between 0 and 2.", "FLOAT")
+        .optopt("", "top-p", "Only pick from the most likely tokens adding up to this probability.", "FLOAT")
+        .optopt("", "max-tokens", "Most tokens the answer can take, 4096 by default.", "COUNT")
+        .optmulti("", "stop", "Stop the answer at this sequence. Can be repeated.", "SEQUENCE")
+        .optopt("", "seed", "Ask for the same answer every time, as far as the provider can.", "INTEGER")
+        .optopt("", "presence-penalty", "Discourage tokens that already appeared.", "FLOAT")
+        .optopt("", "frequency-penalty", "Discourage tokens the more often they appeared.", "FLOAT")
+        .optopt("", "reasoning-effort", "One of: minimal, low, medium, high. Reasoning models stream what they think when the provider allows it, \
+            it is shown dimmed above the answer. Press Tab to expand or collapse it.", "EFFORT");
+}
+
+fn options() -> Options {
+    let mut opts = Options::new();
+    // whatever follows the first word of the prompt is part of it, even if it looks like a flag
+    opts.parsing_style(ParsingStyle::StopAtFirstFree);
+    general_options(&mut opts);
+    generation_options(&mut opts);
+    opts
+}
+
 pub fn print_usage(with_desc: bool) {
     if with_desc {
         println!("Interact with an LLM.\n");
     }
 
+    let mut general = Options::new();
+    general_options(&mut general);
+    let mut generation = Options::new();
+    generation_options(&mut generation);
+    let rows = |opts: &mut dyn Iterator<Item = String>| opts.collect::<Vec<String>>().join("\n");
+
     println!("Usage:
-    hello --configure <verb> <what> <who> [value]
-    hello <yap>... [options]
+    hello [options] [--] <yap>...
+    hello [-p <profile>] --configure <verb> <what> <who> [value]
+    hello --configure create|delete profile <profile>
+    hello --configure copy profile <profile> <new profile>
+    hello --configure create vault
+    hello --usage
 
 Options:
-    --configure Execute the command in configuration mode. If this flag is present, expects verb, what, who arguments. Must be the very first command argument.
+{}
+
+Generation options, providers ignore the ones they have no equivalent for:
+{}
+
+Config file:
+    Defaults for the generation options and the instructions can be set in the config file, ie: \"generation\": {{\"temperature\": 0.0, \"seed\": 42}}, \"system_prompt\": \"Answer in French.\"
+    Prices of known models can be overridden in the \"pricing\" section, ie: \"pricing\": {{\"my-model\": {{\"input\": 1.0, \"output\": 4.0, \"cached\": 0.25}}}} in dollars per million tokens.
+    Custom endpoints that report the token usage when streaming, like OpenAI does, can say so with \"stream_usage\": true in their entry of the \"endpoints\" section.
+    MCP servers are declared in the \"mcp_servers\" section, ie: \"mcp_servers\": {{\"git\": {{\"command\": \"uvx\", \"args\": [\"mcp-server-git\"], \"env\": {{}}}}}}
+    Every call to an MCP server is approved first, unless its entry has \"trust\": \"read_only\" for the tools it flags as read only, its resources and prompts, or \"trust\": \"all\".
+    gpt-5 and o3-pro go through OpenAI's Responses API, other models through chat completions. This can be changed per model in the \"openai_api\" section,
+    which also enables OpenAI's own tools, ie: \"openai_api\": {{\"gpt-4.1-mini\": {{\"api\": \"responses\", \"builtin_tools\": [\"web_search\", \"code_interpreter\"]}}}}
+    With \"store\": true in a model's entry OpenAI keeps its responses for 30 days, and only the new messages are sent after the first answer.
 
 Arguments:
-    <verb>  An action to take on the <what>. One of: get, set
-    <what>  The subject of the action. One of: key
-    <who>   A specifier for which <what> to act on. One of: openai
-    <yap>   Some words that make up a prompt. Beware that some shell programs interpret some characters so you may need to escape them. Alternativly you can enclose all of your prompt in double quotes to avoid this issue altogether.
+    <verb>  An action to take on the <what>. One of: list, get, set, unset
+            list prints every setting that has a value, get prints one of them, keys are masked.
+    <what>  The subject of the action. One of: key, key-command, key-file, host, header, model, resource, deployment, api-version, provider, system-prompt
+            or a generation option: temperature, top-p, max-tokens, stop, seed, presence-penalty, frequency-penalty, reasoning-effort
+            key is stored as is in the config file, on shared machines prefer key-command, a shell command printing the key (ie: \"pass show openai\"),
+            or key-file, a file holding it. create vault moves the keys of the config file into an encrypted vault, keys set afterwards go there too.
+            Its passphrase is asked on the terminal, or read from $HELLO_VAULT_PASSPHRASE.
+            The first of these that has a key is used: $HELLO_<WHO>_KEY, the provider's usual variable (ie: $OPENAI_API_KEY),
+            key-command, key-file, the vault and the config file. get key tells which one it was. A failing key-command or key-file is skipped with a warning.
+            host is the base URL of ollama or of a custom endpoint (ie: http://localhost:8000/v1), setting it for a new name creates the endpoint and unsetting it removes the endpoint.
+            header expects a \"Name: value\" pair sent with every request to a custom endpoint, unsetting it removes all of them.
+            model is the default model of a provider or endpoint, for azure the one served by the deployment.
+            resource, deployment and api-version locate the azure deployment. resource is either the resource name or its full endpoint URL.
+            provider, default-profile, system-prompt and the generation options take no <who>, they are the defaults of every prompt. Setting stop again adds a sequence.
+            With -p, the verb acts on the profile's provider, model, key, system-prompt or generation options instead, none of them take a <who>.
+            A profile's model and key only apply when --provider isn't given, the rest of the profile always does.
+            The profile's key then wins over every source listed for key, the environment included. It goes into the vault once there is one.
+    <who>   A specifier for which <what> to act on. One of: openai, anthropic, gemini, azure, ollama or the name of a custom OpenAI compatible endpoint
+    <yap>   Some words that make up a prompt. Options must come before it, or be ended with -- if the prompt starts with a dash.
+            Beware that some shell programs interpret some characters so you may need to escape them. Alternativly you can enclose all of your prompt in double quotes to avoid this issue altogether.
 
 $> hello what is the radius of the earth ?
 The radius of Earth is approximately 6,371 kilometers (3,959 miles). 
 This is the average radius, as Earth is not a perfect sphere but rather an oblate spheroid, slightly flattened at the poles and bulging at the equator.
 The equatorial radius is about 6,378 kilometers (3,963 miles), while the polar radius is about 6,357 kilometers (3,950 miles).
-");
+", general.usage_with_format(rows), generation.usage_with_format(rows));
+}
+
+/// Reads the value of `name` if it was given
+fn parse_opt<T: FromStr>(matches: &Matches, name: &str) -> Result<Option<T>> {
+    match matches.opt_str(name) {
+        None => Ok(None),
+        Some(value) => value.parse()
+            .map(Some)
+            .map_err(|_| Error::InvalidArgument(format!("Error: invalid value for --{name}: {value}"))),
+    }
+}
+
+/// `args` excludes the program name
+pub fn parse_args(args: &[String]) -> Result<Command> {
+    if args.is_empty() {
+        return Ok(Command::Help);
+    }
+
+    let matches = options().parse(args).map_err(|e| Error::InvalidArgument(format!("Error: {e}")))?;
+    if matches.opt_present("help") {
+        return Ok(Command::Help);
+    }
+    if matches.opt_present("usage") {
+        return Ok(Command::Usage);
+    }
+    if matches.opt_present("configure") {
+        return Ok(Command::Configure { profile: matches.opt_str("profile"), args: matches.free });
+    }
+
+    let max_cost = parse_opt::<f64>(&matches, "max-cost")?;
+    if max_cost.is_some_and(|limit| limit < 0.0 || limit.is_nan()) {
+        return Err(Error::InvalidArgument(String::from("Error: --max-cost expects an amount of dollars")));
+    }
+    let generation = GenerationOptions {
+        temperature: parse_opt(&matches, "temperature")?,
+        top_p: parse_opt(&matches, "top-p")?,
+        max_tokens: parse_opt(&matches, "max-tokens")?,
+        stop: matches.opt_strs("stop"),
+        seed: parse_opt(&matches, "seed")?,
+        presence_penalty: parse_opt(&matches, "presence-penalty")?,
+        frequency_penalty: parse_opt(&matches, "frequency-penalty")?,
+        reasoning_effort: parse_opt(&matches, "reasoning-effort")?,
+        response_format: None,
+    };
+
+    if matches.free.is_empty() {
+        return Err(Error::InvalidArgument(String::from("Error: missing a prompt")));
+    }
+
+    Ok(Command::Prompt(Box::new(Args {
+        provider: matches.opt_str("provider"),
+        profile: matches.opt_str("profile"),
+        model: matches.opt_str("model"),
+        system: matches.opt_str("system"),
+        max_cost,
+        tools: !matches.opt_present("no-tools"),
+        images: matches.opt_strs("image"),
+        schema: matches.opt_str("schema"),
+        generation,
+        prompt: matches.free,
+    })))
 }
 
-pub fn get_config_action(args: &[String]) -> Result<(Verb, What, Provider)> {
-    // could use a little bit more safeguards
-    assert!(args.len() == 3);
-    let verb = match Verb::from_str(args[0].as_str()) {
+/// `args` are whatever follows --configure: list, get <what> [who], unset <what> [who], set <what> [who] <value>,
+/// create profile <name>, copy profile <from> <to> or delete profile <name>.
+/// The settings of a profile take no who.
+pub fn get_config_action(args: &[String], in_profile: bool) -> Result<ConfigAction> {
+    let Some(verb) = args.first() else {
+        return Err(Error::ReadConfigAction(String::from("Error: configure mode expects a verb")));
+    };
+    let verb = match Verb::from_str(verb.as_str()) {
         Ok(v) => v,
         Err(_) => {
             return Err(Error::ReadConfigAction(String::from("Error: unrecognized verb argument")));
         }
     };
-    let what = match What::from_str(args[1].as_str()) {
-        Ok(w) => w,
-        Err(_) => {
+    if let Verb::List = verb {
+        if args.len() > 1 {
+            return Err(Error::ReadConfigAction(String::from("Error: list expects no other argument")));
+        }
+        return Ok(ConfigAction::List);
+    }
+    if let Verb::Create | Verb::Copy | Verb::Delete = verb {
+        if in_profile {
+            return Err(Error::ReadConfigAction(String::from("Error: profiles and the vault are created without -p")));
+        }
+        if let (Verb::Create, [what]) = (&verb, &args[1..]) {
+            if what == "vault" {
+                return Ok(ConfigAction::CreateVault);
+            }
+        }
+        return get_profile_action(verb, &args[1..]);
+    }
+
+    let what = match args.get(1).map(|w| What::from_str(w.as_str())) {
+        Some(Ok(w)) => w,
+        Some(Err(_)) => {
             return Err(Error::ReadConfigAction(String::from("Error: unrecognized what argument")));
+        },
+        None => {
+            return Err(Error::ReadConfigAction(String::from("Error: missing a what argument")));
         }
     };
-    let who = match ProviderExt::from_str(args[2].as_str()) {
-        Ok(ProviderExt(w)) => w,
-        Err(_) => {
-            return Err(Error::ReadConfigAction(String::from("Error: unrecognized who argument")));
+    if in_profile && !what.in_profile() {
+        return Err(not_in_profile());
+    }
+    let mut rest = args[2..].iter();
+    let who = if what.needs_who() && !in_profile {
+        match rest.next().map(|w| Who::from_str(w.as_str())) {
+            Some(Ok(w)) => Some(w),
+            Some(Err(_)) => {
+                return Err(Error::ReadConfigAction(String::from("Error: unrecognized who argument")));
+            },
+            None => {
+                return Err(Error::ReadConfigAction(String::from("Error: missing a who argument")));
+            }
         }
+    } else {
+        None
     };
 
-    Ok((verb, what, who))
+    let value = rest.next().cloned();
+    if rest.next().is_some() {
+        return Err(Error::ReadConfigAction(String::from("Error: too many arguments, quote values containing spaces")));
+    }
+    match (verb, value) {
+        (Verb::Set, Some(value)) => Ok(ConfigAction::Set { what, who, value }),
+        (Verb::Set, None) => Err(Error::ReadConfigAction(String::from("Error: missing a value to set"))),
+        (_, Some(_)) => Err(Error::ReadConfigAction(String::from("Error: only set expects a value"))),
+        (Verb::Get, None) => Ok(ConfigAction::Get { what, who }),
+        (_, None) => Ok(ConfigAction::Unset { what, who }),
+    }
+}
+
+fn get_profile_action(verb: Verb, args: &[String]) -> Result<ConfigAction> {
+    if args.first().map(String::as_str) != Some("profile") {
+        return Err(Error::ReadConfigAction(String::from("Error: only profiles and the vault can be created, and only profiles copied or deleted")));
+    }
+    match (verb, &args[1..]) {
+        (Verb::Create, [name]) => Ok(ConfigAction::CreateProfile(name.clone())),
+        (Verb::Copy, [from, to]) => Ok(ConfigAction::CopyProfile { from: from.clone(), to: to.clone() }),
+        (Verb::Delete, [name]) => Ok(ConfigAction::DeleteProfile(name.clone())),
+        (Verb::Copy, _) => Err(Error::ReadConfigAction(String::from("Error: copy expects the profile to copy and the name of the new one"))),
+        _ => Err(Error::ReadConfigAction(String::from("Error: expects the name of a profile"))),
+    }
+}
+
+/// Enough of a key to tell which one it is
+pub fn mask_key(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    if chars.len() < 12 {
+        return "*".repeat(chars.len());
+    }
+    let start: String = chars[..3].iter().collect();
+    let end: String = chars[chars.len() - 4..].iter().collect();
+    format!("{start}…{end}")
+}
+
+fn set_generation(generation: &mut GenerationOptions, option: GenerationOption, value: &str) -> Result<()> {
+    fn parse<T: FromStr>(option: GenerationOption, value: &str) -> Result<Option<T>> {
+        value.parse().map(Some).map_err(|_| Error::ReadConfigAction(format!("Error: invalid value for {}: {value}", option.name())))
+    }
+    match option {
+        GenerationOption::Temperature => generation.temperature = parse(option, value)?,
+        GenerationOption::TopP => generation.top_p = parse(option, value)?,
+        GenerationOption::MaxTokens => generation.max_tokens = parse(option, value)?,
+        // like the flag, every set adds a sequence
+        GenerationOption::Stop => generation.stop.push(value.to_string()),
+        GenerationOption::Seed => generation.seed = parse(option, value)?,
+        GenerationOption::PresencePenalty => generation.presence_penalty = parse(option, value)?,
+        GenerationOption::FrequencyPenalty => generation.frequency_penalty = parse(option, value)?,
+        GenerationOption::ReasoningEffort => generation.reasoning_effort = parse(option, value)?,
+    }
+    Ok(())
+}
+
+fn get_generation(generation: &GenerationOptions, option: GenerationOption) -> Option<String> {
+    match option {
+        GenerationOption::Temperature => generation.temperature.map(|v| v.to_string()),
+        GenerationOption::TopP => generation.top_p.map(|v| v.to_string()),
+        GenerationOption::MaxTokens => generation.max_tokens.map(|v| v.to_string()),
+        GenerationOption::Stop if generation.stop.is_empty() => None,
+        GenerationOption::Stop => Some(generation.stop.join("\n")),
+        GenerationOption::Seed => generation.seed.map(|v| v.to_string()),
+        GenerationOption::PresencePenalty => generation.presence_penalty.map(|v| v.to_string()),
+        GenerationOption::FrequencyPenalty => generation.frequency_penalty.map(|v| v.to_string()),
+        GenerationOption::ReasoningEffort => generation.reasoning_effort.map(|v| v.as_str().to_string()),
+    }
+}
+
+fn unset_generation(generation: &mut GenerationOptions, option: GenerationOption) {
+    match option {
+        GenerationOption::Temperature => generation.temperature = None,
+        GenerationOption::TopP => generation.top_p = None,
+        GenerationOption::MaxTokens => generation.max_tokens = None,
+        GenerationOption::Stop => generation.stop.clear(),
+        GenerationOption::Seed => generation.seed = None,
+        GenerationOption::PresencePenalty => generation.presence_penalty = None,
+        GenerationOption::FrequencyPenalty => generation.frequency_penalty = None,
+        GenerationOption::ReasoningEffort => generation.reasoning_effort = None,
+    }
+}
+
+/// $HELLO_<NAME>_KEY, then the variable the provider's own tools read
+fn key_env_vars(name: &str) -> Vec<String> {
+    let name: String = name.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' }).collect();
+    let mut vars = vec![format!("HELLO_{name}_KEY")];
+    let usual = match name.as_str() {
+        "OPENAI" => Some("OPENAI_API_KEY"),
+        "ANTHROPIC" => Some("ANTHROPIC_API_KEY"),
+        "GEMINI" => Some("GEMINI_API_KEY"),
+        "AZURE" => Some("AZURE_OPENAI_API_KEY"),
+        _ => None,
+    };
+    vars.extend(usual.map(String::from));
+    vars
+}
+
+/// Run by the shell so that pipes and quotes work, its errors go straight to the terminal.
+/// Only the first line is kept, `pass show` prints metadata after the password.
+fn run_key_command(name: &str, command: &str) -> std::result::Result<String, String> {
+    let output = process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("unable to run the key command of {name}: {e}"))?;
+    if !output.status.success() {
+        return Err(format!("the key command of {name} failed, {}", output.status));
+    }
+    first_line(&String::from_utf8_lossy(&output.stdout)).ok_or(format!("the key command of {name} printed nothing"))
+}
+
+fn read_key_file(name: &str, path: &str) -> std::result::Result<String, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("unable to read the key file of {name}: {e}"))?;
+    first_line(&content).ok_or(format!("the key file of {name} is empty"))
+}
+
+fn process_env() -> fn(&str) -> Option<String> {
+    |name| env::var(name).ok()
+}
+
+fn passphrase_prompt() -> fn(bool) -> std::result::Result<Zeroizing<String>, String> {
+    vault::read_passphrase
+}
+
+fn first_line(s: &str) -> Option<String> {
+    s.lines().next().map(str::trim).filter(|l| !l.is_empty()).map(String::from)
+}
+
+fn profile_vault_entry(profile: &str) -> String {
+    format!("profile:{profile}")
+}
+
+fn vault_error(e: String) -> Error {
+    Error::ReadConfigAction(format!("Error: {e}"))
+}
+
+fn not_in_profile() -> Error {
+    Error::ReadConfigAction(String::from("Error: a profile only holds a provider, model, key, system-prompt and generation options"))
+}
+
+pub fn get_provider(arg: &str, config: &Config) -> Result<Provider> {
+    match ProviderKind::from_str(arg) {
+        Ok(ProviderKind::OpenAi) => Ok(Provider::OpenAi),
+        Ok(ProviderKind::Anthropic) => Ok(Provider::Anthropic),
+        Ok(ProviderKind::Gemini) => Ok(Provider::Gemini),
+        Ok(ProviderKind::Ollama) => Ok(Provider::Ollama {
+            host: config.ollama_host.clone().unwrap_or(ollama::DEFAULT_HOST.to_string()),
+        }),
+        Ok(ProviderKind::Azure) => {
+            let (Some(resource), Some(deployment)) = (&config.azure.resource, &config.azure.deployment) else {
+                return Err(Error::ReadConfigAction(String::from("Error: the azure resource and deployment must be configured first")));
+            };
+            // Accept either the bare resource name or the whole endpoint
+            let resource = if resource.contains("://") { resource.clone() } else { format!("https://{resource}.openai.azure.com") };
+            Ok(Provider::Azure {
+                resource,
+                deployment: deployment.clone(),
+                api_version: config.azure.api_version.clone().unwrap_or(openai::AZURE_API_VERSION.to_string()),
+            })
+        },
+        Err(_) => match config.endpoints.get(arg) {
+            Some(endpoint) => Ok(Provider::OpenAiCompatible {
+                base_url: endpoint.base_url.clone(),
+                headers: endpoint.headers.iter().map(|(n, v)| (n.clone(), v.clone())).collect(),
+                key: endpoint.key.clone(),
+                stream_usage: endpoint.stream_usage,
+            }),
+            None => Err(Error::ReadConfigAction(String::from("Error: unrecognized provider argument"))),
+        }
+    }
 }
 
 impl Config {
@@ -129,28 +814,568 @@ impl Config {
         let mut contents = String::new();
         let _ = f.read_to_string(&mut contents);
 
-        if contents.len() == 0 {
-            Ok(Self {
-                keys: HashMap::new(),
-            })
+        let mut config = if contents.is_empty() {
+            Self {
+                provider: None,
+                keys: BTreeMap::new(),
+                models: BTreeMap::new(),
+                key_commands: BTreeMap::new(),
+                key_files: BTreeMap::new(),
+                vault_keys: BTreeMap::new(),
+                vault_path: PathBuf::new(),
+                env_var: process_env(),
+                read_passphrase: passphrase_prompt(),
+                vault: OnceCell::new(),
+                ollama_host: None,
+                endpoints: BTreeMap::new(),
+                azure: AzureDeployment::default(),
+                retry: RetryPolicy::default(),
+                pricing: BTreeMap::new(),
+                mcp_servers: BTreeMap::new(),
+                generation: GenerationOptions::default(),
+                system_prompt: None,
+                openai_api: BTreeMap::new(),
+                profiles: BTreeMap::new(),
+                default_profile: None,
+            }
         } else {
             match serde_json::from_str::<Config>(contents.as_str()) {
-                Ok(c) => Ok(c),
+                Ok(c) => c,
                 Err(c) => {
                     eprintln!("Failed to parse config file: {c:?}");
-                    Err(Error::ConfigParse)
+                    return Err(Error::ConfigParse);
                 }
             }
+        };
+        config.vault_path = p.as_ref().with_file_name(vault::VAULT_FILE_NAME);
+        Ok(config)
+    }
+
+    /// Into the vault once there is one, no copy is left in the config file then
+    pub fn insert_key(&mut self, who: Who, key: String) -> Result<()> {
+        if self.has_vault() {
+            let name = self.key_owner(&who)?;
+            self.change_vault(|vault| vault.insert(name.clone(), key))?;
+            self.forget_plain_key(&who)?;
+            let _ = self.vault_keys.insert(name.clone(), name);
+            return Ok(());
         }
 
+        match who {
+            Who::Provider(ProviderKind::Azure) => {
+                self.azure.key = Some(key);
+                Ok(())
+            },
+            Who::Provider(provider) if provider.requires_key() => {
+                let _ = self.keys.insert(provider, key);
+                Ok(())
+            },
+            Who::Provider(_) => Err(Error::ReadConfigAction(String::from("Error: this provider does not use a key"))),
+            Who::Endpoint(name) => {
+                self.get_endpoint_mut(&name)?.key = Some(key);
+                Ok(())
+            },
+        }
     }
 
-    pub fn insert_key(&mut self, provider: Provider, key: String) {
-        let _ = self.keys.insert(provider, key);
+    /// Key of the provider or endpoint called `name`, from the first of these that has one:
+    /// $HELLO_<NAME>_KEY, the usual variable of the provider, the key command, the key file, the vault and the config file itself.
+    /// A key command or file that fails is skipped with a warning, a vault that can't be unlocked is an error.
+    /// The key of the profile in use, see `get_profile_key`, is looked at before all of these.
+    /// The key is wiped once dropped but not the copies made on the way, in the environment or the output of the command.
+    pub fn get_key(&self, name: &str) -> Result<Option<(Zeroizing<String>, KeySource)>> {
+        for var in key_env_vars(name) {
+            if let Some(key) = (self.env_var)(&var).as_deref().and_then(first_line) {
+                return Ok(Some((Zeroizing::new(key), KeySource::Env(var))));
+            }
+        }
+        if let Some(command) = self.key_commands.get(name) {
+            match run_key_command(name, command) {
+                Ok(key) => return Ok(Some((Zeroizing::new(key), KeySource::Command))),
+                Err(e) => eprintln!("Warning: {e}, looking further"),
+            }
+        }
+        if let Some(path) = self.key_files.get(name) {
+            match read_key_file(name, path) {
+                Ok(key) => return Ok(Some((Zeroizing::new(key), KeySource::File))),
+                Err(e) => eprintln!("Warning: {e}, looking further"),
+            }
+        }
+        if let Some(entry) = self.vault_keys.get(name) {
+            let key = self.unlock_vault()?.get(entry).map(|key| Zeroizing::new(key.to_string()));
+            return Ok(key.map(|key| (key, KeySource::Vault)));
+        }
+
+        let key = match Who::from_str(name) {
+            Ok(Who::Provider(ProviderKind::Azure)) => self.azure.key.clone(),
+            Ok(Who::Provider(provider)) => self.keys.get(&provider).cloned(),
+            Ok(Who::Endpoint(name)) => self.endpoints.get(&name).and_then(|e| e.key.clone()),
+            Err(_) => None,
+        };
+        Ok(key.map(|key| (Zeroizing::new(key), KeySource::Config)))
     }
 
-    pub fn get_key(&self, provider: Provider) -> Option<String> {
-        self.keys.get(&provider).map(|s| s.to_owned())
+    pub fn set_host(&mut self, who: Who, host: String) -> Result<()> {
+        match who {
+            Who::Provider(ProviderKind::Ollama) => {
+                self.ollama_host = Some(host);
+                Ok(())
+            },
+            Who::Provider(_) => Err(Error::ReadConfigAction(String::from("Error: this provider does not have a configurable host"))),
+            Who::Endpoint(name) => {
+                self.endpoints.entry(name).or_default().base_url = host;
+                Ok(())
+            },
+        }
+    }
+
+    pub fn set_header(&mut self, who: Who, header: String) -> Result<()> {
+        let Who::Endpoint(name) = who else {
+            return Err(Error::ReadConfigAction(String::from("Error: headers can only be set on custom endpoints")));
+        };
+
+        let Some((header_name, value)) = header.split_once(':') else {
+            return Err(Error::ReadConfigAction(String::from("Error: expected a header formatted as \"Name: value\"")));
+        };
+
+        let _ = self.get_endpoint_mut(&name)?.headers.insert(header_name.trim().to_string(), value.trim().to_string());
+        Ok(())
+    }
+
+    pub fn set_model(&mut self, who: Who, model: String) -> Result<()> {
+        match who {
+            Who::Provider(ProviderKind::Azure) => {
+                self.azure.model = Some(model);
+                Ok(())
+            },
+            Who::Provider(provider) => {
+                let _ = self.models.insert(provider.name().to_string(), model);
+                Ok(())
+            },
+            Who::Endpoint(name) => {
+                self.get_endpoint_mut(&name)?.model = Some(model);
+                Ok(())
+            },
+        }
+    }
+
+    /// Model configured for the provider or endpoint called `name`.
+    /// An azure deployment serves a single model so its name is a good enough fallback.
+    pub fn get_model(&self, name: &str) -> Option<String> {
+        match Who::from_str(name) {
+            Ok(Who::Provider(ProviderKind::Azure)) => self.azure.model.clone().or(self.azure.deployment.clone()),
+            Ok(Who::Provider(provider)) => self.models.get(provider.name()).cloned(),
+            Ok(Who::Endpoint(name)) => self.endpoints.get(&name).and_then(|e| e.model.clone()),
+            Err(_) => None,
+        }
+    }
+
+    /// Price of `model` when served by `provider`, None if we have no idea
+    pub fn get_pricing(&self, provider: &Provider, model: &str) -> Option<Pricing> {
+        if let Some(pricing) = self.pricing.get(model) {
+            return Some(*pricing);
+        }
+        match provider {
+            // whatever runs locally is free
+            Provider::Ollama { .. } => Some(Pricing::FREE),
+            _ => models::find(model).map(|m| m.pricing),
+        }
+    }
+
+    /// The config wins, whether the model is named by its alias or not
+    pub fn get_openai_api(&self, model: &str) -> openai::Api {
+        let name = models::find(model).map(|m| m.name).unwrap_or(model);
+        self.openai_api.iter()
+            .find(|(m, _)| models::find(m).map(|i| i.name).unwrap_or(m) == name)
+            .map(|(_, api)| api.clone())
+            .unwrap_or(openai::Api::for_model(model))
+    }
+
+    pub fn set_azure(&mut self, who: Who, what: What, value: String) -> Result<()> {
+        let Who::Provider(ProviderKind::Azure) = who else {
+            return Err(Error::ReadConfigAction(String::from("Error: resource, deployment and api-version only apply to azure")));
+        };
+
+        match what {
+            What::Resource => self.azure.resource = Some(value),
+            What::Deployment => self.azure.deployment = Some(value),
+            What::ApiVersion => self.azure.api_version = Some(value),
+            _ => return Err(Error::ReadConfigAction(String::from("Error: azure only has a resource, deployment and api-version to set"))),
+        }
+        Ok(())
+    }
+
+    pub fn set(&mut self, what: What, who: Option<Who>, value: String) -> Result<()> {
+        match (what, who) {
+            (What::Key, Some(who)) => self.insert_key(who, value),
+            (What::Host, Some(who)) => self.set_host(who, value),
+            (What::Header, Some(who)) => self.set_header(who, value),
+            (What::Model, Some(who)) => self.set_model(who, value),
+            (What::Resource | What::Deployment | What::ApiVersion, Some(who)) => self.set_azure(who, what, value),
+            (What::KeyCommand, Some(who)) => {
+                let name = self.key_owner(&who)?;
+                let _ = self.key_commands.insert(name, value);
+                Ok(())
+            },
+            (What::KeyFile, Some(who)) => {
+                let name = self.key_owner(&who)?;
+                let _ = self.key_files.insert(name, value);
+                Ok(())
+            },
+            (What::Provider, _) => {
+                self.check_provider(&value)?;
+                self.provider = Some(value);
+                Ok(())
+            },
+            (What::DefaultProfile, _) => {
+                self.find_profile(&value)?;
+                self.default_profile = Some(value);
+                Ok(())
+            },
+            (What::SystemPrompt, _) => {
+                self.system_prompt = Some(value);
+                Ok(())
+            },
+            (What::Generation(option), _) => set_generation(&mut self.generation, option, &value),
+            (_, None) => Err(Error::ReadConfigAction(String::from("Error: missing a who argument"))),
+        }
+    }
+
+    /// Keys are masked, None if the setting has no value
+    pub fn get(&self, what: What, who: Option<&Who>) -> Result<Option<String>> {
+        let azure = |who: Option<&Who>| matches!(who, Some(Who::Provider(ProviderKind::Azure)));
+        let value = match (what, who) {
+            (What::Key, Some(who)) => {
+                let name = self.key_owner(who)?;
+                self.get_key(&name)?.map(|(key, source)| format!("{} ({source})", mask_key(&key)))
+            },
+            (What::KeyCommand, Some(who)) => self.key_commands.get(&self.key_owner(who)?).cloned(),
+            (What::KeyFile, Some(who)) => self.key_files.get(&self.key_owner(who)?).cloned(),
+            (What::Host, Some(Who::Provider(ProviderKind::Ollama))) => self.ollama_host.clone(),
+            (What::Host, Some(Who::Endpoint(name))) => Some(self.get_endpoint(name)?.base_url.clone()),
+            (What::Header, Some(Who::Endpoint(name))) => {
+                let headers: Vec<String> = self.get_endpoint(name)?.headers.iter().map(|(n, v)| format!("{n}: {v}")).collect();
+                if headers.is_empty() { None } else { Some(headers.join("\n")) }
+            },
+            (What::Model, Some(Who::Provider(ProviderKind::Azure))) => self.azure.model.clone(),
+            (What::Model, Some(Who::Provider(provider))) => self.models.get(provider.name()).cloned(),
+            (What::Model, Some(Who::Endpoint(name))) => self.get_endpoint(name)?.model.clone(),
+            (What::Resource, who) if azure(who) => self.azure.resource.clone(),
+            (What::Deployment, who) if azure(who) => self.azure.deployment.clone(),
+            (What::ApiVersion, who) if azure(who) => self.azure.api_version.clone(),
+            (What::Provider, _) => self.provider.clone(),
+            (What::DefaultProfile, _) => self.default_profile.clone(),
+            (What::SystemPrompt, _) => self.system_prompt.clone(),
+            (What::Generation(option), _) => get_generation(&self.generation, option),
+            _ => return Err(Error::ReadConfigAction(String::from("Error: there is no such setting for this who"))),
+        };
+        Ok(value)
+    }
+
+    pub fn unset(&mut self, what: What, who: Option<Who>) -> Result<()> {
+        let azure = |who: &Option<Who>| matches!(who, Some(Who::Provider(ProviderKind::Azure)));
+        match (what, who) {
+            (What::Key, Some(who)) => {
+                if let Some(entry) = self.vault_keys.remove(&self.key_owner(&who)?) {
+                    self.change_vault(|vault| vault.remove(&entry))?;
+                }
+                self.forget_plain_key(&who)?;
+            },
+            (What::KeyCommand, Some(who)) => { let _ = self.key_commands.remove(&self.key_owner(&who)?); },
+            (What::KeyFile, Some(who)) => { let _ = self.key_files.remove(&self.key_owner(&who)?); },
+            (What::Host, Some(Who::Provider(ProviderKind::Ollama))) => self.ollama_host = None,
+            // an endpoint is nothing without its host
+            (What::Host, Some(Who::Endpoint(name))) => {
+                if self.endpoints.remove(&name).is_none() {
+                    return Err(Error::ReadConfigAction(format!("Error: unknown endpoint \"{name}\"")));
+                }
+                if self.provider.as_ref() == Some(&name) {
+                    self.provider = None;
+                }
+                let _ = self.key_commands.remove(&name);
+                let _ = self.key_files.remove(&name);
+                if let Some(entry) = self.vault_keys.remove(&name) {
+                    self.change_vault(|vault| vault.remove(&entry))?;
+                }
+            },
+            (What::Header, Some(Who::Endpoint(name))) => self.get_endpoint_mut(&name)?.headers.clear(),
+            (What::Model, Some(Who::Provider(ProviderKind::Azure))) => self.azure.model = None,
+            (What::Model, Some(Who::Provider(provider))) => { let _ = self.models.remove(provider.name()); },
+            (What::Model, Some(Who::Endpoint(name))) => self.get_endpoint_mut(&name)?.model = None,
+            (What::Resource, who) if azure(&who) => self.azure.resource = None,
+            (What::Deployment, who) if azure(&who) => self.azure.deployment = None,
+            (What::ApiVersion, who) if azure(&who) => self.azure.api_version = None,
+            (What::Provider, _) => self.provider = None,
+            (What::DefaultProfile, _) => self.default_profile = None,
+            (What::SystemPrompt, _) => self.system_prompt = None,
+            (What::Generation(option), _) => unset_generation(&mut self.generation, option),
+            _ => return Err(Error::ReadConfigAction(String::from("Error: there is no such setting for this who"))),
+        }
+        Ok(())
+    }
+
+    /// Every setting that has a value, one `<what> [who]: <value>` line each
+    pub fn list(&self) -> Vec<String> {
+        let mut settings: Vec<(What, Option<Who>)> = vec![(What::Provider, None), (What::DefaultProfile, None), (What::SystemPrompt, None)];
+        settings.extend(GenerationOption::ALL.map(|o| (What::Generation(o), None)));
+        let whos = ["openai", "anthropic", "gemini", "ollama", "azure"].into_iter()
+            .map(String::from)
+            .chain(self.endpoints.keys().cloned());
+        for who in whos {
+            for what in [What::Host, What::Key, What::KeyCommand, What::KeyFile, What::Model, What::Header, What::Resource, What::Deployment, What::ApiVersion] {
+                settings.push((what, Who::from_str(&who).ok()));
+            }
+        }
+
+        let mut lines = Vec::new();
+        for (what, who) in settings {
+            let Ok(Some(value)) = self.get(what, who.as_ref()) else { continue };
+            let who = who.map(|who| format!(" {}", who.name())).unwrap_or_default();
+            for value in value.lines() {
+                lines.push(format!("{}{who}: {value}", what.name()));
+            }
+        }
+        lines.extend(self.profiles.keys().map(|name| format!("profile: {name}")));
+        lines
+    }
+
+    /// The profile called `name`, or else the default one. Without either the rest of the config applies as is.
+    pub fn get_profile(&self, name: Option<&str>) -> Result<Profile> {
+        match name.or(self.default_profile.as_deref()) {
+            Some(name) => self.find_profile(name).cloned(),
+            None => Ok(Profile::default()),
+        }
+    }
+
+    pub fn create_profile(&mut self, name: String) -> Result<()> {
+        self.copy_profile(None, name)
+    }
+
+    /// Creates `to` with the settings of `from`, or empty
+    pub fn copy_profile(&mut self, from: Option<&str>, to: String) -> Result<()> {
+        if self.profiles.contains_key(&to) {
+            return Err(Error::ReadConfigAction(format!("Error: profile \"{to}\" already exists")));
+        }
+        let mut profile = match from {
+            Some(from) => self.find_profile(from)?.clone(),
+            None => Profile::default(),
+        };
+        // each profile has its own entry so that unsetting one key leaves the other
+        if let Some(entry) = profile.vault_key.take() {
+            if let Some(key) = self.unlock_vault()?.get(&entry).map(String::from) {
+                let copy = profile_vault_entry(&to);
+                self.change_vault(|vault| vault.insert(copy.clone(), key))?;
+                profile.vault_key = Some(copy);
+            }
+        }
+        let _ = self.profiles.insert(to, profile);
+        Ok(())
+    }
+
+    pub fn delete_profile(&mut self, name: &str) -> Result<()> {
+        if let Some(entry) = self.find_profile(name)?.vault_key.clone() {
+            self.change_vault(|vault| vault.remove(&entry))?;
+        }
+        let _ = self.profiles.remove(name);
+        if self.default_profile.as_deref() == Some(name) {
+            self.default_profile = None;
+        }
+        Ok(())
+    }
+
+    pub fn set_in_profile(&mut self, name: &str, what: What, value: String) -> Result<()> {
+        if let What::Provider = what {
+            self.check_provider(&value)?;
+        }
+        if let (What::Key, true) = (what, self.has_vault()) {
+            self.find_profile(name)?;
+            let entry = profile_vault_entry(name);
+            self.change_vault(|vault| vault.insert(entry.clone(), value))?;
+            let profile = self.find_profile_mut(name)?;
+            profile.key = None;
+            profile.vault_key = Some(entry);
+            return Ok(());
+        }
+        let profile = self.find_profile_mut(name)?;
+        match what {
+            What::Provider => profile.provider = Some(value),
+            What::Model => profile.model = Some(value),
+            What::Key => profile.key = Some(value),
+            What::SystemPrompt => profile.system_prompt = Some(value),
+            What::Generation(option) => set_generation(&mut profile.generation, option, &value)?,
+            _ => return Err(not_in_profile()),
+        }
+        Ok(())
+    }
+
+    /// Keys are masked, None if the setting has no value
+    pub fn get_in_profile(&self, name: &str, what: What) -> Result<Option<String>> {
+        let profile = self.find_profile(name)?;
+        let value = match what {
+            What::Provider => profile.provider.clone(),
+            What::Model => profile.model.clone(),
+            What::Key => self.get_profile_key(profile)?.as_deref().map(|key| mask_key(key)),
+            What::SystemPrompt => profile.system_prompt.clone(),
+            What::Generation(option) => get_generation(&profile.generation, option),
+            _ => return Err(not_in_profile()),
+        };
+        Ok(value)
+    }
+
+    pub fn unset_in_profile(&mut self, name: &str, what: What) -> Result<()> {
+        if let What::Key = what {
+            if let Some(entry) = self.find_profile_mut(name)?.vault_key.take() {
+                self.change_vault(|vault| vault.remove(&entry))?;
+            }
+        }
+        let profile = self.find_profile_mut(name)?;
+        match what {
+            What::Provider => profile.provider = None,
+            What::Model => profile.model = None,
+            What::Key => profile.key = None,
+            What::SystemPrompt => profile.system_prompt = None,
+            What::Generation(option) => unset_generation(&mut profile.generation, option),
+            _ => return Err(not_in_profile()),
+        }
+        Ok(())
+    }
+
+    /// Whichever of the config file or the vault holds it, wiped once dropped
+    pub fn get_profile_key(&self, profile: &Profile) -> Result<Option<Zeroizing<String>>> {
+        match &profile.vault_key {
+            Some(entry) => Ok(self.unlock_vault()?.get(entry).map(|key| Zeroizing::new(key.to_string()))),
+            None => Ok(profile.key.clone().map(Zeroizing::new)),
+        }
+    }
+
+    /// Moves every key of the config file into a new vault, the keys set afterwards go there too
+    pub fn create_vault(&mut self) -> Result<()> {
+        if self.has_vault() {
+            return Err(Error::ReadConfigAction(String::from("Error: the vault already exists")));
+        }
+        let passphrase = (self.read_passphrase)(true).map_err(vault_error)?;
+        let mut vault = Vault::create_with(self.vault_path.clone(), &passphrase).map_err(vault_error)?;
+        let mut keys: Vec<(String, String)> = std::mem::take(&mut self.keys).into_iter().map(|(provider, key)| (provider.name().to_string(), key)).collect();
+        keys.extend(self.azure.key.take().map(|key| (String::from("azure"), key)));
+        keys.extend(self.endpoints.iter_mut().filter_map(|(name, e)| e.key.take().map(|key| (name.clone(), key))));
+        for (name, key) in keys {
+            vault.insert(name.clone(), key);
+            let _ = self.vault_keys.insert(name.clone(), name);
+        }
+        for (name, profile) in self.profiles.iter_mut() {
+            if let Some(key) = profile.key.take() {
+                let entry = profile_vault_entry(name);
+                vault.insert(entry.clone(), key);
+                profile.vault_key = Some(entry);
+            }
+        }
+
+        vault.save().map_err(vault_error)?;
+        let _ = self.vault.set(vault);
+        Ok(())
+    }
+
+    fn has_vault(&self) -> bool {
+        Vault::exists(&self.vault_path)
+    }
+
+    /// Undoes `create_vault` when the config file pointing into the vault couldn't be saved,
+    /// the keys are still in the config file then and the vault can be created again
+    pub fn discard_vault(&mut self) {
+        self.vault = OnceCell::new();
+        let _ = fs::remove_file(&self.vault_path);
+    }
+
+    fn unlock_vault(&self) -> Result<&Vault> {
+        if let Some(vault) = self.vault.get() {
+            return Ok(vault);
+        }
+        let passphrase = (self.read_passphrase)(false).map_err(vault_error)?;
+        let vault = Vault::unlock_with(self.vault_path.clone(), &passphrase).map_err(vault_error)?;
+        Ok(self.vault.get_or_init(|| vault))
+    }
+
+    /// Unlocks the vault if needed, applies `change` and writes it back
+    fn change_vault(&mut self, change: impl FnOnce(&mut Vault)) -> Result<()> {
+        self.unlock_vault()?;
+        let vault = self.vault.get_mut().expect("the vault was just unlocked");
+        change(vault);
+        vault.save().map_err(vault_error)
+    }
+
+    /// Removes the key stored as is in the config file
+    fn forget_plain_key(&mut self, who: &Who) -> Result<()> {
+        match who {
+            Who::Provider(ProviderKind::Azure) => self.azure.key = None,
+            Who::Provider(provider) if provider.requires_key() => { let _ = self.keys.remove(provider); },
+            Who::Provider(_) => return Err(Error::ReadConfigAction(String::from("Error: this provider does not use a key"))),
+            Who::Endpoint(name) => self.get_endpoint_mut(name)?.key = None,
+        }
+        Ok(())
+    }
+
+    /// Every setting of the profile that has a value, one `<what>: <value>` line each
+    pub fn list_profile(&self, name: &str) -> Result<Vec<String>> {
+        let whats = [What::Provider, What::Model, What::Key, What::SystemPrompt].into_iter()
+            .chain(GenerationOption::ALL.map(What::Generation));
+        let mut lines = Vec::new();
+        for what in whats {
+            if let Some(value) = self.get_in_profile(name, what)? {
+                lines.extend(value.lines().map(|v| format!("{}: {v}", what.name())));
+            }
+        }
+        Ok(lines)
+    }
+
+    /// Name under which the key sources of `who` are kept, if it uses a key
+    fn key_owner(&self, who: &Who) -> Result<String> {
+        match who {
+            Who::Provider(provider) if !provider.requires_key() => Err(Error::ReadConfigAction(String::from("Error: this provider does not use a key"))),
+            Who::Provider(provider) => Ok(provider.name().to_string()),
+            Who::Endpoint(name) => {
+                self.get_endpoint(name)?;
+                Ok(name.clone())
+            },
+        }
+    }
+
+    /// Either a built-in provider or an endpoint that exists
+    fn check_provider(&self, value: &str) -> Result<()> {
+        match Who::from_str(value) {
+            Ok(Who::Endpoint(name)) if !self.endpoints.contains_key(&name) => {
+                Err(Error::ReadConfigAction(format!("Error: unknown endpoint \"{name}\", set its host first")))
+            },
+            Ok(_) => Ok(()),
+            Err(_) => Err(Error::ReadConfigAction(String::from("Error: unrecognized provider argument"))),
+        }
+    }
+
+    fn find_profile(&self, name: &str) -> Result<&Profile> {
+        match self.profiles.get(name) {
+            Some(p) => Ok(p),
+            None => Err(Error::ReadConfigAction(format!("Error: unknown profile \"{name}\""))),
+        }
+    }
+
+    fn find_profile_mut(&mut self, name: &str) -> Result<&mut Profile> {
+        match self.profiles.get_mut(name) {
+            Some(p) => Ok(p),
+            None => Err(Error::ReadConfigAction(format!("Error: unknown profile \"{name}\", create it first"))),
+        }
+    }
+
+    fn get_endpoint(&self, name: &str) -> Result<&Endpoint> {
+        match self.endpoints.get(name) {
+            Some(e) => Ok(e),
+            None => Err(Error::ReadConfigAction(format!("Error: unknown endpoint \"{name}\""))),
+        }
+    }
+
+    fn get_endpoint_mut(&mut self, name: &str) -> Result<&mut Endpoint> {
+        match self.endpoints.get_mut(name) {
+            Some(e) => Ok(e),
+            None => Err(Error::ReadConfigAction(format!("Error: unknown endpoint \"{name}\", set its host first"))),
+        }
     }
 
     pub fn save<P: AsRef<Path>>(&self, p: P) -> Result<()> {
@@ -167,10 +1392,200 @@ impl Config {
                 eprintln!("{e:?}");
                 Err(Error::FileRW)
             }
-            Ok(mut f) => {
-                let _ = f.write_all(json.as_bytes());
-                Ok(())
-            }
-        } 
+            Ok(mut f) => f.write_all(json.as_bytes()).map_err(|e| {
+                eprintln!("{e:?}");
+                Error::FileRW
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Command> {
+        parse_args(&args.split_whitespace().map(String::from).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn flags_before_the_prompt() {
+        let Ok(Command::Prompt(args)) = parse("--model o4-mini -s terse --stop END --stop STOP --temperature 0.5 explain --model this") else {
+            panic!("expected a prompt");
+        };
+        assert_eq!(args.provider, None);
+        assert_eq!(args.model.as_deref(), Some("o4-mini"));
+        assert_eq!(args.system.as_deref(), Some("terse"));
+        assert_eq!(args.generation.stop, ["END", "STOP"]);
+        assert_eq!(args.generation.temperature, Some(0.5));
+        assert_eq!(args.prompt, ["explain", "--model", "this"]);
+        assert!(args.tools);
+    }
+
+    #[test]
+    fn double_dash_ends_the_flags() {
+        let Ok(Command::Prompt(args)) = parse("--no-tools -- -v means verbose?") else {
+            panic!("expected a prompt");
+        };
+        assert!(!args.tools);
+        assert_eq!(args.prompt, ["-v", "means", "verbose?"]);
+    }
+
+    #[test]
+    fn modes_and_errors() {
+        assert!(matches!(parse(""), Ok(Command::Help)));
+        assert!(matches!(parse("--usage"), Ok(Command::Usage)));
+        assert!(matches!(parse("--configure set key openai sk-1"), Ok(Command::Configure { profile: None, args }) if args.len() == 4));
+        assert!(matches!(parse("-p work --configure list"), Ok(Command::Configure { profile: Some(_), .. })));
+        assert!(matches!(parse("--temperature hot hi"), Err(Error::InvalidArgument(_))));
+        assert!(matches!(parse("--max-cost -1 hi"), Err(Error::InvalidArgument(_))));
+        assert!(matches!(parse("--reasoning-effort extreme hi"), Err(Error::InvalidArgument(_))));
+        assert!(matches!(parse("--model o4-mini"), Err(Error::InvalidArgument(_))));
+        assert!(matches!(parse("--bogus hi"), Err(Error::InvalidArgument(_))));
+    }
+
+    /// Blind to the keys found in the environment of whoever runs the tests
+    fn config(json: &str) -> Config {
+        let mut config: Config = serde_json::from_str(json).unwrap();
+        config.env_var = |_| None;
+        config.read_passphrase = |_| Ok(Zeroizing::new(String::from("correct horse")));
+        config
+    }
+
+    fn action(args: &str) -> Result<ConfigAction> {
+        get_config_action(&args.split_whitespace().map(String::from).collect::<Vec<_>>(), false)
+    }
+
+    #[test]
+    fn config_actions() {
+        assert!(matches!(action("list"), Ok(ConfigAction::List)));
+        assert!(matches!(action("get key openai"), Ok(ConfigAction::Get { what: What::Key, who: Some(_) })));
+        assert!(matches!(action("set temperature 0.2"), Ok(ConfigAction::Set { what: What::Generation(GenerationOption::Temperature), who: None, value }) if value == "0.2"));
+        assert!(matches!(action("unset host gw"), Ok(ConfigAction::Unset { what: What::Host, who: Some(Who::Endpoint(_)) })));
+        assert!(action("get key").is_err());
+        assert!(action("set model openai").is_err());
+        assert!(action("list everything").is_err());
+        assert!(action("frobnicate key openai").is_err());
+        assert!(matches!(action("copy profile work home"), Ok(ConfigAction::CopyProfile { from, to }) if from == "work" && to == "home"));
+        assert!(matches!(action("delete profile work"), Ok(ConfigAction::DeleteProfile(_))));
+        assert!(action("create profile").is_err());
+        assert!(action("create key openai").is_err());
+    }
+
+    #[test]
+    fn profiles() {
+        let in_profile = |args: &str| get_config_action(&args.split_whitespace().map(String::from).collect::<Vec<_>>(), true);
+        assert!(matches!(in_profile("set model gpt-4.1"), Ok(ConfigAction::Set { what: What::Model, who: None, .. })));
+        assert!(in_profile("set host gw http://localhost").is_err());
+        assert!(in_profile("create profile work").is_err());
+
+        let mut config = config(r#"{ "keys": {} }"#);
+        assert!(config.set_in_profile("work", What::Model, String::from("gpt-4.1")).is_err());
+        config.create_profile(String::from("work")).unwrap();
+        config.set_in_profile("work", What::Provider, String::from("azure")).unwrap();
+        config.set_in_profile("work", What::Key, String::from("0123456789abcdef")).unwrap();
+        config.set_in_profile("work", What::Generation(GenerationOption::Temperature), String::from("0")).unwrap();
+        assert!(config.set_in_profile("work", What::Provider, String::from("gw")).is_err());
+        assert_eq!(config.list_profile("work").unwrap(), ["provider: azure", "key: 012…cdef", "temperature: 0"]);
+
+        config.copy_profile(Some("work"), String::from("home")).unwrap();
+        config.set_in_profile("home", What::Provider, String::from("ollama")).unwrap();
+        assert!(config.copy_profile(Some("work"), String::from("home")).is_err());
+        config.set(What::DefaultProfile, None, String::from("home")).unwrap();
+        assert_eq!(config.get_profile(None).unwrap().provider.as_deref(), Some("ollama"));
+        assert_eq!(config.get_profile(Some("work")).unwrap().provider.as_deref(), Some("azure"));
+        assert!(config.get_profile(Some("play")).is_err());
+
+        config.delete_profile("home").unwrap();
+        assert_eq!(config.default_profile, None);
+        assert_eq!(config.list(), ["profile: work"]);
+    }
+
+    #[test]
+    fn global_settings() {
+        let mut config = config(r#"{ "keys": {}, "endpoints": {} }"#);
+        config.set(What::Generation(GenerationOption::Temperature), None, String::from("0.2")).unwrap();
+        assert!(config.set(What::Generation(GenerationOption::Seed), None, String::from("lucky")).is_err());
+        assert!(config.set(What::Provider, None, String::from("gw")).is_err());
+        config.set(What::Model, Who::from_str("anthropic").ok(), String::from("claude-opus-4-1")).unwrap();
+        config.set_host(Who::from_str("listed").unwrap(), String::from("http://localhost:8000/v1")).unwrap();
+        config.insert_key(Who::from_str("listed").unwrap(), String::from("sk-proj-0123456789abcd")).unwrap();
+
+        assert_eq!(config.get_model("anthropic").as_deref(), Some("claude-opus-4-1"));
+        assert_eq!(config.list(), [
+            "temperature: 0.2",
+            "model anthropic: claude-opus-4-1",
+            "host listed: http://localhost:8000/v1",
+            "key listed: sk-…abcd (from the config file)",
+        ]);
+        config.unset(What::Generation(GenerationOption::Temperature), None).unwrap();
+        assert_eq!(config.get(What::Generation(GenerationOption::Temperature), None).unwrap(), None);
+        assert_eq!(mask_key("short"), "*****");
+    }
+
+    #[test]
+    fn keys_of_older_config_files() {
+        let config: Config = serde_json::from_str(r#"{ "keys": { "OpenAi": "sk-old" } }"#).unwrap();
+        assert_eq!(config.keys.get(&ProviderKind::OpenAi).map(String::as_str), Some("sk-old"));
+        assert_eq!(ProviderKind::from_str("anthropic").map(|k| k.name()).ok(), Some("anthropic"));
+        assert!(ProviderKind::from_str("endpoint").is_err());
+    }
+
+    #[test]
+    fn key_sources() {
+        assert_eq!(key_env_vars("openai"), ["HELLO_OPENAI_KEY", "OPENAI_API_KEY"]);
+        assert_eq!(key_env_vars("my.gw"), ["HELLO_MY_GW_KEY"]);
+
+        let who = || Who::from_str("key-order").unwrap();
+        let mut config = config(r#"{ "keys": {} }"#);
+        assert!(config.set(What::KeyCommand, Some(who()), String::from("true")).is_err());
+        assert!(config.set(What::KeyFile, Who::from_str("ollama").ok(), String::from("/tmp/key")).is_err());
+        config.set_host(who(), String::from("http://localhost:8000/v1")).unwrap();
+        config.insert_key(who(), String::from("sk-config")).unwrap();
+        assert_eq!(config.get_key("key-order").unwrap(), Some((Zeroizing::new(String::from("sk-config")), KeySource::Config)));
+
+        let path = env::temp_dir().join(format!("hello-key-{}", process::id()));
+        fs::write(&path, "sk-file\n").unwrap();
+        config.set(What::KeyFile, Some(who()), path.to_string_lossy().to_string()).unwrap();
+        assert_eq!(config.get_key("key-order").unwrap(), Some((Zeroizing::new(String::from("sk-file")), KeySource::File)));
+
+        config.set(What::KeyCommand, Some(who()), String::from("printf 'sk-command\\nlogin: me\\n'")).unwrap();
+        assert_eq!(config.get_key("key-order").unwrap(), Some((Zeroizing::new(String::from("sk-command")), KeySource::Command)));
+        config.set(What::KeyCommand, Some(who()), String::from("exit 3")).unwrap();
+        assert_eq!(config.get_key("key-order").unwrap(), Some((Zeroizing::new(String::from("sk-file")), KeySource::File)));
+        let _ = fs::remove_file(&path);
+        assert_eq!(config.get_key("key-order").unwrap(), Some((Zeroizing::new(String::from("sk-config")), KeySource::Config)));
+
+        config.env_var = |name| (name == "HELLO_KEY_ORDER_KEY").then(|| String::from("sk-env"));
+        assert_eq!(config.get_key("key-order").unwrap(), Some((Zeroizing::new(String::from("sk-env")), KeySource::Env(String::from("HELLO_KEY_ORDER_KEY")))));
+    }
+
+    #[test]
+    fn keys_in_the_vault() {
+        let who = || Who::from_str("vaulted").unwrap();
+        let mut config = config(r#"{ "keys": {}, "profiles": { "work": { "key": "sk-profile" } } }"#);
+        config.vault_path = env::temp_dir().join(format!("hello-vault-config-{}.json", process::id()));
+        config.set_host(who(), String::from("http://localhost:8000/v1")).unwrap();
+        config.insert_key(who(), String::from("sk-endpoint")).unwrap();
+
+        config.create_vault().unwrap();
+        assert!(config.create_vault().is_err());
+        let saved = serde_json::to_string(&config).unwrap();
+        assert!(!saved.contains("sk-endpoint") && !saved.contains("sk-profile"));
+        assert_eq!(config.get_key("vaulted").unwrap(), Some((Zeroizing::new(String::from("sk-endpoint")), KeySource::Vault)));
+        assert_eq!(config.get_profile_key(&config.get_profile(Some("work")).unwrap()).unwrap().as_deref().map(String::as_str), Some("sk-profile"));
+
+        config.insert_key(who(), String::from("sk-rotated")).unwrap();
+        config.copy_profile(Some("work"), String::from("home")).unwrap();
+        config.unset_in_profile("work", What::Key).unwrap();
+        // a fresh config has to unlock the vault from the file
+        let mut config = self::config(&serde_json::to_string(&config).unwrap());
+        config.vault_path = env::temp_dir().join(format!("hello-vault-config-{}.json", process::id()));
+        assert_eq!(config.get_key("vaulted").unwrap(), Some((Zeroizing::new(String::from("sk-rotated")), KeySource::Vault)));
+        assert_eq!(config.get_profile_key(&config.get_profile(Some("home")).unwrap()).unwrap().as_deref().map(String::as_str), Some("sk-profile"));
+        assert_eq!(config.get_profile_key(&config.get_profile(Some("work")).unwrap()).unwrap(), None);
+        config.unset(What::Key, Some(who())).unwrap();
+        assert_eq!(config.get_key("vaulted").unwrap(), None);
+        let _ = fs::remove_file(&config.vault_path);
     }
 }
@@ -1,14 +1,27 @@
 use parking_lot::Mutex;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use crate::cli::Config;
-use llm_int::LLMContext;
+use crate::mcp;
+use crate::request::retry::RetryPolicy;
+use llm_int::{ContentPart, GenerationOptions, LLMContext, Usage};
+use llm_int::models::Pricing;
 
 #[allow(unused)]
 struct SharedState {
     piped: Option<String>,
-    initial_prompt: String,
+    /// The prompt followed by the images given along with it
+    initial_prompt: Vec<ContentPart>,
     config: Config,
     llm_ctx: LLMContext,
+    /// Everything spent since hello was started
+    session_usage: Usage,
+    /// Unknown for models missing from the registry and the config
+    pricing: Option<Pricing>,
+    /// Most we agree to pay for the prompt of a single request, in dollars
+    max_cost: Option<f64>,
+    /// Whether the model can use the built-in tools
+    tools: bool,
 }
 
 #[derive(Clone)]
@@ -17,13 +30,17 @@ pub struct Context {
 }
 
 impl Context {
-    pub fn new(initial_prompt: String, piped: Option<String>, config: Config, llm_ctx: LLMContext) -> Self {
+    pub fn new(initial_prompt: Vec<ContentPart>, piped: Option<String>, config: Config, llm_ctx: LLMContext, pricing: Option<Pricing>, max_cost: Option<f64>, tools: bool) -> Self {
         Self {
             shared_state: Arc::new(Mutex::new(SharedState {
                 piped,
                 initial_prompt,
                 config,
-                llm_ctx
+                llm_ctx,
+                session_usage: Usage::default(),
+                pricing,
+                max_cost,
+                tools,
             })),
         }
     }
@@ -32,11 +49,51 @@ impl Context {
         self.shared_state.lock().piped.clone()
     }
 
-    pub fn get_initial_prompt(&self) -> String {
+    pub fn get_initial_prompt(&self) -> Vec<ContentPart> {
         self.shared_state.lock().initial_prompt.clone()
     }
 
     pub fn get_llm(&self) -> LLMContext {
         self.shared_state.lock().llm_ctx.clone()
     }
+
+    pub fn get_retry_policy(&self) -> RetryPolicy {
+        self.shared_state.lock().config.retry.clone()
+    }
+
+    /// Adds the usage of a finished answer to the session totals, returns the new totals
+    pub fn add_usage(&self, usage: &Usage) -> Usage {
+        let mut state = self.shared_state.lock();
+        state.session_usage.add(usage);
+        state.session_usage.clone()
+    }
+
+    pub fn get_session_usage(&self) -> Usage {
+        self.shared_state.lock().session_usage.clone()
+    }
+
+    pub fn get_pricing(&self) -> Option<Pricing> {
+        self.shared_state.lock().pricing
+    }
+
+    pub fn get_max_cost(&self) -> Option<f64> {
+        self.shared_state.lock().max_cost
+    }
+
+    pub fn get_generation_options(&self) -> GenerationOptions {
+        self.shared_state.lock().config.generation.clone()
+    }
+
+    /// None to use the built-in instructions
+    pub fn get_system_prompt(&self) -> Option<String> {
+        self.shared_state.lock().config.system_prompt.clone()
+    }
+
+    pub fn get_mcp_servers(&self) -> BTreeMap<String, mcp::ServerConfig> {
+        self.shared_state.lock().config.mcp_servers.clone()
+    }
+
+    pub fn tools_enabled(&self) -> bool {
+        self.shared_state.lock().tools
+    }
 }
@@ -2,9 +2,14 @@ mod cli;
 mod term;
 mod request;
 mod context;
+mod usage;
+mod mcp;
+mod schema;
+mod structured;
+mod vault;
 
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::fs;
 use std::thread;
 use std::sync::mpsc::channel;
@@ -12,12 +17,13 @@ use std::process::exit;
 use std::io::{stdin, Read};
 use std::os::fd::AsRawFd;
 use term::TermTask;
+use structured::StructuredTask;
 use request::RequestTask;
 use context::Context;
-use llm_int::{LLMContext, Provider, openai};
+use llm_int::{ContentPart, JsonSchema, LLMContext};
 use directories::ProjectDirs;
 
-const CONFIG_FILE_NAME: &'static str = ".config.json";
+const CONFIG_FILE_NAME: &str = ".config.json";
 
 extern "C" {
     fn isatty(fd: i32) -> i32;
@@ -35,107 +41,319 @@ fn open_config() -> (PathBuf, cli::Config) {
         None => { panic!("Unable to find an appropriate directory for config file"); }
     };
     fs::create_dir_all(&data_dir)
-        .expect(format!("Unable to create data directory at: {}", data_dir.to_string_lossy()).as_str());
+        .unwrap_or_else(|_| panic!("Unable to create data directory at: {}", data_dir.to_string_lossy()));
     let mut config_file_path = data_dir.clone();
     config_file_path.push(CONFIG_FILE_NAME);
     if !config_file_path.exists() {
         fs::write(&config_file_path, "")
-            .expect(format!("Unable to create file {}", config_file_path.to_string_lossy()).as_str());
+            .unwrap_or_else(|_| panic!("Unable to create file {}", config_file_path.to_string_lossy()));
     }
 
     let cfg = match cli::Config::open(&config_file_path) {
         Ok(c) => c,
-        Err(_) => { exit(-1); }
+        Err(e) => fail(e, 1),
     };
 
     (config_file_path, cfg)
 }
 
-fn main() {
-    let mut stdin = stdin();
-    let piped = if !is_tty(&stdin) {
-        let mut buffer = String::new();
-        stdin.read_to_string(&mut buffer).unwrap();
-        Some(buffer)
-    } else {
-        None
-    };
+/// Tells what went wrong, along with the usage when the command line is at fault
+fn fail(e: cli::Error, code: i32) -> ! {
+    match e {
+        cli::Error::ArgParse => {
+            eprintln!("Error: unable to parse the arguments");
+            cli::print_usage(false);
+        },
+        cli::Error::InvalidArgument(e) => {
+            eprintln!("{e}");
+            cli::print_usage(false);
+        },
+        cli::Error::ReadConfigAction(e) => eprintln!("{e}"),
+        cli::Error::FileRW => eprintln!("Error: unable to read or write the config file"),
+        cli::Error::ConfigParse => eprintln!("Error: unable to parse or serialize the config file"),
+    }
+    exit(code);
+}
 
-    let (config_file_path, mut config) = open_config();
+/// Exits if the file at `path` can't be read or is not JSON
+fn read_schema(path: &str) -> JsonSchema {
+    let schema = fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|s| serde_json::from_str(&s).map_err(|e| e.to_string()));
+    match schema {
+        Ok(schema) => {
+            let name = std::path::Path::new(path).file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+            JsonSchema::new(&name, schema)
+        },
+        Err(e) => {
+            eprintln!("Error: unable to read the schema at {path}: {e}");
+            exit(1);
+        }
+    }
+}
 
-    let argv: Vec<String> = env::args().collect();
-    let argc = argv.len();
+/// URLs are left for the provider to fetch, files are read and exit if they are not an image
+fn read_image(path: &str) -> ContentPart {
+    if path.starts_with("http://") || path.starts_with("https://") {
+        return ContentPart::ImageUrl(path.to_string());
+    }
 
-    if argc == 1 {
-        cli::print_usage(true);
-        exit(0);
+    let data = fs::read(path).unwrap_or_else(|e| {
+        eprintln!("Error: unable to read the image at {path}: {e}");
+        exit(1);
+    });
+    match llm_int::image_mime_type(&data) {
+        Some(mime_type) => ContentPart::Image { mime_type: mime_type.to_string(), data },
+        None => {
+            eprintln!("Error: {path} is not a PNG, JPEG, GIF or WebP image");
+            exit(1);
+        }
     }
+}
 
-    if argv[1] == "--configure" {
-        if argc < 5 {
-            eprintln!("Error: configure mode expects at least three extra arguments");
-            cli::print_usage(false);
+/// Piped text goes in the context, a piped image or PDF is attached to the prompt. Exits on anything else.
+fn read_stdin() -> (Option<String>, Option<ContentPart>) {
+    let mut stdin = stdin();
+    if is_tty(&stdin) {
+        return (None, None);
+    }
+
+    let mut buffer = Vec::new();
+    stdin.read_to_end(&mut buffer).unwrap();
+    if let Some(mime_type) = llm_int::image_mime_type(&buffer) {
+        return (None, Some(ContentPart::Image { mime_type: mime_type.to_string(), data: buffer }));
+    }
+    if buffer.starts_with(b"%PDF-") {
+        return (None, Some(ContentPart::File { name: String::from("stdin.pdf"), mime_type: String::from("application/pdf"), data: buffer }));
+    }
+    match String::from_utf8(buffer) {
+        Ok(text) => (Some(text), None),
+        Err(_) => {
+            eprintln!("Error: the piped input is not text, nor a PNG, JPEG, GIF, WebP image or a PDF");
             exit(1);
         }
+    }
+}
 
-        match cli::get_config_action(&argv[2..5]) {
-            Err(cli::Error::ReadConfigAction(e)) => {
-                eprintln!("{}", e);
-                cli::print_usage(false);
-                exit(1);
-            },
-            Err(_) => { unimplemented!(); }
-            Ok((verb, what, who)) => {
-                match verb {
-                    cli::Verb::Set => {
-                        match what {
-                            cli::What::Key => {
-                                if argc != 6 {
-                                    eprintln!("Error: missing a key value");
-                                    cli::print_usage(false);
-                                    exit(1);
-                                }
-                                config.insert_key(who, argv[5].clone());
-                            }
-                        }
-                    },
-                    cli::Verb::Get => {
+fn main() {
+    let (config_file_path, mut config) = open_config();
 
+    let argv: Vec<String> = env::args().skip(1).collect();
+    let command = match cli::parse_args(&argv) {
+        Ok(command) => command,
+        Err(e) => fail(e, 1),
+    };
+
+    match command {
+        cli::Command::Help => {
+            cli::print_usage(true);
+        },
+        cli::Command::Usage => {
+            let usage_file_path = config_file_path.with_file_name(usage::USAGE_FILE_NAME);
+            match usage::read_totals(&usage_file_path) {
+                Ok(totals) if totals.is_empty() => println!("No usage recorded yet."),
+                Ok(totals) => {
+                    for ((provider, model), (sessions, total)) in totals {
+                        let cost = cli::get_provider(&provider, &config).ok()
+                            .and_then(|p| config.get_pricing(&p, &model))
+                            .map(|pricing| format!(", {}", usage::describe_cost(pricing.cost(&total))))
+                            .unwrap_or_default();
+                        println!("{provider} {model}: {sessions} session(s), {}{cost}", usage::describe(&total));
                     }
-                };
+                },
+                Err(e) => {
+                    eprintln!("Error: unable to read {}: {e}", usage_file_path.to_string_lossy());
+                    exit(1);
+                }
             }
-        };
-        let _ = config.save(&config_file_path);
-    } else {
-        let prompt: String = argv.iter()
-            .skip(1)
-            .fold(String::from("Hello,"), |mut acc, arg| { acc.push_str(" "); acc.push_str(arg); acc });
-
-        let api_key = match config.get_key(Provider::OpenAi) {
-            Some(k) => k,
-            None => {
-                eprintln!("Error: missing api key.");
-                cli::print_usage(false);
-                exit(2);
+        },
+        cli::Command::Configure { profile, args } => configure(&config_file_path, &mut config, profile.as_deref(), &args),
+        cli::Command::Prompt(args) => prompt(&config_file_path, config, *args),
+    }
+}
+
+fn configure(config_file_path: &Path, config: &mut cli::Config, profile: Option<&str>, args: &[String]) {
+    let action = match cli::get_config_action(args, profile.is_some()) {
+        Ok(action) => action,
+        Err(cli::Error::ReadConfigAction(e)) => {
+            eprintln!("{}", e);
+            cli::print_usage(false);
+            exit(1);
+        },
+        Err(e) => fail(e, 1),
+    };
+
+    let creates_vault = matches!(action, cli::ConfigAction::CreateVault);
+    let res = match action {
+        cli::ConfigAction::List => {
+            let lines = match profile {
+                Some(profile) => config.list_profile(profile),
+                None => Ok(config.list()),
+            };
+            match lines {
+                Ok(lines) => {
+                    for line in lines {
+                        println!("{line}");
+                    }
+                    return;
+                },
+                Err(e) => Err(e),
             }
-        };
-        let llm_ctx = LLMContext::new(Provider::OpenAi, openai::Models::GPT_4_1_Mini.to_string(), api_key);
-        let ctx = Context::new(prompt, piped, config, llm_ctx);
-
-        let (tx_ans, rx_ans) = channel();
-        let (tx_tty, rx_tty) = channel();
-        let req_thr_handle = thread::spawn({
-            let ctx = ctx.clone();
-            move || {
-                RequestTask::new(ctx).run(tx_ans, rx_tty);
+        },
+        cli::ConfigAction::Get { what, who } => {
+            let value = match profile {
+                Some(profile) => config.get_in_profile(profile, what),
+                None => config.get(what, who.as_ref()),
+            };
+            match value {
+                Ok(Some(value)) => {
+                    println!("{value}");
+                    return;
+                },
+                Ok(None) => {
+                    eprintln!("Error: {} is not set", args[1..].join(" "));
+                    exit(1);
+                },
+                Err(e) => Err(e),
             }
-        });
+        },
+        cli::ConfigAction::Set { what, who, value } => match profile {
+            Some(profile) => config.set_in_profile(profile, what, value),
+            None => config.set(what, who, value),
+        },
+        cli::ConfigAction::Unset { what, who } => match profile {
+            Some(profile) => config.unset_in_profile(profile, what),
+            None => config.unset(what, who),
+        },
+        cli::ConfigAction::CreateProfile(name) => config.create_profile(name),
+        cli::ConfigAction::CreateVault => config.create_vault(),
+        cli::ConfigAction::CopyProfile { from, to } => config.copy_profile(Some(&from), to),
+        cli::ConfigAction::DeleteProfile(name) => config.delete_profile(&name),
+    };
+
+    if let Err(e) = res {
+        fail(e, 1);
+    }
+    if let Err(e) = config.save(config_file_path) {
+        if creates_vault {
+            config.discard_vault();
+            eprintln!("Error: the vault was not kept since the config file couldn't be updated, the keys are still in it");
+        }
+        fail(e, 1);
+    }
+}
+
+fn prompt(config_file_path: &Path, mut config: cli::Config, args: cli::Args) {
+    let profile = match config.get_profile(args.profile.as_deref()) {
+        Ok(p) => p,
+        Err(cli::Error::ReadConfigAction(e)) => {
+            eprintln!("{}", e);
+            exit(1);
+        },
+        Err(e) => fail(e, 1),
+    };
+    // the profile's model and key are meant for its own provider
+    let (profile_model, profile_key) = match args.provider {
+        Some(_) => (None, Ok(None)),
+        None => (profile.model.clone(), config.get_profile_key(&profile)),
+    };
+    let provider_name = args.provider.or(profile.provider).or(config.provider.clone()).unwrap_or(String::from("openai"));
+    let provider = match cli::get_provider(&provider_name, &config) {
+        Ok(p) => p,
+        Err(cli::Error::ReadConfigAction(e)) => {
+            eprintln!("{}", e);
+            cli::print_usage(false);
+            exit(1);
+        },
+        Err(e) => fail(e, 1),
+    };
+
+    let mut options = args.generation;
+    options.response_format = args.schema.as_deref().map(read_schema);
+    let mut images: Vec<ContentPart> = args.images.iter().map(|i| read_image(i)).collect();
+
+    let (piped, piped_image) = read_stdin();
+    images.extend(piped_image);
 
-        match TermTask::new(ctx.clone()).run(tx_tty, rx_ans) {
-            Err(e) => { println!("{e:?}"); },
-            Ok(()) => ()
-        };
+    let prompt = args.prompt.iter()
+        .fold(String::from("Hello,"), |mut acc, arg| { acc.push(' '); acc.push_str(arg); acc });
+    let mut prompt = vec![ContentPart::Text(prompt)];
+    prompt.append(&mut images);
 
-        let _ = req_thr_handle.join();
+    let api_key = match profile_key {
+        Ok(None) => config.get_key(&provider_name).map(|k| k.map(|(key, _)| key)),
+        found => found,
+    };
+    let api_key = match api_key {
+        Ok(Some(k)) => Some(k),
+        Ok(None) if !provider.requires_key() => None,
+        Ok(None) => {
+            eprintln!("Error: missing api key.");
+            cli::print_usage(false);
+            exit(2);
+        },
+        Err(cli::Error::ReadConfigAction(e)) => {
+            eprintln!("{}", e);
+            exit(2);
+        },
+        Err(e) => fail(e, 2),
+    };
+    let model = match args.model.or(profile_model).or(config.get_model(&provider_name)).or(provider.default_model().map(String::from)) {
+        Some(m) => m,
+        None => {
+            eprintln!("Error: no model configured for this endpoint.");
+            cli::print_usage(false);
+            exit(2);
+        }
+    };
+    let pricing = config.get_pricing(&provider, &model);
+    if args.max_cost.is_some() && pricing.is_none() {
+        eprintln!("Error: the price of {model} is unknown, add it to the config file to use --max-cost");
+        exit(2);
+    }
+
+    // the flags take precedence over the defaults of the config file
+    let schema = options.response_format.as_ref().map(|f| f.schema.clone());
+    config.generation = options.or(&profile.generation).or(&config.generation);
+    config.system_prompt = args.system.or(profile.system_prompt).or(config.system_prompt);
+
+    let openai_api = config.get_openai_api(&model);
+    // llm_int holds its own copy of the key for as long as the session lasts, that one isn't wiped
+    let llm_ctx = LLMContext::with_openai_api(provider, model.clone(), api_key.as_deref().cloned(), openai_api);
+    let ctx = Context::new(prompt, piped, config, llm_ctx, pricing, args.max_cost, args.tools);
+
+    let (tx_ans, rx_ans) = channel();
+    let (tx_tty, rx_tty) = channel();
+    let req_thr_handle = thread::spawn({
+        let ctx = ctx.clone();
+        move || {
+            RequestTask::new(ctx).run(tx_ans, rx_tty);
+        }
+    });
+
+    let mut structured_result = None;
+    if let Some(schema) = schema {
+        structured_result = Some(StructuredTask::new(ctx.clone(), schema).run(tx_tty, rx_ans));
+    } else if let Err(e) = TermTask::new(ctx.clone()).run(tx_tty, rx_ans) {
+        println!("{e:?}");
+    }
+
+    let _ = req_thr_handle.join();
+
+    let session_usage = ctx.get_session_usage();
+    if !session_usage.is_empty() {
+        let record = usage::UsageRecord::new(provider_name, model, session_usage);
+        if let Err(e) = usage::append_record(&config_file_path.with_file_name(usage::USAGE_FILE_NAME), &record) {
+            eprintln!("Warning: unable to record token usage: {e}");
+        }
+    }
+
+    match structured_result {
+        Some(Ok(value)) => println!("{}", serde_json::to_string_pretty(&value).unwrap_or_default()),
+        Some(Err(e)) => {
+            eprintln!("Error: {e}");
+            exit(3);
+        },
+        None => (),
     }
 }
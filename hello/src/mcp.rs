@@ -0,0 +1,521 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use llm_int::{Tool, ToolCall};
+
+const PROTOCOL_VERSION: &str = "2025-06-18";
+const INIT_TIMEOUT: Duration = Duration::from_secs(30);
+const CALL_TIMEOUT: Duration = Duration::from_secs(120);
+/// Separates the server name from the tool name in what the model sees, ie: `git__git_status`
+const SEPARATOR: &str = "__";
+/// Models only accept tool names made of these characters, up to this length
+const MAX_TOOL_NAME_LEN: usize = 64;
+
+/// How to launch a server, same shape as in most MCP client configs
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ServerConfig {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub trust: Trust,
+}
+
+/// Which calls to a server can run without asking the user
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all="snake_case")]
+pub enum Trust {
+    /// Every call is approved first
+    #[default]
+    None,
+    /// Tools the server flags as read only, reading resources and fetching prompts.
+    /// The flag is the server's word, only worth trusting for a server you trust.
+    ReadOnly,
+    All,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Spawn(String),
+    /// The server exited or closed its stdout
+    Closed,
+    Timeout,
+    /// The server answered with a JSON-RPC error
+    Rpc { code: i64, message: String },
+    Protocol(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Spawn(msg) => write!(f, "unable to start: {msg}"),
+            Error::Closed => write!(f, "the server exited"),
+            Error::Timeout => write!(f, "the server took too long to answer"),
+            Error::Rpc { code, message } => write!(f, "{message} ({code})"),
+            Error::Protocol(msg) => write!(f, "unexpected answer: {msg}"),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all="camelCase")]
+struct ToolAnnotations {
+    #[serde(default)]
+    read_only_hint: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all="camelCase")]
+struct ToolInfo {
+    name: String,
+    #[serde(default)]
+    description: String,
+    input_schema: Value,
+    #[serde(default)]
+    annotations: ToolAnnotations,
+}
+
+#[derive(Deserialize)]
+struct ResourceInfo {
+    uri: String,
+    name: String,
+    #[serde(default)]
+    description: String,
+}
+
+#[derive(Deserialize)]
+struct PromptArgument {
+    name: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    required: bool,
+}
+
+#[derive(Deserialize)]
+struct PromptInfo {
+    name: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    arguments: Vec<PromptArgument>,
+}
+
+/// A running server, spoken to with newline delimited JSON-RPC over its stdin and stdout
+pub struct Server {
+    name: String,
+    trust: Trust,
+    child: Child,
+    stdin: ChildStdin,
+    /// Fed by a thread reading stdout, so that a silent server can't block us forever
+    rx: Receiver<Value>,
+    next_id: u64,
+    tools: Vec<ToolInfo>,
+    resources: Vec<ResourceInfo>,
+    prompts: Vec<PromptInfo>,
+}
+
+impl Server {
+    pub fn start(name: &str, config: &ServerConfig) -> Result<Self, Error> {
+        let mut child = Command::new(&config.command)
+            .args(&config.args)
+            .envs(&config.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            // would mess up the terminal
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| Error::Spawn(format!("{}: {e}", config.command)))?;
+
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            return Err(Error::Spawn(String::from("no stdio")));
+        };
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break; };
+                // servers aren't supposed to print anything else but some log to stdout anyway
+                if let Ok(msg) = serde_json::from_str::<Value>(&line) {
+                    if tx.send(msg).is_err() { break; }
+                }
+            }
+        });
+
+        let mut server = Self {
+            name: name.to_string(),
+            trust: config.trust,
+            child,
+            stdin,
+            rx,
+            next_id: 0,
+            tools: Vec::new(),
+            resources: Vec::new(),
+            prompts: Vec::new(),
+        };
+
+        let init = server.request("initialize", json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": {},
+            "clientInfo": {"name": "hello", "version": env!("CARGO_PKG_VERSION")},
+        }), INIT_TIMEOUT)?;
+        server.send(&json!({"jsonrpc": "2.0", "method": "notifications/initialized"}))?;
+
+        let capabilities = &init["capabilities"];
+        if capabilities.get("tools").is_some() {
+            server.tools = server.list("tools/list", "tools")?;
+        }
+        if capabilities.get("resources").is_some() {
+            server.resources = server.list("resources/list", "resources")?;
+        }
+        if capabilities.get("prompts").is_some() {
+            server.prompts = server.list("prompts/list", "prompts")?;
+        }
+        Ok(server)
+    }
+
+    fn send(&mut self, msg: &Value) -> Result<(), Error> {
+        let mut line = msg.to_string();
+        line.push('\n');
+        self.stdin.write_all(line.as_bytes()).and_then(|_| self.stdin.flush()).map_err(|_| Error::Closed)
+    }
+
+    fn request(&mut self, method: &str, params: Value, timeout: Duration) -> Result<Value, Error> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(&json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params}))?;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let msg = match self.rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(msg) => msg,
+                Err(RecvTimeoutError::Timeout) => return Err(Error::Timeout),
+                Err(RecvTimeoutError::Disconnected) => return Err(Error::Closed),
+            };
+
+            // requests and notifications from the server, only pings are worth answering
+            if let Some(method) = msg.get("method").and_then(Value::as_str) {
+                if let Some(request_id) = msg.get("id") {
+                    let reply = if method == "ping" {
+                        json!({"jsonrpc": "2.0", "id": request_id, "result": {}})
+                    } else {
+                        json!({"jsonrpc": "2.0", "id": request_id, "error": {"code": -32601, "message": "method not found"}})
+                    };
+                    self.send(&reply)?;
+                }
+                continue;
+            }
+
+            // could be the late answer to a request that timed out
+            if msg.get("id").and_then(Value::as_u64) != Some(id) { continue; }
+
+            if let Some(error) = msg.get("error") {
+                return Err(Error::Rpc {
+                    code: error["code"].as_i64().unwrap_or_default(),
+                    message: error["message"].as_str().unwrap_or_default().to_string(),
+                });
+            }
+            return msg.get("result").cloned().ok_or(Error::Protocol(format!("{method} has no result")));
+        }
+    }
+
+    /// Fetches every page of a list
+    fn list<T: DeserializeOwned>(&mut self, method: &str, key: &str) -> Result<Vec<T>, Error> {
+        let mut items = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({"cursor": cursor}),
+                None => json!({}),
+            };
+            let mut result = self.request(method, params, INIT_TIMEOUT)?;
+            let page: Vec<T> = serde_json::from_value(result[key].take()).map_err(|e| Error::Protocol(format!("{method}: {e}")))?;
+            items.extend(page);
+
+            cursor = result["nextCursor"].as_str().map(String::from);
+            if cursor.is_none() {
+                return Ok(items);
+            }
+        }
+    }
+
+    fn call_tool(&mut self, name: &str, arguments: Value) -> Result<String, Error> {
+        let result = self.request("tools/call", json!({"name": name, "arguments": arguments}), CALL_TIMEOUT)?;
+        let text = content_to_text(&result["content"]);
+        if result["isError"].as_bool().unwrap_or(false) {
+            Ok(format!("error: {text}"))
+        } else {
+            Ok(text)
+        }
+    }
+
+    fn read_resource(&mut self, uri: &str) -> Result<String, Error> {
+        let result = self.request("resources/read", json!({"uri": uri}), CALL_TIMEOUT)?;
+        let contents = result["contents"].as_array().cloned().unwrap_or_default();
+        Ok(contents.iter()
+            .map(|c| match c["text"].as_str() {
+                Some(text) => text.to_string(),
+                None => format!("[binary content of {}]", c["uri"].as_str().unwrap_or(uri)),
+            })
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    fn get_prompt(&mut self, name: &str, arguments: Value) -> Result<String, Error> {
+        let result = self.request("prompts/get", json!({"name": name, "arguments": arguments}), CALL_TIMEOUT)?;
+        let messages = result["messages"].as_array().cloned().unwrap_or_default();
+        Ok(messages.iter()
+            .map(|m| format!("{}: {}", m["role"].as_str().unwrap_or("user"), content_to_text(&json!([m["content"]]))))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Flattens MCP content blocks into what we can hand to the model
+fn content_to_text(content: &Value) -> String {
+    content.as_array().cloned().unwrap_or_default().iter()
+        .map(|block| match block["type"].as_str() {
+            Some("text") => block["text"].as_str().unwrap_or_default().to_string(),
+            Some("resource") => block["resource"]["text"].as_str()
+                .map(String::from)
+                .unwrap_or(format!("[resource {}]", block["resource"]["uri"].as_str().unwrap_or_default())),
+            Some("resource_link") => format!("[resource {}]", block["uri"].as_str().unwrap_or_default()),
+            Some(other) => format!("[{other} content]"),
+            None => String::new(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+        .collect()
+}
+
+/// `<prefix><name>` within the length models accept. Names that end up the same once sanitized or truncated,
+/// within a server or across servers, get a numbered suffix so that none is shadowed.
+fn exposed_name<T>(prefix: &str, name: &str, taken: &BTreeMap<String, T>) -> String {
+    let full = format!("{prefix}{}", sanitize(name));
+    // sanitized names are ASCII, truncating them can't split a character
+    let mut exposed = full.clone();
+    exposed.truncate(MAX_TOOL_NAME_LEN);
+    let mut n = 2;
+    while taken.contains_key(&exposed) {
+        let suffix = format!("_{n}");
+        exposed = format!("{}{suffix}", &full[..full.len().min(MAX_TOOL_NAME_LEN - suffix.len())]);
+        n += 1;
+    }
+    exposed
+}
+
+/// What an exposed tool name stands for
+enum Target {
+    Tool { name: String, read_only: bool },
+    ReadResource,
+    GetPrompt,
+}
+
+/// Every configured server, their tools, resources and prompts exposed as tools named `<server>__<name>`
+#[derive(Default)]
+pub struct Clients {
+    servers: Vec<Server>,
+    /// Exposed name to server index and target
+    targets: BTreeMap<String, (usize, Target)>,
+    tools: Vec<Tool>,
+}
+
+impl Clients {
+    /// Starts every server at once so that a slow one doesn't hold up the others,
+    /// the ones that fail are left out and returned along with the reason
+    pub fn start(configs: &BTreeMap<String, ServerConfig>) -> (Self, Vec<(String, Error)>) {
+        let started: Vec<(&String, Result<Server, Error>)> = std::thread::scope(|scope| {
+            let handles: Vec<_> = configs.iter()
+                .map(|(name, config)| (name, scope.spawn(move || Server::start(name, config))))
+                .collect();
+            handles.into_iter()
+                .map(|(name, handle)| (name, handle.join().unwrap_or_else(|_| Err(Error::Spawn(String::from("the client thread panicked"))))))
+                .collect()
+        });
+
+        let mut clients = Self::default();
+        let mut errors = Vec::new();
+        for (name, res) in started {
+            match res {
+                Ok(server) => clients.add(server),
+                Err(e) => errors.push((name.clone(), e)),
+            }
+        }
+        (clients, errors)
+    }
+
+    fn add(&mut self, server: Server) {
+        let idx = self.servers.len();
+        let prefix = format!("{}{SEPARATOR}", sanitize(&server.name));
+        let mut expose = |name: &str, target: Target, description: String, parameters: Value| {
+            let exposed = exposed_name(&prefix, name, &self.targets);
+            self.tools.push(Tool { name: exposed.clone(), description, parameters });
+            self.targets.insert(exposed, (idx, target));
+        };
+
+        for tool in &server.tools {
+            let target = Target::Tool { name: tool.name.clone(), read_only: tool.annotations.read_only_hint };
+            expose(&tool.name, target, tool.description.clone(), tool.input_schema.clone());
+        }
+
+        if !server.resources.is_empty() {
+            let mut description = format!("Reads a resource of the {} MCP server. Available resources:", server.name);
+            for r in &server.resources {
+                description.push_str(&format!("\n- {} ({}) {}", r.uri, r.name, r.description));
+            }
+            expose("read_resource", Target::ReadResource, description, json!({
+                "type": "object",
+                "properties": {"uri": {"type": "string"}},
+                "required": ["uri"],
+            }));
+        }
+
+        if !server.prompts.is_empty() {
+            let mut description = format!("Fetches a prompt template of the {} MCP server. Available prompts:", server.name);
+            for p in &server.prompts {
+                description.push_str(&format!("\n- {}: {}", p.name, p.description));
+                for a in &p.arguments {
+                    let required = if a.required { ", required" } else { "" };
+                    description.push_str(&format!("\n  - argument {}{required}: {}", a.name, a.description));
+                }
+            }
+            expose("get_prompt", Target::GetPrompt, description, json!({
+                "type": "object",
+                "properties": {
+                    "name": {"type": "string"},
+                    "arguments": {"type": "object", "additionalProperties": {"type": "string"}},
+                },
+                "required": ["name"],
+            }));
+        }
+
+        self.servers.push(server);
+    }
+
+    pub fn tools(&self) -> &[Tool] {
+        &self.tools
+    }
+
+    pub fn owns(&self, call: &ToolCall) -> bool {
+        self.targets.contains_key(&call.name)
+    }
+
+    /// Everything is approved first unless the server is trusted with it in its config
+    pub fn needs_approval(&self, call: &ToolCall) -> bool {
+        let Some((idx, target)) = self.targets.get(&call.name) else {
+            return true;
+        };
+        match (self.servers[*idx].trust, target) {
+            (Trust::All, _) => false,
+            (Trust::ReadOnly, Target::Tool { read_only, .. }) => !read_only,
+            (Trust::ReadOnly, Target::ReadResource | Target::GetPrompt) => false,
+            (Trust::None, _) => true,
+        }
+    }
+
+    /// Runs the call on the server it belongs to and returns what the model gets to see, failures included
+    pub fn execute(&mut self, call: &ToolCall) -> String {
+        let Some((idx, target)) = self.targets.get(&call.name) else {
+            return format!("error: unknown tool \"{}\"", call.name);
+        };
+        let arguments: Value = match call.parse_arguments() {
+            Ok(a) => a,
+            Err(e) => return format!("error: {e}"),
+        };
+
+        let server = &mut self.servers[*idx];
+        let res = match target {
+            Target::Tool { name, .. } => server.call_tool(name, arguments),
+            Target::ReadResource => server.read_resource(arguments["uri"].as_str().unwrap_or_default()),
+            Target::GetPrompt => {
+                let name = arguments["name"].as_str().unwrap_or_default().to_string();
+                server.get_prompt(&name, arguments.get("arguments").cloned().unwrap_or(json!({})))
+            },
+        };
+        res.unwrap_or_else(|e| format!("error: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> BTreeMap<String, ServerConfig> {
+        let script = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/mcp_server.sh");
+        BTreeMap::from([
+            (String::from("fix ture"), ServerConfig { command: String::from("sh"), args: vec![script.to_string()], trust: Trust::ReadOnly, ..Default::default() }),
+            (String::from("broken"), ServerConfig { command: String::from("/nonexistent/mcp-server"), ..Default::default() }),
+        ])
+    }
+
+    fn call(name: &str, arguments: &str) -> ToolCall {
+        ToolCall { id: String::from("call_0"), name: name.to_string(), arguments: arguments.to_string() }
+    }
+
+    #[test]
+    fn fixture_server() {
+        let (mut clients, errors) = Clients::start(&fixture());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, "broken");
+
+        let names: Vec<&str> = clients.tools().iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["fix_ture__echo", "fix_ture__delete", "fix_ture__read_resource", "fix_ture__get_prompt"]);
+        assert!(clients.tools()[2].description.contains("fixture://readme"));
+
+        let echo = call("fix_ture__echo", "{\"text\":\"hi\"}");
+        assert!(clients.owns(&echo));
+        assert!(!clients.needs_approval(&echo));
+        assert!(clients.needs_approval(&call("fix_ture__delete", "{}")));
+        assert!(!clients.needs_approval(&call("fix_ture__read_resource", "{}")));
+        assert!(!clients.owns(&call("read_file", "{}")));
+
+        clients.servers[0].trust = Trust::None;
+        assert!(clients.needs_approval(&echo));
+        assert!(clients.needs_approval(&call("fix_ture__read_resource", "{}")));
+        assert!(clients.needs_approval(&call("fix_ture__get_prompt", "{}")));
+        clients.servers[0].trust = Trust::All;
+        assert!(!clients.needs_approval(&call("fix_ture__delete", "{}")));
+
+        assert_eq!(clients.execute(&echo), "echo: hi");
+        assert_eq!(clients.execute(&call("fix_ture__delete", "{}")), "error: refusing to delete");
+        assert_eq!(clients.execute(&call("fix_ture__read_resource", "{\"uri\":\"fixture://readme\"}")), "fixture readme");
+        assert_eq!(clients.execute(&call("fix_ture__get_prompt", "{\"name\":\"review\"}")), "user: review this");
+        assert_eq!(clients.execute(&call("fix_ture__nope", "{}")), "error: unknown tool \"fix_ture__nope\"");
+    }
+
+    #[test]
+    fn colliding_names() {
+        let mut taken = BTreeMap::from([(String::from("git__a_b"), ())]);
+        assert_eq!(exposed_name("git__", "a.b", &taken), "git__a_b_2");
+        taken.insert(String::from("git__a_b_2"), ());
+        assert_eq!(exposed_name("git__", "a b", &taken), "git__a_b_3");
+        assert_eq!(exposed_name("other__", "a.b", &taken), "other__a_b");
+
+        let long = "x".repeat(80);
+        let first = exposed_name("git__", &long, &taken);
+        assert_eq!(first.len(), MAX_TOOL_NAME_LEN);
+        taken.insert(first, ());
+        let second = exposed_name("git__", &format!("{long}y"), &taken);
+        assert_eq!(second.len(), MAX_TOOL_NAME_LEN);
+        assert!(second.ends_with("x_2"));
+    }
+}
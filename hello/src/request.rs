@@ -1,92 +1,405 @@
 mod predefined_prompts;
+pub mod retry;
+pub mod tools;
 
+use std::collections::VecDeque;
 use std::sync::mpsc::{Sender, Receiver};
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use parking_lot::Mutex;
 use curl::easy::{Easy, List};
 use curl::multi::{Multi, EasyHandle};
-use llm_int::{LLMContext, LLMApi, Message, Role};
+use llm_int::{LLMContext, LLMApi, LLMEvent, Message, Reasoning, Role, Tool, ToolCall, ToolCallAccumulator, stream};
 use crate::context::Context;
 use crate::term::TermTaskMessage;
-use predefined_prompts::SYSPROMPT;
+use crate::usage;
+use crate::mcp;
+use predefined_prompts::{CONTEXT_PROMPT, SYSPROMPT, TOOLS_PROMPT};
+
+/// Most rounds of tool calls a single prompt can trigger
+const MAX_TOOL_STEPS: u32 = 20;
 
 pub enum RequestTaskMessage {
-    ReceivedPiece(String),
+    Event(LLMEvent),
+    Error(llm_int::Error),
+    /// The request failed before anything was received and will be sent again after `delay`
+    Retrying {
+        attempt: u32,
+        max_retries: u32,
+        delay: Duration,
+        error: llm_int::Error,
+    },
+    /// A tool is being run on behalf of the model
+    ToolRunning(ToolCall),
+    /// The user has to say whether this call can run, answered with `TermTaskMessage::ToolApproval`
+    ToolApproval(ToolCall),
+    /// The model kept asking for tools past `MAX_TOOL_STEPS`
+    ToolLimit(u32),
+    /// The request was not sent because its prompt would cost more than allowed
+    CostLimit {
+        estimated: f64,
+        limit: f64,
+    },
+    /// A configured MCP server couldn't be started, hello goes on without its tools
+    McpFailed {
+        server: String,
+        error: mcp::Error,
+    },
     Done,
 }
 
-#[derive(PartialEq, Eq)]
+/// What the curl callbacks learned about a transfer, looked at once it is over
+#[derive(Default)]
+struct TransferState {
+    status: u32,
+    /// Error responses are not streamed so they are kept whole to be decoded at the end
+    error_body: Vec<u8>,
+    error: Option<llm_int::Error>,
+    /// Once something reached the user the request can't be transparently sent again
+    streamed: bool,
+    /// How long the provider asked us to wait, from the response headers
+    retry_after: Option<Duration>,
+    ratelimit_reset: Option<Duration>,
+    /// The answer so far, it goes in the history along with the tool calls
+    text: String,
+    tool_calls: ToolCallAccumulator,
+    /// Some providers want it back along with the tool results
+    reasoning: Reasoning,
+}
+
+impl TransferState {
+    /// An explicit Retry-After takes precedence over the rate limit resets, only looked at when rate limited
+    fn retry_hint(&self) -> Option<Duration> {
+        self.retry_after.or(self.ratelimit_reset)
+    }
+}
+
+fn parse_status_line(header: &[u8]) -> Option<u32> {
+    let line = std::str::from_utf8(header).ok()?;
+    if !line.starts_with("HTTP/") { return None; }
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
 enum PollingMode {
     AwaitRequestUpdate,
+    /// Also used while waiting on the user to approve a tool call
     AwaitPrompt,
 }
 
 pub struct RequestTask {
     multi: Multi, 
     easy_handle: Option<EasyHandle>,
+    transfer: Arc<Mutex<TransferState>>,
+    /// Number of retries already attempted for the current request
+    attempt: u32,
+    retry_at: Option<Instant>,
     ctx: Context,
     polling_mode: PollingMode,
+    history: Vec<Message>,
+    /// Declared with every request, empty if tools are disabled
+    tools: Vec<Tool>,
+    mcp: mcp::Clients,
+    /// Calls of the current step that haven't been run yet
+    pending_calls: VecDeque<ToolCall>,
+    awaiting_approval: Option<ToolCall>,
+    /// Rounds of tool calls made for the current prompt
+    steps: u32,
 }
 
 impl RequestTask {
     pub fn new(ctx: Context) -> Self {
+        let tools = if ctx.tools_enabled() { tools::builtin_tools() } else { Vec::new() };
         Self {
             multi: Multi::new(),
             easy_handle: None,
+            transfer: Arc::new(Mutex::new(TransferState::default())),
+            attempt: 0,
+            retry_at: None,
             ctx,
             polling_mode: PollingMode::AwaitPrompt,
+            history: Vec::new(),
+            tools,
+            mcp: mcp::Clients::default(),
+            pending_calls: VecDeque::new(),
+            awaiting_approval: None,
+            steps: 0,
         }
     }
 
     fn stop_ongoing(&mut self) {
+        self.retry_at = None;
+        self.pending_calls.clear();
+        self.awaiting_approval = None;
         if let Some(easy_handle) = self.easy_handle.take() {
             let _ = self.multi.remove(easy_handle);
         }
     }
 
-    fn build_easy_handle(&self, llm_ctx: LLMContext, messages: Vec<Message>, tx_ans: Sender<RequestTaskMessage>) -> Easy {
-        let req = llm_ctx.build_request(messages);
+    fn build_easy_handle(&self, llm_ctx: LLMContext, messages: Vec<Message>, transfer: Arc<Mutex<TransferState>>, tx_ans: Sender<RequestTaskMessage>) -> llm_int::Result<Easy> {
+        let req = llm_ctx.build_request(messages, &self.tools, &self.ctx.get_generation_options())?;
+        let transport_err = |e: curl::Error| llm_int::Error::Transport(e.to_string());
 
         let mut easy = Easy::new();
-        easy.url(&req.uri().to_string()).unwrap();
-        let headers = req.headers()
-            .iter()
-            .fold(List::new(), |mut list, (hn, hv)| { let _ = list.append(format!("{hn}: {}", hv.to_str().unwrap()).as_str()); list });
-        easy.http_headers(headers).unwrap();
+        easy.url(&req.uri().to_string()).map_err(transport_err)?;
+        let mut headers = List::new();
+        for (hn, hv) in req.headers() {
+            let hv = hv.to_str().map_err(|e| llm_int::Error::InvalidRequest(e.to_string()))?;
+            headers.append(format!("{hn}: {hv}").as_str()).map_err(transport_err)?;
+        }
+        easy.http_headers(headers).map_err(transport_err)?;
 
         if req.method() == http::Method::POST {
-            easy.post(true).unwrap();
-            easy.post_fields_copy(req.into_body().as_slice()).unwrap();
+            easy.post(true).map_err(transport_err)?;
+            easy.post_fields_copy(req.into_body().as_slice()).map_err(transport_err)?;
         }
 
+        easy.header_function({
+            let transfer = transfer.clone();
+            move |header| {
+                // redirects and 100-continue come with their own status line, the last one wins
+                let mut transfer = transfer.lock();
+                if let Some(status) = parse_status_line(header) {
+                    transfer.status = status;
+                    transfer.retry_after = None;
+                    transfer.ratelimit_reset = None;
+                } else if let Some(delay) = std::str::from_utf8(header).ok().and_then(retry::parse_retry_header) {
+                    if header.to_ascii_lowercase().starts_with(b"x-ratelimit-reset") {
+                        // wait for whichever of the requests or tokens limit resets last
+                        transfer.ratelimit_reset = Some(transfer.ratelimit_reset.map_or(delay, |d| d.max(delay)));
+                    } else {
+                        transfer.retry_after = Some(delay);
+                    }
+                }
+                true
+            }
+        }).map_err(transport_err)?;
+
+        let mut decoder = stream::Decoder::new();
         easy.write_function(move |data| { 
-            let (sz, content) = llm_ctx.build_response(data);
-            let _ = tx_ans.send(RequestTaskMessage::ReceivedPiece(content.0));
-            Ok(sz)
-        }).unwrap();
+            let mut transfer = transfer.lock();
+            if transfer.status >= 300 {
+                transfer.error_body.extend_from_slice(data);
+                return Ok(data.len());
+            }
+
+            match llm_ctx.build_response(&mut decoder, data) {
+                Ok((sz, events)) => {
+                    for event in events {
+                        if matches!(event, LLMEvent::TextDelta(_) | LLMEvent::RefusalDelta(_) | LLMEvent::ReasoningDelta(_) | LLMEvent::ToolCallDelta { .. }) {
+                            transfer.streamed = true;
+                        }
+                        match &event {
+                            LLMEvent::TextDelta(text) => transfer.text.push_str(text),
+                            LLMEvent::ReasoningDelta(text) => transfer.reasoning.text.push_str(text),
+                            LLMEvent::ReasoningSignature(signature) => transfer.reasoning.signature = Some(signature.clone()),
+                            LLMEvent::RedactedReasoning(data) => transfer.reasoning.redacted.push(data.clone()),
+                            _ => (),
+                        }
+                        transfer.tool_calls.push(&event);
+                        let _ = tx_ans.send(RequestTaskMessage::Event(event));
+                    }
+                    Ok(sz)
+                },
+                Err(e) => {
+                    transfer.error = Some(e);
+                    // not consuming the data makes curl abort the transfer
+                    Ok(0)
+                },
+            }
+        }).map_err(transport_err)?;
 
-        easy
+        Ok(easy)
     }
 
-    pub fn run(mut self, tx_ans: Sender<RequestTaskMessage>, rx_tty: Receiver<TermTaskMessage>) {
+    /// Sends the whole history in a new transfer, returns false if it couldn't be started
+    fn start_request(&mut self, tx_ans: Sender<RequestTaskMessage>) -> bool {
+        let messages = self.history.clone();
+        if let (Some(limit), Some(pricing)) = (self.ctx.get_max_cost(), self.ctx.get_pricing()) {
+            let estimated = pricing.prompt_cost(usage::estimate_prompt_tokens(&messages));
+            if estimated > limit {
+                let _ = tx_ans.send(RequestTaskMessage::CostLimit { estimated, limit });
+                return false;
+            }
+        }
 
-        let sysprompt_full: String = match self.ctx.get_piped_input() {
-            Some(piped) => {
-                let mut s = String::from(SYSPROMPT);
-                s.push_str(piped.as_str());
-                s
+        self.transfer = Arc::new(Mutex::new(TransferState::default()));
+        let res = self.build_easy_handle(self.ctx.get_llm(), messages, self.transfer.clone(), tx_ans.clone())
+            .and_then(|easy| self.multi.add(easy).map_err(|e| llm_int::Error::Transport(e.to_string())));
+
+        match res {
+            Ok(handle) => {
+                self.easy_handle = Some(handle);
+                true
             },
-            None => String::from(SYSPROMPT)
+            Err(e) => {
+                let _ = tx_ans.send(RequestTaskMessage::Error(e));
+                false
+            }
+        }
+    }
+
+    /// Looks at how the ongoing transfer ended, must be called before it's removed from the multi handle
+    fn transfer_error(&self) -> Option<llm_int::Error> {
+        let mut curl_err = None;
+        if let Some(handle) = &self.easy_handle {
+            self.multi.messages(|msg| {
+                if let Some(Err(e)) = msg.result_for(handle) {
+                    curl_err = Some(e);
+                }
+            });
+        }
+
+        let mut transfer = self.transfer.lock();
+        let error = if let Some(e) = transfer.error.take() {
+            Some(e)
+        } else if transfer.status >= 300 {
+            Some(self.ctx.get_llm().build_error(transfer.status, &transfer.error_body))
+        } else {
+            curl_err.map(|e| llm_int::Error::Transport(e.to_string()))
         };
 
-        let mut history: Vec<(Role, String)> = vec![
-            (Role::Developer, sysprompt_full),
-            (Role::User, self.ctx.get_initial_prompt())
+        match error {
+            Some(llm_int::Error::RateLimit { retry_after: None }) => Some(llm_int::Error::RateLimit { retry_after: transfer.retry_hint() }),
+            e => e,
+        }
+    }
+
+    /// Decides what to do with a transfer that just ended
+    fn finish_transfer(&mut self, tx_ans: &Sender<RequestTaskMessage>) -> PollingMode {
+        let error = self.transfer_error();
+        let (streamed, text, tool_calls, reasoning) = {
+            let mut transfer = self.transfer.lock();
+            (transfer.streamed, std::mem::take(&mut transfer.text), std::mem::take(&mut transfer.tool_calls), std::mem::take(&mut transfer.reasoning))
+        };
+        self.stop_ongoing();
+
+        let Some(error) = error else {
+            if tool_calls.is_empty() {
+                let _ = tx_ans.send(RequestTaskMessage::Done);
+                return PollingMode::AwaitPrompt;
+            }
+            if self.steps >= MAX_TOOL_STEPS {
+                let _ = tx_ans.send(RequestTaskMessage::ToolLimit(self.steps));
+                let _ = tx_ans.send(RequestTaskMessage::Done);
+                return PollingMode::AwaitPrompt;
+            }
+
+            self.steps += 1;
+            let calls = tool_calls.finish();
+            let mut message = Message::with_tool_calls(text, calls.clone());
+            if !reasoning.is_empty() {
+                message = message.with_reasoning(reasoning);
+            }
+            self.history.push(message);
+            self.pending_calls = calls.into();
+            return self.run_pending_calls(tx_ans);
+        };
+
+        let policy = self.ctx.get_retry_policy();
+        let delay = if streamed { None } else { policy.next_delay(self.attempt + 1, &error) };
+        match delay {
+            Some(delay) => {
+                self.attempt += 1;
+                self.retry_at = Some(Instant::now() + delay);
+                let _ = tx_ans.send(RequestTaskMessage::Retrying { attempt: self.attempt, max_retries: policy.max_retries, delay, error });
+                PollingMode::AwaitRequestUpdate
+            },
+            None => {
+                let _ = tx_ans.send(RequestTaskMessage::Error(error));
+                let _ = tx_ans.send(RequestTaskMessage::Done);
+                PollingMode::AwaitPrompt
+            }
+        }
+    }
+
+    fn needs_approval(&self, call: &ToolCall) -> bool {
+        if self.mcp.owns(call) {
+            self.mcp.needs_approval(call)
+        } else {
+            tools::needs_approval(call)
+        }
+    }
+
+    fn execute(&mut self, call: &ToolCall) -> String {
+        if self.mcp.owns(call) {
+            self.mcp.execute(call)
+        } else {
+            tools::execute(call)
+        }
+    }
+
+    /// Runs the calls the model asked for until one needs approval, then sends the results back once they're all in
+    fn run_pending_calls(&mut self, tx_ans: &Sender<RequestTaskMessage>) -> PollingMode {
+        while let Some(call) = self.pending_calls.pop_front() {
+            if self.needs_approval(&call) {
+                let _ = tx_ans.send(RequestTaskMessage::ToolApproval(call.clone()));
+                self.awaiting_approval = Some(call);
+                return PollingMode::AwaitPrompt;
+            }
+
+            let _ = tx_ans.send(RequestTaskMessage::ToolRunning(call.clone()));
+            let result = self.execute(&call);
+            self.history.push(Message::tool_result(call.id, result));
+        }
+
+        self.attempt = 0;
+        if self.start_request(tx_ans.clone()) {
+            PollingMode::AwaitRequestUpdate
+        } else {
+            let _ = tx_ans.send(RequestTaskMessage::Done);
+            PollingMode::AwaitPrompt
+        }
+    }
+
+    fn answer_approval(&mut self, approved: bool, tx_ans: &Sender<RequestTaskMessage>) -> PollingMode {
+        let Some(call) = self.awaiting_approval.take() else {
+            return self.polling_mode;
+        };
+
+        let result = if approved {
+            let _ = tx_ans.send(RequestTaskMessage::ToolRunning(call.clone()));
+            self.execute(&call)
+        } else {
+            String::from("the user refused to let this run")
+        };
+        self.history.push(Message::tool_result(call.id, result));
+        self.run_pending_calls(tx_ans)
+    }
+
+    pub fn run(mut self, tx_ans: Sender<RequestTaskMessage>, rx_tty: Receiver<TermTaskMessage>) {
+        if self.ctx.tools_enabled() {
+            let (mcp, errors) = mcp::Clients::start(&self.ctx.get_mcp_servers());
+            for (server, error) in errors {
+                let _ = tx_ans.send(RequestTaskMessage::McpFailed { server, error });
+            }
+            self.tools.extend_from_slice(mcp.tools());
+            self.mcp = mcp;
+        }
+
+        let mut sysprompt_full = String::new();
+        if !self.tools.is_empty() {
+            let cwd = std::env::current_dir().map(|d| d.to_string_lossy().into_owned()).unwrap_or_default();
+            sysprompt_full.push_str(TOOLS_PROMPT);
+            sysprompt_full.push_str(&cwd);
+            sysprompt_full.push_str("\n\n");
+        }
+        sysprompt_full.push_str(self.ctx.get_system_prompt().as_deref().unwrap_or(SYSPROMPT));
+        sysprompt_full.push_str(CONTEXT_PROMPT);
+        if let Some(piped) = self.ctx.get_piped_input() {
+            sysprompt_full.push_str(piped.as_str());
+        }
+
+        self.history = vec![
+            Message::new(Role::Developer, sysprompt_full),
+            Message::with_parts(Role::User, self.ctx.get_initial_prompt()),
         ];
 
-        let messages = Message::from_history(&history);
-        let easy = self.build_easy_handle(self.ctx.get_llm(), messages, tx_ans.clone());
-        self.easy_handle = self.multi.add(easy).map_or(None, |h| Some(h));
-        self.polling_mode = PollingMode::AwaitRequestUpdate;
+        if self.start_request(tx_ans.clone()) {
+            self.polling_mode = PollingMode::AwaitRequestUpdate;
+        } else {
+            let _ = tx_ans.send(RequestTaskMessage::Done);
+        }
 
         let mut run_task = true;
         let mut next_polling: Option<PollingMode> = None;
@@ -96,44 +409,62 @@ impl RequestTask {
             }
 
             let tty_msg: Option<TermTaskMessage> = match self.polling_mode {
-                PollingMode::AwaitRequestUpdate => rx_tty.try_recv().map_or(None, |m| Some(m)),
+                PollingMode::AwaitRequestUpdate => rx_tty.try_recv().ok(),
                 PollingMode::AwaitPrompt => match rx_tty.recv() {
                     Ok(msg) => Some(msg),
                     Err(_) => { self.stop_ongoing(); run_task = false; None } //tty task is closed
                 },
             };
 
-            match tty_msg {
-                Some(tty_msg) => match tty_msg {
+            if let Some(tty_msg) = tty_msg {
+                match tty_msg {
                     TermTaskMessage::ReceivedUserPrompt {user_prompt, llm_answer_prev} => {
                         self.stop_ongoing();
+                        self.attempt = 0;
+                        self.steps = 0;
 
-                        if let Some(llm_answer_prev) = llm_answer_prev {
-                            history.push((Role::Assistant, llm_answer_prev));
+                        // an answer cancelled before any text has nothing worth sending back
+                        if let Some(llm_answer_prev) = llm_answer_prev.filter(|a| !a.is_empty()) {
+                            self.history.push(Message::new(Role::Assistant, llm_answer_prev));
                         }
 
-                        history.push((Role::User, user_prompt));
+                        self.history.push(Message::new(Role::User, user_prompt));
 
-                        let messages = Message::from_history(&history);
-                        let easy = self.build_easy_handle(self.ctx.get_llm(), messages, tx_ans.clone());
-                        self.easy_handle = self.multi.add(easy).map_or(None, |h| Some(h));
-                        next_polling = Some(PollingMode::AwaitRequestUpdate);
+                        if self.start_request(tx_ans.clone()) {
+                            next_polling = Some(PollingMode::AwaitRequestUpdate);
+                        } else {
+                            let _ = tx_ans.send(RequestTaskMessage::Done);
+                        }
+                    },
+                    TermTaskMessage::ToolApproval(approved) => {
+                        next_polling = Some(self.answer_approval(approved, &tx_ans));
                     },
                     TermTaskMessage::Die => {
                         self.stop_ongoing();
                         run_task = false;
                     }
-                },
-                None => ()
+                }
             }
 
             if self.polling_mode == PollingMode::AwaitRequestUpdate {
+                if let Some(retry_at) = self.retry_at {
+                    if Instant::now() >= retry_at {
+                        self.retry_at = None;
+                        if !self.start_request(tx_ans.clone()) {
+                            let _ = tx_ans.send(RequestTaskMessage::Done);
+                            next_polling = Some(PollingMode::AwaitPrompt);
+                        }
+                    } else {
+                        // nothing for curl to wait on in the meantime
+                        std::thread::sleep(Duration::from_millis(30));
+                        continue;
+                    }
+                }
+
                 let _ = self.multi.wait(&mut [], Duration::from_millis(30));
                 if let Ok(running_handles) = self.multi.perform() {
-                    if running_handles == 0 && self.easy_handle.is_some() { 
-                        self.stop_ongoing();
-                        let _ = tx_ans.send(RequestTaskMessage::Done);
-                        next_polling = Some(PollingMode::AwaitPrompt);
+                    if running_handles == 0 && self.easy_handle.is_some() {
+                        next_polling = Some(self.finish_transfer(&tx_ans));
                     }
                 }
             }
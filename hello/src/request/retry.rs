@@ -0,0 +1,133 @@
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use llm_int::Error;
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled at each attempt
+    pub base_delay_ms: u64,
+    /// Longest we are willing to wait. The backoff stops growing there, but if a rate limit asks for more we give up
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay_ms: 500,
+            max_delay_ms: 30_000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before attempt number `attempt` (starting at 1), None if we shouldn't retry at all.
+    /// Only a rate limit says how long to wait, anything else backs off exponentially.
+    pub fn next_delay(&self, attempt: u32, error: &Error) -> Option<Duration> {
+        if attempt > self.max_retries || !is_retryable(error) {
+            return None;
+        }
+
+        let max_delay = Duration::from_millis(self.max_delay_ms);
+        match error {
+            Error::RateLimit { retry_after: Some(d) } if *d > max_delay => None,
+            Error::RateLimit { retry_after: Some(d) } => Some(*d),
+            _ => {
+                let backoff = Duration::from_millis(self.base_delay_ms.saturating_mul(1 << (attempt - 1).min(16)));
+                Some(backoff.min(max_delay))
+            },
+        }
+    }
+}
+
+fn is_retryable_status(status: u32) -> bool {
+    // 529 is Anthropic telling us it is overloaded
+    matches!(status, 408 | 409 | 429 | 529) || status >= 500
+}
+
+fn is_retryable(error: &Error) -> bool {
+    match error {
+        Error::RateLimit { .. } | Error::Transport(_) => true,
+        Error::HttpStatus(status) | Error::Provider { status: Some(status), .. } => is_retryable_status(*status),
+        _ => false,
+    }
+}
+
+/// Reads how long the provider wants us to wait from a response header, if it is one that says so.
+/// Only the delay-seconds form of Retry-After is understood, not the HTTP date.
+pub fn parse_retry_header(header: &str) -> Option<Duration> {
+    let (name, value) = header.split_once(':')?;
+    let value = value.trim();
+    match name.trim().to_ascii_lowercase().as_str() {
+        "retry-after-ms" => value.parse::<f64>().ok().map(|ms| Duration::from_secs_f64(ms / 1000.0)),
+        "retry-after" => value.parse::<f64>().ok().map(Duration::from_secs_f64),
+        n if n.starts_with("x-ratelimit-reset-") => parse_reset_duration(value),
+        _ => None,
+    }
+}
+
+/// Parses durations formatted like OpenAI's rate limit resets, ie: `1s`, `6m0s`, `250ms`, `1h2m3.5s`
+fn parse_reset_duration(s: &str) -> Option<Duration> {
+    let mut total = 0.0;
+    let mut num = String::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_digit() || c == '.' {
+            num.push(c);
+            continue;
+        }
+
+        let n: f64 = num.parse().ok()?;
+        num.clear();
+        total += match c {
+            'h' => n * 3600.0,
+            'm' if chars.peek() == Some(&'s') => { chars.next(); n / 1000.0 },
+            'm' => n * 60.0,
+            's' => n,
+            _ => return None,
+        };
+    }
+
+    // a bare number is in seconds
+    if !num.is_empty() {
+        total += num.parse::<f64>().ok()?;
+    }
+    Some(Duration::from_secs_f64(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_headers() {
+        assert_eq!(parse_retry_header("Retry-After: 3"), Some(Duration::from_secs(3)));
+        assert_eq!(parse_retry_header("retry-after-ms: 250\r\n"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_retry_header("x-ratelimit-reset-requests: 6m0s"), Some(Duration::from_secs(360)));
+        assert_eq!(parse_retry_header("x-ratelimit-reset-tokens: 120ms"), Some(Duration::from_millis(120)));
+        assert_eq!(parse_retry_header("x-ratelimit-reset-tokens: 1m2.5s"), Some(Duration::from_millis(62_500)));
+        assert_eq!(parse_retry_header("Retry-After: Wed, 21 Oct 2015 07:28:00 GMT"), None);
+        assert_eq!(parse_retry_header("content-type: application/json"), None);
+    }
+
+    #[test]
+    fn next_delay() {
+        let policy = RetryPolicy::default();
+        let err = Error::HttpStatus(503);
+        assert_eq!(policy.next_delay(1, &err), Some(Duration::from_millis(500)));
+        assert_eq!(policy.next_delay(3, &err), Some(Duration::from_millis(2000)));
+        assert_eq!(policy.next_delay(4, &err), None);
+        assert_eq!(policy.next_delay(1, &Error::HttpStatus(401)), None);
+
+        // the backoff is capped rather than given up on
+        let patient = RetryPolicy { max_retries: 10, ..RetryPolicy::default() };
+        assert_eq!(patient.next_delay(10, &err), Some(Duration::from_millis(30_000)));
+
+        let rate_limit = |secs| Error::RateLimit { retry_after: Some(Duration::from_secs(secs)) };
+        assert_eq!(policy.next_delay(1, &rate_limit(2)), Some(Duration::from_secs(2)));
+        assert_eq!(policy.next_delay(1, &rate_limit(120)), None);
+        assert_eq!(policy.next_delay(2, &Error::RateLimit { retry_after: None }), Some(Duration::from_millis(1000)));
+    }
+}
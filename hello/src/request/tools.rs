@@ -0,0 +1,288 @@
+use std::fs;
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+use serde::Deserialize;
+use serde_json::json;
+use llm_int::{Tool, ToolCall};
+
+/// Longest output handed back to the model, the rest is cut
+const MAX_OUTPUT_LEN: usize = 20_000;
+const SHELL_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub const READ_FILE: &str = "read_file";
+pub const LIST_DIR: &str = "list_dir";
+pub const GREP: &str = "grep";
+pub const RUN_SHELL: &str = "run_shell";
+
+pub fn builtin_tools() -> Vec<Tool> {
+    vec![
+        Tool::new(READ_FILE, "Reads a text file, relative paths are relative to the user's working directory. Files outside of it need the user's approval.", json!({
+            "type": "object",
+            "properties": {
+                "path": {"type": "string"},
+            },
+            "required": ["path"],
+        })),
+        Tool::new(LIST_DIR, "Lists the entries of a directory, directories end with a slash. Directories outside the working directory need the user's approval.", json!({
+            "type": "object",
+            "properties": {
+                "path": {"type": "string", "description": "Defaults to the working directory"},
+            },
+        })),
+        Tool::new(GREP, "Searches files recursively for lines matching an extended regular expression, prints them as path:line:content. Searching outside the working directory needs the user's approval.", json!({
+            "type": "object",
+            "properties": {
+                "pattern": {"type": "string"},
+                "path": {"type": "string", "description": "File or directory to search, defaults to the working directory"},
+            },
+            "required": ["pattern"],
+        })),
+        Tool::new(RUN_SHELL, "Runs a command with sh in the working directory and returns its exit status and output. The user has to approve every command.", json!({
+            "type": "object",
+            "properties": {
+                "command": {"type": "string"},
+            },
+            "required": ["command"],
+        })),
+    ]
+}
+
+/// Tools that write or execute anything must be approved by the user first,
+/// and so must reading anything outside the working directory
+pub fn needs_approval(call: &ToolCall) -> bool {
+    let path = match call.name.as_str() {
+        READ_FILE | LIST_DIR => call.parse_arguments::<PathArgs>().ok().map(|a| a.path),
+        GREP => call.parse_arguments::<GrepArgs>().ok().map(|a| a.path),
+        _ => return true,
+    };
+    match path {
+        Some(path) => !in_working_dir(path.as_deref().unwrap_or(".")),
+        // the call fails without touching anything
+        None => false,
+    }
+}
+
+/// Whether `path` is the working directory or under it once `..` and symlinks are resolved.
+/// A path that doesn't exist can't be resolved and isn't considered under it.
+fn in_working_dir(path: &str) -> bool {
+    let (Ok(cwd), Ok(path)) = (std::env::current_dir().and_then(fs::canonicalize), fs::canonicalize(path)) else {
+        return false;
+    };
+    path.starts_with(cwd)
+}
+
+#[derive(Deserialize)]
+struct PathArgs {
+    path: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GrepArgs {
+    pattern: String,
+    path: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ShellArgs {
+    command: String,
+}
+
+/// One line summary of the call for the user, ie: `read_file src/main.rs`
+pub fn describe(call: &ToolCall) -> String {
+    let detail = match call.name.as_str() {
+        READ_FILE | LIST_DIR => call.parse_arguments::<PathArgs>().ok().map(|a| a.path.unwrap_or(String::from("."))),
+        GREP => call.parse_arguments::<GrepArgs>().ok().map(|a| format!("{} {}", a.pattern, a.path.unwrap_or(String::from(".")))),
+        RUN_SHELL => call.parse_arguments::<ShellArgs>().ok().map(|a| a.command),
+        _ => None,
+    };
+    match detail {
+        Some(detail) => format!("{} {detail}", call.name),
+        None => format!("{} {}", call.name, call.arguments),
+    }
+}
+
+/// Runs the call and returns what the model gets to see, failures included
+pub fn execute(call: &ToolCall) -> String {
+    let res = match call.name.as_str() {
+        READ_FILE => call.parse_arguments::<PathArgs>()
+            .map_err(|e| e.to_string())
+            .and_then(|a| read_file(&a.path.unwrap_or_default())),
+        LIST_DIR => call.parse_arguments::<PathArgs>()
+            .map_err(|e| e.to_string())
+            .and_then(|a| list_dir(&a.path.unwrap_or(String::from(".")))),
+        GREP => call.parse_arguments::<GrepArgs>()
+            .map_err(|e| e.to_string())
+            .and_then(|a| grep(&a.pattern, &a.path.unwrap_or(String::from(".")))),
+        RUN_SHELL => call.parse_arguments::<ShellArgs>()
+            .map_err(|e| e.to_string())
+            .and_then(|a| run_shell(&a.command, SHELL_TIMEOUT)),
+        name => Err(format!("unknown tool \"{name}\"")),
+    };
+
+    let mut output = res.unwrap_or_else(|e| format!("error: {e}"));
+    truncate(&mut output);
+    output
+}
+
+fn truncate(s: &mut String) {
+    if s.len() <= MAX_OUTPUT_LEN { return; }
+
+    let mut end = MAX_OUTPUT_LEN;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    let total = s.len();
+    s.truncate(end);
+    s.push_str(&format!("\n[output truncated, {} more bytes]", total - end));
+}
+
+fn read_file(path: &str) -> Result<String, String> {
+    let bytes = fs::read(path).map_err(|e| format!("{path}: {e}"))?;
+    String::from_utf8(bytes).map_err(|_| format!("{path} is not a text file"))
+}
+
+fn list_dir(path: &str) -> Result<String, String> {
+    let mut entries: Vec<String> = fs::read_dir(path)
+        .map_err(|e| format!("{path}: {e}"))?
+        .filter_map(|e| e.ok())
+        .map(|e| {
+            let mut name = e.file_name().to_string_lossy().into_owned();
+            if e.file_type().is_ok_and(|t| t.is_dir()) {
+                name.push('/');
+            }
+            name
+        })
+        .collect();
+    entries.sort();
+    Ok(entries.join("\n"))
+}
+
+fn grep(pattern: &str, path: &str) -> Result<String, String> {
+    if !Path::new(path).exists() {
+        return Err(format!("{path}: no such file or directory"));
+    }
+
+    let output = Command::new("grep")
+        .args(["-rnIE", "--exclude-dir=.git", "--exclude-dir=target", "--exclude-dir=node_modules", "-e", pattern, "--", path])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("unable to run grep: {e}"))?;
+
+    // grep exits with 1 when nothing matched
+    match output.status.code() {
+        Some(0) => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+        Some(1) => Ok(String::from("no match")),
+        _ => Err(String::from_utf8_lossy(&output.stderr).into_owned()),
+    }
+}
+
+/// The command runs in its own process group, killed as a whole once `timeout` is over or sh exits,
+/// so that nothing it started in the background keeps running or holds the output pipes open
+fn run_shell(command: &str, timeout: Duration) -> Result<String, String> {
+    let mut child = Command::new("sh")
+        .args(["-c", command])
+        .process_group(0)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("unable to run sh: {e}"))?;
+
+    // the pipes are drained on their own threads so a chatty command can't fill them and block
+    let drain = |pipe: Option<Box<dyn Read + Send>>| std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        String::from_utf8_lossy(&buf).into_owned()
+    });
+    let stdout = drain(child.stdout.take().map(|p| Box::new(p) as Box<dyn Read + Send>));
+    let stderr = drain(child.stderr.take().map(|p| Box::new(p) as Box<dyn Read + Send>));
+
+    // sh leads the group, its pid is the group id
+    let kill_group = |pgid: u32| {
+        let _ = Command::new("kill").args(["-KILL", "--", &format!("-{pgid}")])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+    };
+
+    let started = Instant::now();
+    let res = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Ok(status.to_string()),
+            Ok(None) if started.elapsed() > timeout => {
+                kill_group(child.id());
+                let _ = child.wait();
+                break Ok(format!("killed after {}s", timeout.as_secs()));
+            },
+            Ok(None) => std::thread::sleep(Duration::from_millis(20)),
+            Err(e) => break Err(e.to_string()),
+        }
+    };
+    kill_group(child.id());
+    let status = res?;
+
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    Ok(format!("{status}\n--- stdout ---\n{stdout}\n--- stderr ---\n{stderr}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, arguments: &str) -> ToolCall {
+        ToolCall { id: String::from("call_0"), name: name.to_string(), arguments: arguments.to_string() }
+    }
+
+    #[test]
+    fn builtin_calls() {
+        let listing = execute(&call(LIST_DIR, "{\"path\": \"src\"}"));
+        assert!(listing.lines().any(|l| l == "main.rs"));
+        assert!(listing.lines().any(|l| l == "request/"));
+
+        assert!(execute(&call(READ_FILE, "{\"path\": \"Cargo.toml\"}")).contains("name = \"hello\""));
+        assert!(execute(&call(READ_FILE, "{}")).starts_with("error: "));
+        assert!(execute(&call(GREP, "{\"pattern\": \"^pub const RUN_SHELL\", \"path\": \"src/request\"}")).contains("tools.rs:"));
+        assert!(execute(&call(RUN_SHELL, "{\"command\": \"echo hi; echo oops >&2; exit 3\"}")).contains("exit status: 3\n--- stdout ---\nhi\n\n--- stderr ---\noops\n"));
+        assert_eq!(execute(&call("rm_rf", "{}")), "error: unknown tool \"rm_rf\"");
+
+        assert!(!needs_approval(&call(GREP, "{\"pattern\": \"x\"}")));
+        assert!(needs_approval(&call(RUN_SHELL, "")));
+        assert_eq!(describe(&call(RUN_SHELL, "{\"command\": \"cargo test\"}")), "run_shell cargo test");
+    }
+
+    #[test]
+    fn outside_working_dir() {
+        assert!(!needs_approval(&call(READ_FILE, "{\"path\": \"Cargo.toml\"}")));
+        assert!(!needs_approval(&call(LIST_DIR, "{}")));
+        assert!(!needs_approval(&call(GREP, "{\"pattern\": \"x\", \"path\": \"src/../src\"}")));
+        assert!(needs_approval(&call(READ_FILE, "{\"path\": \"/etc/passwd\"}")));
+        assert!(needs_approval(&call(LIST_DIR, "{\"path\": \"..\"}")));
+        assert!(needs_approval(&call(GREP, "{\"pattern\": \"x\", \"path\": \"/\"}")));
+        assert!(needs_approval(&call(READ_FILE, "{\"path\": \"no/such/file\"}")));
+    }
+
+    #[test]
+    fn shell_process_group() {
+        // what is left in the background doesn't hold up the answer
+        let started = Instant::now();
+        assert!(run_shell("sleep 30 & echo started", SHELL_TIMEOUT).unwrap().contains("exit status: 0\n--- stdout ---\nstarted\n"));
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        let started = Instant::now();
+        assert!(run_shell("sleep 30 & sleep 30", Duration::from_secs(1)).unwrap().starts_with("killed after 1s"));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn truncate_on_char_boundary() {
+        let mut s = "é".repeat(MAX_OUTPUT_LEN);
+        truncate(&mut s);
+        assert!(s.ends_with(&format!("[output truncated, {} more bytes]", MAX_OUTPUT_LEN)));
+    }
+}
@@ -0,0 +1,314 @@
+use serde_json::{Map, Value};
+
+/// Deeper than that a `$ref` is most likely going around in circles
+const MAX_DEPTH: u32 = 64;
+/// Enough for the model to fix its answer without flooding the prompt
+const MAX_ERRORS: usize = 10;
+
+/// Reads the answer of the model as JSON, some wrap it in a markdown code block even when told not to
+pub fn parse_answer(answer: &str) -> Result<Value, String> {
+    let mut answer = answer.trim();
+    if let Some(fenced) = answer.strip_prefix("```") {
+        answer = fenced.split_once('\n').map_or("", |(_, rest)| rest);
+        answer = answer.trim_end().strip_suffix("```").unwrap_or(answer);
+    }
+
+    serde_json::from_str(answer).map_err(|e| format!("the answer is not valid JSON: {e}"))
+}
+
+/// Checks `instance` against a JSON Schema, returns what doesn't match one error per line.
+/// Covers the structural keywords, `pattern` and `format` are not checked.
+pub fn validate(schema: &Value, instance: &Value) -> Result<(), String> {
+    let mut validator = Validator { root: schema, errors: Vec::new() };
+    validator.check(schema, instance, "$", 0);
+
+    if validator.errors.is_empty() {
+        return Ok(());
+    }
+    let more = validator.errors.len().saturating_sub(MAX_ERRORS);
+    validator.errors.truncate(MAX_ERRORS);
+    if more > 0 {
+        validator.errors.push(format!("and {more} more"));
+    }
+    Err(validator.errors.join("\n"))
+}
+
+struct Validator<'a> {
+    root: &'a Value,
+    errors: Vec<String>,
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn is_type(value: &Value, name: &str) -> bool {
+    match name {
+        "integer" => value.as_f64().is_some_and(|n| n.fract() == 0.0),
+        "number" => value.is_number(),
+        name => type_name(value) == name,
+    }
+}
+
+impl<'a> Validator<'a> {
+    /// Runs `schema` on its own, to know whether it matches without keeping its errors
+    fn matches(&mut self, schema: &'a Value, instance: &Value, path: &str, depth: u32) -> bool {
+        let before = self.errors.len();
+        self.check(schema, instance, path, depth);
+        let matched = self.errors.len() == before;
+        self.errors.truncate(before);
+        matched
+    }
+
+    fn check(&mut self, schema: &'a Value, instance: &Value, path: &str, depth: u32) {
+        let schema = match schema {
+            Value::Bool(true) => return,
+            Value::Bool(false) => {
+                self.errors.push(format!("{path}: no value is allowed here"));
+                return;
+            },
+            Value::Object(schema) => schema,
+            _ => return,
+        };
+        if depth > MAX_DEPTH {
+            self.errors.push(format!("{path}: the schema nests too deep"));
+            return;
+        }
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            match reference.strip_prefix('#').and_then(|pointer| self.root.pointer(pointer)) {
+                Some(target) => self.check(target, instance, path, depth + 1),
+                None => self.errors.push(format!("{path}: unsupported $ref {reference}")),
+            }
+        }
+
+        self.check_generic(schema, instance, path);
+        self.check_combinators(schema, instance, path, depth);
+        match instance {
+            Value::Object(object) => self.check_object(schema, object, path, depth),
+            Value::Array(items) => self.check_array(schema, items, path, depth),
+            Value::String(s) => self.check_string(schema, s, path),
+            Value::Number(_) => self.check_number(schema, instance.as_f64().unwrap_or_default(), path),
+            _ => (),
+        }
+    }
+
+    fn check_generic(&mut self, schema: &Map<String, Value>, instance: &Value, path: &str) {
+        let types: Vec<&str> = match schema.get("type") {
+            Some(Value::String(t)) => vec![t.as_str()],
+            Some(Value::Array(ts)) => ts.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|t| is_type(instance, t)) {
+            self.errors.push(format!("{path}: expected {}, got {}", types.join(" or "), type_name(instance)));
+        }
+
+        if let Some(Value::Array(values)) = schema.get("enum") {
+            if !values.contains(instance) {
+                self.errors.push(format!("{path}: {instance} is not one of {}", Value::Array(values.clone())));
+            }
+        }
+        if let Some(value) = schema.get("const") {
+            if value != instance {
+                self.errors.push(format!("{path}: expected {value}, got {instance}"));
+            }
+        }
+    }
+
+    fn check_combinators(&mut self, schema: &'a Map<String, Value>, instance: &Value, path: &str, depth: u32) {
+        if let Some(Value::Array(all)) = schema.get("allOf") {
+            for sub in all {
+                self.check(sub, instance, path, depth + 1);
+            }
+        }
+        if let Some(Value::Array(any)) = schema.get("anyOf") {
+            if !any.iter().any(|sub| self.matches(sub, instance, path, depth + 1)) {
+                self.errors.push(format!("{path}: matches none of the anyOf schemas"));
+            }
+        }
+        if let Some(Value::Array(one)) = schema.get("oneOf") {
+            let matched = one.iter().filter(|sub| self.matches(sub, instance, path, depth + 1)).count();
+            if matched != 1 {
+                self.errors.push(format!("{path}: matches {matched} of the oneOf schemas instead of exactly one"));
+            }
+        }
+        if let Some(not) = schema.get("not") {
+            if self.matches(not, instance, path, depth + 1) {
+                self.errors.push(format!("{path}: matches a schema it must not"));
+            }
+        }
+        if let Some(condition) = schema.get("if") {
+            let branch = if self.matches(condition, instance, path, depth + 1) { "then" } else { "else" };
+            if let Some(sub) = schema.get(branch) {
+                self.check(sub, instance, path, depth + 1);
+            }
+        }
+    }
+
+    fn check_object(&mut self, schema: &'a Map<String, Value>, object: &Map<String, Value>, path: &str, depth: u32) {
+        let properties = schema.get("properties").and_then(Value::as_object);
+
+        for name in schema.get("required").and_then(Value::as_array).into_iter().flatten().filter_map(Value::as_str) {
+            if !object.contains_key(name) {
+                self.errors.push(format!("{path}: missing required property \"{name}\""));
+            }
+        }
+
+        for (name, value) in object {
+            let property_path = format!("{path}.{name}");
+            match properties.and_then(|p| p.get(name)) {
+                Some(sub) => self.check(sub, value, &property_path, depth + 1),
+                // patternProperties can't be checked, properties they might cover are let through
+                None if schema.contains_key("patternProperties") => (),
+                None => match schema.get("additionalProperties") {
+                    Some(Value::Bool(false)) => self.errors.push(format!("{path}: unexpected property \"{name}\"")),
+                    Some(sub) => self.check(sub, value, &property_path, depth + 1),
+                    None => (),
+                },
+            }
+        }
+
+        if let Some(min) = schema.get("minProperties").and_then(Value::as_u64) {
+            if (object.len() as u64) < min {
+                self.errors.push(format!("{path}: expected at least {min} properties, got {}", object.len()));
+            }
+        }
+        if let Some(max) = schema.get("maxProperties").and_then(Value::as_u64) {
+            if object.len() as u64 > max {
+                self.errors.push(format!("{path}: expected at most {max} properties, got {}", object.len()));
+            }
+        }
+    }
+
+    fn check_array(&mut self, schema: &'a Map<String, Value>, items: &[Value], path: &str, depth: u32) {
+        // before 2020-12 an array of schemas in items meant what prefixItems means now
+        let (prefix, rest) = match (schema.get("prefixItems"), schema.get("items")) {
+            (Some(Value::Array(prefix)), rest) => (prefix.as_slice(), rest),
+            (None, Some(Value::Array(prefix))) => (prefix.as_slice(), schema.get("additionalItems")),
+            (_, rest) => (&[][..], rest),
+        };
+        for (i, item) in items.iter().enumerate() {
+            let sub = prefix.get(i).or(rest);
+            if let Some(sub) = sub {
+                self.check(sub, item, &format!("{path}[{i}]"), depth + 1);
+            }
+        }
+
+        if let Some(contains) = schema.get("contains") {
+            if !items.iter().enumerate().any(|(i, item)| self.matches(contains, item, &format!("{path}[{i}]"), depth + 1)) {
+                self.errors.push(format!("{path}: no item matches the contains schema"));
+            }
+        }
+        if let Some(min) = schema.get("minItems").and_then(Value::as_u64) {
+            if (items.len() as u64) < min {
+                self.errors.push(format!("{path}: expected at least {min} items, got {}", items.len()));
+            }
+        }
+        if let Some(max) = schema.get("maxItems").and_then(Value::as_u64) {
+            if items.len() as u64 > max {
+                self.errors.push(format!("{path}: expected at most {max} items, got {}", items.len()));
+            }
+        }
+        if schema.get("uniqueItems") == Some(&Value::Bool(true)) {
+            if let Some(i) = (1..items.len()).find(|&i| items[..i].contains(&items[i])) {
+                self.errors.push(format!("{path}: item {i} is a duplicate"));
+            }
+        }
+    }
+
+    fn check_string(&mut self, schema: &Map<String, Value>, s: &str, path: &str) {
+        let len = s.chars().count() as u64;
+        if let Some(min) = schema.get("minLength").and_then(Value::as_u64) {
+            if len < min {
+                self.errors.push(format!("{path}: expected at least {min} characters, got {len}"));
+            }
+        }
+        if let Some(max) = schema.get("maxLength").and_then(Value::as_u64) {
+            if len > max {
+                self.errors.push(format!("{path}: expected at most {max} characters, got {len}"));
+            }
+        }
+    }
+
+    fn check_number(&mut self, schema: &Map<String, Value>, n: f64, path: &str) {
+        let bound = |keyword: &str| schema.get(keyword).and_then(Value::as_f64);
+        if let Some(min) = bound("minimum").filter(|min| n < *min) {
+            self.errors.push(format!("{path}: {n} is less than {min}"));
+        }
+        if let Some(max) = bound("maximum").filter(|max| n > *max) {
+            self.errors.push(format!("{path}: {n} is greater than {max}"));
+        }
+        if let Some(min) = bound("exclusiveMinimum").filter(|min| n <= *min) {
+            self.errors.push(format!("{path}: {n} is not greater than {min}"));
+        }
+        if let Some(max) = bound("exclusiveMaximum").filter(|max| n >= *max) {
+            self.errors.push(format!("{path}: {n} is not less than {max}"));
+        }
+        if let Some(divisor) = bound("multipleOf").filter(|d| *d > 0.0) {
+            let quotient = n / divisor;
+            if (quotient - quotient.round()).abs() > 1e-9 {
+                self.errors.push(format!("{path}: {n} is not a multiple of {divisor}"));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parse_fenced_answer() {
+        assert_eq!(parse_answer("```json\n{\"a\": 1}\n```\n").unwrap(), json!({"a": 1}));
+        assert_eq!(parse_answer(" [1, 2] ").unwrap(), json!([1, 2]));
+        assert!(parse_answer("Sure! {\"a\": 1}").is_err());
+    }
+
+    #[test]
+    fn validate_instances() {
+        let schema = json!({
+            "type": "object",
+            "required": ["name", "tags"],
+            "additionalProperties": false,
+            "properties": {
+                "name": {"type": "string", "minLength": 1},
+                "age": {"type": "integer", "minimum": 0},
+                "tags": {"type": "array", "items": {"$ref": "#/$defs/tag"}, "uniqueItems": true},
+                "kind": {"enum": ["a", "b"]},
+            },
+            "$defs": {"tag": {"type": "string", "maxLength": 3}},
+        });
+
+        assert!(validate(&schema, &json!({"name": "Ann", "age": 3, "tags": ["x", "y"], "kind": "a"})).is_ok());
+
+        let errors = validate(&schema, &json!({"name": "", "age": 1.5, "tags": ["long", "x", "x"], "kind": "c", "extra": null})).unwrap_err();
+        let errors: Vec<&str> = errors.lines().collect();
+        assert_eq!(errors, [
+            "$.age: expected integer, got number",
+            "$: unexpected property \"extra\"",
+            "$.kind: \"c\" is not one of [\"a\",\"b\"]",
+            "$.name: expected at least 1 characters, got 0",
+            "$.tags[0]: expected at most 3 characters, got 4",
+            "$.tags: item 2 is a duplicate",
+        ]);
+
+        assert_eq!(validate(&schema, &json!([])).unwrap_err(), "$: expected object, got array");
+    }
+
+    #[test]
+    fn validate_combinators() {
+        let schema = json!({"oneOf": [{"type": "integer"}, {"type": "number", "multipleOf": 0.5}]});
+        assert!(validate(&schema, &json!(1.5)).is_ok());
+        assert!(validate(&schema, &json!(2)).is_err());
+        assert!(validate(&json!({"anyOf": [{"type": "null"}, {"const": 1}]}), &json!(1)).is_ok());
+        assert!(validate(&json!({"not": {"type": "string"}}), &json!("a")).is_err());
+    }
+}
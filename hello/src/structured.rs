@@ -0,0 +1,115 @@
+use std::sync::mpsc::{Receiver, Sender};
+use serde_json::Value;
+use llm_int::{LLMEvent, Usage};
+use crate::context::Context;
+use crate::request::{tools, RequestTaskMessage};
+use crate::term::TermTaskMessage;
+use crate::{schema, usage};
+
+/// Times the model is told what was wrong with its answer before giving up
+const MAX_SCHEMA_RETRIES: u32 = 2;
+
+/// Stands in for the terminal when the answer must match a JSON Schema: nothing is shown while it streams,
+/// an answer that doesn't validate is sent back with the errors, and only the validated JSON is returned.
+/// Notices go to stderr so stdout can be piped.
+pub struct StructuredTask {
+    ctx: Context,
+    schema: Value,
+    /// Text of the current request, what came before led to tool calls
+    answer: String,
+    refusal: String,
+    /// Token usage of the current request as last reported
+    usage: Usage,
+    error: Option<String>,
+    retries: u32,
+}
+
+impl StructuredTask {
+    pub fn new(ctx: Context, schema: Value) -> Self {
+        Self {
+            ctx,
+            schema,
+            answer: String::new(),
+            refusal: String::new(),
+            usage: Usage::default(),
+            error: None,
+            retries: 0,
+        }
+    }
+
+    /// Adds what the last request cost to the session and starts over for the next one
+    fn end_step(&mut self) {
+        let usage = std::mem::take(&mut self.usage);
+        if !usage.is_empty() {
+            self.ctx.add_usage(&usage);
+        }
+        self.answer.clear();
+        self.refusal.clear();
+    }
+
+    /// Returns the validated answer, or why there is none
+    pub fn run(mut self, tx_tty: Sender<TermTaskMessage>, rx_ans: Receiver<RequestTaskMessage>) -> Result<Value, String> {
+        let result = loop {
+            let Ok(message) = rx_ans.recv() else {
+                break Err(String::from("the request task stopped unexpectedly"));
+            };
+
+            match message {
+                RequestTaskMessage::Event(LLMEvent::TextDelta(text)) => self.answer.push_str(&text),
+                RequestTaskMessage::Event(LLMEvent::RefusalDelta(text)) => self.refusal.push_str(&text),
+                RequestTaskMessage::Event(LLMEvent::Usage(usage)) => self.usage.merge(&usage),
+                RequestTaskMessage::Event(_) => (),
+                RequestTaskMessage::Error(e) => self.error = Some(e.to_string()),
+                RequestTaskMessage::ToolRunning(call) => {
+                    eprintln!("> {}", tools::describe(&call));
+                    self.end_step();
+                },
+                RequestTaskMessage::ToolApproval(call) => {
+                    // nobody is there to answer
+                    eprintln!("refused {}, calls needing approval can't be made with --schema", tools::describe(&call));
+                    self.end_step();
+                    let _ = tx_tty.send(TermTaskMessage::ToolApproval(false));
+                },
+                RequestTaskMessage::ToolLimit(steps) => eprintln!("stopped after {steps} rounds of tool calls"),
+                RequestTaskMessage::CostLimit { estimated, limit } => {
+                    self.error = Some(format!("not sent, the prompt would cost about {} which is over the {} limit",
+                        usage::describe_cost(estimated), usage::describe_cost(limit)));
+                },
+                RequestTaskMessage::McpFailed { server, error } => eprintln!("MCP server {server} left out: {error}"),
+                RequestTaskMessage::Retrying { attempt, max_retries, delay, error } => {
+                    eprintln!("{error}, retrying in {:.1}s ({attempt}/{max_retries})", delay.as_secs_f32());
+                },
+                RequestTaskMessage::Done => {
+                    let answer = std::mem::take(&mut self.answer);
+                    let refusal = std::mem::take(&mut self.refusal);
+                    self.end_step();
+
+                    if let Some(e) = self.error.take() {
+                        break Err(e);
+                    }
+                    if !refusal.is_empty() {
+                        break Err(format!("the model refused: {refusal}"));
+                    }
+
+                    let validation = schema::parse_answer(&answer)
+                        .and_then(|value| schema::validate(&self.schema, &value).map(|_| value));
+                    match validation {
+                        Ok(value) => break Ok(value),
+                        Err(e) if self.retries < MAX_SCHEMA_RETRIES => {
+                            self.retries += 1;
+                            eprintln!("the answer doesn't match the schema, retrying ({}/{MAX_SCHEMA_RETRIES}):\n{e}", self.retries);
+                            let _ = tx_tty.send(TermTaskMessage::ReceivedUserPrompt {
+                                user_prompt: format!("Your answer doesn't match the JSON Schema:\n{e}\nAnswer again with only the corrected JSON."),
+                                llm_answer_prev: Some(answer),
+                            });
+                        },
+                        Err(e) => break Err(format!("the answer doesn't match the schema:\n{e}")),
+                    }
+                },
+            }
+        };
+
+        let _ = tx_tty.send(TermTaskMessage::Die);
+        result
+    }
+}
@@ -1,5 +1,6 @@
 mod output_metadata_gen;
 mod str_ext;
+mod reasoning;
 
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
 use str_ext::StrExt;
@@ -10,8 +11,11 @@ use crossterm::event::{PushKeyboardEnhancementFlags, PopKeyboardEnhancementFlags
 use unicode_width::{UnicodeWidthStr, UnicodeWidthChar};
 use std::sync::mpsc::{Receiver, Sender};
 use crate::context::Context;
-use crate::request::RequestTaskMessage;
+use crate::request::{tools, RequestTaskMessage};
+use crate::usage;
+use llm_int::{LLMEvent, FinishReason, Annotation, Usage};
 use output_metadata_gen::OutputMetadata;
+use reasoning::{Change, Reasoning};
 
 enum PollingMode {
     AwaitUserin,
@@ -65,7 +69,7 @@ impl UserIn {
         if numrows > self.dist_to_output { self.dist_to_output = numrows; }
 
         let lines_info = LinesInfo {
-            numlines: numrows.into(),
+            numlines: numrows,
             last_ln_width,
         };
         self.lines_info = lines_info.clone();
@@ -82,6 +86,8 @@ pub enum TermTaskMessage {
         user_prompt: String,
         llm_answer_prev: Option<String>
     },
+    /// Answer to `RequestTaskMessage::ToolApproval`
+    ToolApproval(bool),
     Die,
 }
 
@@ -89,10 +95,26 @@ pub enum TermTaskMessage {
 pub struct TermTask {
     userin: UserIn,
     llmout_buf: String,
+    /// Ranges of llmout_buf that were printed by us and are not part of the answer
+    llmout_notices: Vec<(usize, usize)>,
+    /// Ranges of llmout_buf printed in color, to draw them again the same way
+    llmout_styles: Vec<(usize, usize, style::Color)>,
+    /// What the model thought before each step of the current answer, shown in full or as a single line, toggled with Tab
+    reasoning: Reasoning,
     stdout: Stdout,
     ctx: Context,
     polling_mode: PollingMode,
     metadata: OutputMetadata,
+    /// Sources cited by the current answer, listed once it is done
+    annotations: Vec<Annotation>,
+    /// Token usage of the current request as last reported
+    usage: Usage,
+    /// Token usage of the previous requests made for the current answer, when tools are involved
+    answer_usage: Usage,
+    /// Where the text of the last request starts in llmout_buf, what came before led to tool calls
+    answer_start: usize,
+    /// A tool call is waiting on a y/n from the user
+    pending_approval: bool,
     selected_code_block: usize,
     tsize: (u16, u16),
 }
@@ -102,10 +124,18 @@ impl TermTask {
         Self {
             userin: UserIn::new(),
             llmout_buf: String::new(),
+            llmout_notices: Vec::new(),
+            llmout_styles: Vec::new(),
+            reasoning: Reasoning::default(),
             stdout: stdout(),
             ctx,
             polling_mode: PollingMode::AwaitRequestUpdate,
             metadata: OutputMetadata::new(),
+            annotations: Vec::new(),
+            usage: Usage::default(),
+            answer_usage: Usage::default(),
+            answer_start: 0,
+            pending_approval: false,
             selected_code_block: 0,
             tsize: (0, 0),
         }
@@ -144,6 +174,228 @@ impl TermTask {
         Ok(())
     }
 
+    /// Appends to the output area, keeping userin below it
+    fn print_output(&mut self, s: &str, color: Option<style::Color>) -> std::io::Result<()> {
+        self.clear_userin()?;
+
+        let LinesInfo {numlines: userin_ln, ..} = self.userin.get_lines_info();
+        let (_, curscol) = self.llmout_buf.wrapped_width(self.tsize.0);
+        let current_row = self.tsize.1 - (userin_ln as u16) - 1;
+        if let Some(color) = color {
+            queue!(self.stdout, style::SetForegroundColor(color))?;
+        }
+        self.print(s, curscol as u16, current_row)?;
+        if let Some(color) = color {
+            queue!(self.stdout, style::ResetColor)?;
+            self.llmout_styles.push((self.llmout_buf.len(), self.llmout_buf.len() + s.len(), color));
+        }
+        self.llmout_buf.push_str(s);
+
+        let userin_str = format!("{} {}", UserIn::PREFIX, self.userin.buf.as_str());
+        self.print(&userin_str, 0, self.tsize.1)?;
+        Ok(())
+    }
+
+    /// Prints something that isn't part of the answer on its own line(s) in the output area
+    fn print_notice(&mut self, s: &str, color: style::Color) -> std::io::Result<()> {
+        let start = self.llmout_buf.len();
+        let sep = if self.llmout_buf.is_empty() || self.llmout_buf.ends_with('\n') { "" } else { "\n" };
+        self.print_output(&format!("{sep}{s}\n"), Some(color))?;
+        self.llmout_notices.push((start, self.llmout_buf.len()));
+        Ok(())
+    }
+
+    /// The answer as received, without our notices and the reasoning
+    fn answer(&self) -> String {
+        let mut skipped: Vec<(usize, usize)> = self.llmout_notices.iter().copied()
+            .chain(self.reasoning.ranges())
+            .filter(|(_, end)| *end > self.answer_start)
+            .collect();
+        skipped.sort();
+
+        let mut answer = String::new();
+        let mut last = self.answer_start;
+        for (start, end) in skipped {
+            answer.push_str(&self.llmout_buf[last..start]);
+            last = end;
+        }
+        answer.push_str(&self.llmout_buf[last..]);
+        answer
+    }
+
+    fn handle_llm_event(&mut self, event: LLMEvent) -> std::io::Result<()> {
+        match event {
+            LLMEvent::TextDelta(text) => self.print_output(&text, None)?,
+            LLMEvent::RefusalDelta(text) => self.print_output(&text, Some(style::Color::Yellow))?,
+            LLMEvent::ReasoningDelta(text) => self.print_reasoning(&text)?,
+            // only needed by the request task
+            LLMEvent::ReasoningSignature(_) | LLMEvent::RedactedReasoning(_) => (),
+            // the request task puts the calls together and reports them with ToolRunning or ToolApproval
+            LLMEvent::ToolCallDelta { .. } => (),
+            LLMEvent::Annotation(annotation) => {
+                if !self.annotations.iter().any(|a| a.url == annotation.url) {
+                    self.annotations.push(annotation);
+                }
+            },
+            LLMEvent::Usage(usage) => self.usage.merge(&usage),
+            LLMEvent::Finish { reason } => {
+                let notice = match reason {
+                    FinishReason::Length => Some("answer truncated: maximum length reached"),
+                    FinishReason::ContentFilter => Some("answer stopped by the content filter"),
+                    FinishReason::Refusal => Some("the model refused to answer"),
+                    _ => None,
+                };
+                if let Some(notice) = notice {
+                    self.print_notice(notice, style::Color::Yellow)?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn print_reasoning(&mut self, text: &str) -> std::io::Result<()> {
+        let change = self.reasoning.push(text, &self.llmout_buf);
+        self.show_reasoning(change)
+    }
+
+    /// Nothing more will be added to the reasoning of this step, what comes next goes on its own line
+    fn close_reasoning(&mut self) -> std::io::Result<()> {
+        let change = self.reasoning.close(&self.llmout_buf);
+        self.show_reasoning(change)
+    }
+
+    /// Shows a change of the reasoning being streamed, which is the last thing in the output
+    fn show_reasoning(&mut self, change: Option<Change>) -> std::io::Result<()> {
+        let Some(change) = change else { return Ok(()); };
+        let (start, end, text) = match change {
+            Change::Append(text) => return self.print_output(&text, Some(style::Color::DarkGrey)),
+            Change::Replace { start, end, text } => (start, end, text),
+        };
+
+        let old_rows = self.llmout_buf.wrapped_width(self.tsize.0).0 as usize;
+        self.replace_output(start, end, &text);
+        let Some(line) = reasoning::single_row(&text, self.tsize.0) else {
+            return self.redraw_output(old_rows);
+        };
+
+        // the section ends with a newline so it sits right above the current output row
+        let userin_ln = self.userin.get_lines_info().numlines as u16;
+        queue!(self.stdout,
+            cursor::SavePosition,
+            cursor::MoveTo(0, self.tsize.1 - userin_ln - 2),
+            terminal::Clear(terminal::ClearType::CurrentLine),
+            style::SetForegroundColor(style::Color::DarkGrey),
+            style::Print(line),
+            style::ResetColor,
+            cursor::RestorePosition,
+        )?;
+        self.stdout.flush()
+    }
+
+    fn toggle_reasoning(&mut self) -> std::io::Result<()> {
+        let changes = self.reasoning.toggle();
+        if changes.is_empty() { return Ok(()); }
+
+        let old_rows = self.llmout_buf.wrapped_width(self.tsize.0).0 as usize;
+        for change in changes {
+            match change {
+                Change::Replace { start, end, text } => self.replace_output(start, end, &text),
+                Change::Append(text) => self.llmout_buf.push_str(&text),
+            }
+        }
+        if let PollingMode::AwaitUserin = self.polling_mode {
+            self.metadata.clear();
+            self.metadata.generate(&self.llmout_buf);
+        }
+        self.redraw_output(old_rows)
+    }
+
+    /// Replaces part of llmout_buf, shifting whatever points past it
+    fn replace_output(&mut self, start: usize, end: usize, new: &str) {
+        self.llmout_buf.replace_range(start..end, new);
+
+        let delta = new.len() as isize - (end - start) as isize;
+        let shift = |idx: usize| if idx >= end { (idx as isize + delta) as usize } else { idx };
+        for (s, e) in self.llmout_notices.iter_mut() {
+            (*s, *e) = (shift(*s), shift(*e));
+        }
+        self.llmout_styles.retain(|(s, e, _)| *e <= start || *s >= end);
+        for (s, e, _) in self.llmout_styles.iter_mut() {
+            (*s, *e) = (shift(*s), shift(*e));
+        }
+        self.reasoning.shift(end, shift);
+        self.answer_start = shift(self.answer_start);
+    }
+
+    fn output_color(&self, idx: usize) -> Option<style::Color> {
+        if self.reasoning.contains(idx) {
+            return Some(style::Color::DarkGrey);
+        }
+        self.llmout_styles.iter().find(|(s, e, _)| *s <= idx && idx < *e).map(|(_, _, c)| *c)
+    }
+
+    /// Draws the current output again after it changed in place, `old_rows` is how many rows it took before.
+    /// Only what fits on screen is drawn, rows freed above it are left blank.
+    fn redraw_output(&mut self, old_rows: usize) -> std::io::Result<()> {
+        let rows = reasoning::wrap(&self.llmout_buf, self.tsize.0, |i| self.output_color(i));
+
+        let userin_ln = self.userin.get_lines_info().numlines as i32;
+        let bottom = self.tsize.1 as i32 - userin_ln - 1;
+        let top = bottom + 1 - old_rows.max(rows.len()) as i32;
+
+        queue!(self.stdout, cursor::SavePosition)?;
+        for row in top.max(0)..=bottom {
+            queue!(self.stdout, cursor::MoveTo(0, row as u16), terminal::Clear(terminal::ClearType::CurrentLine))?;
+        }
+        let first = bottom + 1 - rows.len() as i32;
+        for (k, runs) in rows.iter().enumerate() {
+            let row = first + k as i32;
+            if row < 0 { continue; }
+            queue!(self.stdout, cursor::MoveTo(0, row as u16))?;
+            for (run, color) in runs {
+                match color {
+                    Some(color) => queue!(self.stdout, style::SetForegroundColor(*color), style::Print(run), style::ResetColor)?,
+                    None => queue!(self.stdout, style::Print(run))?,
+                }
+            }
+        }
+        queue!(self.stdout, cursor::RestorePosition)?;
+        self.stdout.flush()
+    }
+
+    fn print_sources(&mut self) -> std::io::Result<()> {
+        if self.annotations.is_empty() { return Ok(()); }
+
+        let mut sources = String::from("Sources:");
+        for (i, a) in self.annotations.iter().enumerate() {
+            let title = if a.title.is_empty() { a.url.as_str() } else { a.title.as_str() };
+            sources.push_str(&format!("\n  [{}] {title} <{}>", i + 1, a.url));
+        }
+        self.print_notice(&sources, style::Color::DarkGrey)
+    }
+
+    /// A request that ended in tool calls is followed by another one for the same answer
+    fn end_step(&mut self) {
+        let usage = std::mem::take(&mut self.usage);
+        self.answer_usage.add(&usage);
+        self.answer_start = self.llmout_buf.len();
+    }
+
+    fn print_usage(&mut self) -> std::io::Result<()> {
+        let mut usage = std::mem::take(&mut self.answer_usage);
+        usage.add(&std::mem::take(&mut self.usage));
+        if usage.is_empty() { return Ok(()); }
+
+        let session = self.ctx.add_usage(&usage);
+        let mut notice = format!("tokens: {} | session: {}", usage::describe(&usage), usage::describe(&session));
+        if let Some(pricing) = self.ctx.get_pricing() {
+            notice.push_str(&format!("\ncost: {} | session: {}",
+                usage::describe_cost(pricing.cost(&usage)),
+                usage::describe_cost(pricing.cost(&session))));
+        }
+        self.print_notice(&notice, style::Color::DarkGrey)
+    }
+
     fn clear_userin(&mut self) -> std::io::Result<()> {
         queue!(self.stdout, cursor::SavePosition)?;
 
@@ -190,7 +442,7 @@ impl TermTask {
                     idx_col = 0;
                 },
                 _ => {
-                    let will_wrap = (idx_col as usize) + c.width().unwrap_or(0) > tsize.0 as usize;
+                    let will_wrap = idx_col + c.width().unwrap_or(0) > tsize.0 as usize;
                     if will_wrap {
                         idx_row += 1;
                         idx_col = 0;
@@ -241,7 +493,7 @@ impl TermTask {
 
     pub fn run(mut self, tx_tty: Sender<TermTaskMessage>, rx_ans: Receiver<RequestTaskMessage>) -> std::io::Result<()> {
         // make some room
-        println!("");
+        println!();
         terminal::enable_raw_mode()?;
 
         let supports_keyboard_enhancement = matches!(
@@ -261,9 +513,8 @@ impl TermTask {
             )?;
         }
 
-        let run_task = true;
         let mut next_polling: Option<PollingMode> = None;
-        while run_task {
+        loop {
             if let Some(next_polling) = next_polling.take() {
                 self.polling_mode = next_polling;
             }
@@ -271,28 +522,60 @@ impl TermTask {
             let tsize = terminal::size()?;
             self.tsize = tsize;
             let message: Option<RequestTaskMessage> = match self.polling_mode {
-                PollingMode::AwaitRequestUpdate => rx_ans.recv_timeout(Duration::from_millis(30)).map_or(None, |o| Some(o)),
+                PollingMode::AwaitRequestUpdate => rx_ans.recv_timeout(Duration::from_millis(30)).ok(),
                 // try_recv out of safety but we could just return None
-                PollingMode::AwaitUserin => rx_ans.try_recv().map_or(None, |o| Some(o)),
+                PollingMode::AwaitUserin => rx_ans.try_recv().ok(),
             };
 
             if let Some(message) = message {
+                // anything but more reasoning ends the reasoning of the current step
+                if !matches!(message, RequestTaskMessage::Event(
+                    LLMEvent::ReasoningDelta(_) | LLMEvent::ReasoningSignature(_) | LLMEvent::RedactedReasoning(_) | LLMEvent::Usage(_) | LLMEvent::Annotation(_) | LLMEvent::ToolCallDelta { .. }
+                )) {
+                    self.close_reasoning()?;
+                }
+
                 match message {
                     RequestTaskMessage::Done => {
+                        self.print_sources()?;
+                        self.print_usage()?;
                         self.metadata.generate(&self.llmout_buf);
                         next_polling = Some(PollingMode::AwaitUserin);
                     },
-                    RequestTaskMessage::ReceivedPiece(piece) => {
-                        self.clear_userin()?;
-
-                        let LinesInfo {numlines: userin_ln, ..} = self.userin.get_lines_info();
-                        let (_, curscol) = self.llmout_buf.wrapped_width(tsize.0);
-                        let current_row = tsize.1 - (userin_ln as u16) - 1;
-                        self.print(&piece, curscol as u16, current_row)?;
-                        self.llmout_buf.push_str(&piece);
-
-                        let userin_str = format!("{} {}", UserIn::PREFIX, self.userin.buf.as_str());
-                        self.print(&userin_str, 0, self.tsize.1)?;
+                    RequestTaskMessage::Event(event) => {
+                        self.handle_llm_event(event)?;
+                    },
+                    RequestTaskMessage::Error(e) => {
+                        self.print_notice(&format!("Error: {e}"), style::Color::Red)?;
+                    },
+                    RequestTaskMessage::ToolRunning(call) => {
+                        self.print_notice(&format!("> {}", tools::describe(&call)), style::Color::DarkGrey)?;
+                        self.end_step();
+                    },
+                    RequestTaskMessage::ToolApproval(call) => {
+                        self.print_notice(&format!("Allow {}? [y/n]", tools::describe(&call)), style::Color::Yellow)?;
+                        self.pending_approval = true;
+                        self.end_step();
+                    },
+                    RequestTaskMessage::ToolLimit(steps) => {
+                        self.print_notice(&format!("stopped after {steps} rounds of tool calls"), style::Color::Yellow)?;
+                    },
+                    RequestTaskMessage::CostLimit { estimated, limit } => {
+                        let notice = format!("Error: not sent, the prompt would cost about {} which is over the {} limit",
+                            usage::describe_cost(estimated), usage::describe_cost(limit));
+                        self.print_notice(&notice, style::Color::Red)?;
+                    },
+                    RequestTaskMessage::McpFailed { server, error } => {
+                        self.print_notice(&format!("MCP server {server} left out: {error}"), style::Color::Yellow)?;
+                    },
+                    RequestTaskMessage::Retrying { attempt, max_retries, delay, error } => {
+                        // the rate limit message already mentions the delay
+                        let reason = match error {
+                            llm_int::Error::RateLimit { .. } => String::from("rate limited"),
+                            e => e.to_string(),
+                        };
+                        let notice = format!("{reason}, retrying in {:.1}s ({attempt}/{max_retries})", delay.as_secs_f32());
+                        self.print_notice(&notice, style::Color::DarkGrey)?;
                     }
                 }
             }
@@ -300,7 +583,8 @@ impl TermTask {
             let event = match self.polling_mode {
                 PollingMode::AwaitUserin => event::read()?,
                 PollingMode::AwaitRequestUpdate => {
-                    if !event::poll(Duration::ZERO)? { continue; /* wish i had a goto ... */}
+                    // a zero timeout never picks up anything when reading from /dev/tty
+                    if !event::poll(Duration::from_millis(5))? { continue; /* wish i had a goto ... */}
                     event::read()?
                 }
             };
@@ -323,6 +607,19 @@ impl TermTask {
 
             match event {
                 event::Event::Key(evt) if evt.kind != event::KeyEventKind::Release => match evt.code {
+                    event::KeyCode::Char(c) if self.pending_approval => {
+                        let approved = match c {
+                            'y' | 'Y' => true,
+                            'n' | 'N' => false,
+                            _ => continue,
+                        };
+                        self.pending_approval = false;
+                        if !approved {
+                            self.print_notice("denied", style::Color::DarkGrey)?;
+                            self.end_step();
+                        }
+                        let _ = tx_tty.send(TermTaskMessage::ToolApproval(approved));
+                    },
                     event::KeyCode::Char(c) => {
                         self.userin.buf.push(c);
                         self.userin.count_lines(self.tsize);
@@ -330,10 +627,9 @@ impl TermTask {
                     }
                     event::KeyCode::Up => {
                         let block_maybe = self.metadata.code_blocks().get(self.selected_code_block).cloned();
-                        match block_maybe {
-                            Some(block) => { self.set_highlight(false, block.start, block.end)?; }
-                            None => ()
-                        };
+                        if let Some(block) = block_maybe {
+                            self.set_highlight(false, block.start, block.end)?;
+                        }
 
                         let code_blocks = self.metadata.code_blocks();
                         self.selected_code_block = if self.selected_code_block == 0 {
@@ -343,17 +639,15 @@ impl TermTask {
                             self.selected_code_block - 1
                         };
 
-                        match code_blocks.get(self.selected_code_block) {
-                            Some(block) => { self.set_highlight(true, block.start, block.end)?; }
-                            None => ()
-                        };
+                        if let Some(block) = code_blocks.get(self.selected_code_block) {
+                            self.set_highlight(true, block.start, block.end)?;
+                        }
                     },
                     event::KeyCode::Down => {
                         let block_maybe = self.metadata.code_blocks().get(self.selected_code_block).cloned();
-                        match block_maybe {
-                            Some(block) => { self.set_highlight(false, block.start, block.end)?; }
-                            None => ()
-                        };
+                        if let Some(block) = block_maybe {
+                            self.set_highlight(false, block.start, block.end)?;
+                        }
 
                         let code_blocks = self.metadata.code_blocks();
                         self.selected_code_block = if self.selected_code_block == code_blocks.len() - 1 {
@@ -363,11 +657,11 @@ impl TermTask {
                             self.selected_code_block + 1
                         };
 
-                        match code_blocks.get(self.selected_code_block) {
-                            Some(block) => { self.set_highlight(true, block.start, block.end)?; }
-                            None => ()
-                        };
+                        if let Some(block) = code_blocks.get(self.selected_code_block) {
+                            self.set_highlight(true, block.start, block.end)?;
+                        }
                     },
+                    event::KeyCode::Tab => self.toggle_reasoning()?,
                     event::KeyCode::Backspace => {
                         self.userin.buf.pop();
                         self.userin.count_lines(self.tsize);
@@ -381,17 +675,22 @@ impl TermTask {
                         } else {
                             next_polling = Some(PollingMode::AwaitRequestUpdate);
 
-                            match self.metadata.code_blocks().get(self.selected_code_block) {
-                                Some(block) => {
-                                    self.set_highlight(false, block.start, block.end)?;
-                                },
-                                None => (),
+                            if let Some(block) = self.metadata.code_blocks().get(self.selected_code_block) {
+                                self.set_highlight(false, block.start, block.end)?;
                             }
 
                             // printing two newlines just shifts current userin up and leaves a blank space for future llm output
                             self.print("\n\n", 0, tsize.1 - self.userin.get_lines_info().numlines as u16 - 1)?;
 
-                            let llmout_saved = self.llmout_buf.clone();
+                            let llmout_saved = self.answer();
+                            self.llmout_notices.clear();
+                            self.llmout_styles.clear();
+                            self.reasoning.clear();
+                            self.annotations.clear();
+                            self.usage = Usage::default();
+                            self.answer_usage = Usage::default();
+                            self.answer_start = 0;
+                            self.pending_approval = false;
                             let userin_saved = self.userin.buf.clone();
 
                             self.llmout_buf.clear();
@@ -37,21 +37,18 @@ impl OutputMetadata {
         let mut start_idx = 0;
         content.lines().for_each(|ln| {
             let end_idx = start_idx + ln.len();
-            match ln.strip_prefix("```") {
-                Some(trimmed) => {
-                    if let CurrentElement::None = &self.curr {
-                        self.curr = CurrentElement::CodeBlock(CodeBlock {
-                            lang: if trimmed.len() == 0 { None } else { Some(String::from(trimmed)) },
-                            start: end_idx + 1,
-                            end: 0,
-                        });
-                    }
-                    else if let CurrentElement::CodeBlock(ref mut block) = &mut self.curr {
-                        self.code_blocks.push(block.clone());
-                        self.curr = CurrentElement::None;
-                    }
-                },
-                None => (),
+            if let Some(trimmed) = ln.strip_prefix("```") {
+                if let CurrentElement::None = &self.curr {
+                    self.curr = CurrentElement::CodeBlock(CodeBlock {
+                        lang: if trimmed.is_empty() { None } else { Some(String::from(trimmed)) },
+                        start: end_idx + 1,
+                        end: 0,
+                    });
+                }
+                else if let CurrentElement::CodeBlock(ref mut block) = &mut self.curr {
+                    self.code_blocks.push(block.clone());
+                    self.curr = CurrentElement::None;
+                }
             }
 
             match &mut self.curr {
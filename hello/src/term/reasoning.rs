@@ -0,0 +1,287 @@
+use crossterm::style::Color;
+use unicode_width::{UnicodeWidthStr, UnicodeWidthChar};
+
+/// What a reasoning model streamed before answering, shown dimmed and apart from the answer.
+/// `start` and `end` delimit its display in the output buffer, which depends on whether it is expanded.
+#[derive(Debug, Clone)]
+struct ReasoningSection {
+    start: usize,
+    end: usize,
+    text: String,
+    /// The answer or a tool call came after it, nothing more will be added
+    closed: bool,
+}
+
+impl ReasoningSection {
+    const HEADER: &'static str = "▾ reasoning (Tab to collapse)\n";
+
+    fn new(start: usize) -> Self {
+        Self {
+            start,
+            end: start,
+            text: String::new(),
+            closed: false,
+        }
+    }
+
+    /// A single line when collapsed. Once closed it always ends with a newline so the answer starts on its own line.
+    fn display(&self, expanded: bool) -> String {
+        if !expanded {
+            let words = self.text.split_whitespace().count();
+            let progress = if self.closed { "" } else { "…" };
+            return format!("▸ reasoning{progress} {words} words (Tab to expand)\n");
+        }
+
+        let mut display = format!("{}{}", Self::HEADER, self.text);
+        if self.closed && !display.ends_with('\n') {
+            display.push('\n');
+        }
+        display
+    }
+}
+
+/// How the output buffer has to change for the reasoning to be shown right
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    /// Printed at the end of the buffer
+    Append(String),
+    /// `start..end` of the buffer becomes `text`
+    Replace { start: usize, end: usize, text: String },
+}
+
+/// The reasoning of the current answer, a section per step, either all expanded or all collapsed.
+/// Its display lives in the output buffer of the terminal, the changes it needs are handed back to be applied in order.
+#[derive(Debug, Default)]
+pub struct Reasoning {
+    sections: Vec<ReasoningSection>,
+    expanded: bool,
+}
+
+impl Reasoning {
+    pub fn clear(&mut self) {
+        self.sections.clear();
+    }
+
+    pub fn ranges(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.sections.iter().map(|s| (s.start, s.end))
+    }
+
+    pub fn contains(&self, idx: usize) -> bool {
+        self.sections.iter().any(|s| s.start <= idx && idx < s.end)
+    }
+
+    /// Adds to the section at the end of `buffer`, or starts a new one on its own line
+    pub fn push(&mut self, text: &str, buffer: &str) -> Option<Change> {
+        let open = self.sections.last().is_some_and(|s| !s.closed && s.end == buffer.len());
+        let sep = if open || buffer.is_empty() || buffer.ends_with('\n') { "" } else { "\n" };
+        if !open {
+            self.sections.push(ReasoningSection::new(buffer.len() + sep.len()));
+        }
+        let expanded = self.expanded;
+        let section = self.sections.last_mut()?;
+        section.text.push_str(text);
+
+        if expanded || !open {
+            // while open the expanded display only grows, and there is no collapsed one to update yet
+            let display = section.display(expanded);
+            let new = format!("{sep}{}", &display[section.end - section.start..]);
+            section.end = section.start + display.len();
+            Some(Change::Append(new))
+        } else {
+            Self::redisplay(section, buffer)
+        }
+    }
+
+    /// Nothing more will be added to the last section, what comes next goes on its own line
+    pub fn close(&mut self, buffer: &str) -> Option<Change> {
+        let expanded = self.expanded;
+        let section = self.sections.last_mut().filter(|s| !s.closed)?;
+        section.closed = true;
+        if section.end != buffer.len() {
+            return None;
+        }
+
+        if !expanded {
+            Self::redisplay(section, buffer)
+        } else if buffer.ends_with('\n') {
+            None
+        } else {
+            section.end += 1;
+            Some(Change::Append(String::from("\n")))
+        }
+    }
+
+    /// Expands or collapses every section, the later ones first so that the ranges of the earlier ones stay valid
+    pub fn toggle(&mut self) -> Vec<Change> {
+        self.expanded = !self.expanded;
+        let expanded = self.expanded;
+        self.sections.iter_mut().rev()
+            .map(|section| {
+                let (start, end) = (section.start, section.end);
+                let text = section.display(expanded);
+                section.end = start + text.len();
+                Change::Replace { start, end, text }
+            })
+            .collect()
+    }
+
+    /// Follows a change of the buffer that moved what comes after `end`
+    pub fn shift(&mut self, end: usize, shift: impl Fn(usize) -> usize) {
+        for section in self.sections.iter_mut().filter(|s| s.start >= end) {
+            (section.start, section.end) = (shift(section.start), shift(section.end));
+        }
+    }
+
+    /// The collapsed line again, if it changed
+    fn redisplay(section: &mut ReasoningSection, buffer: &str) -> Option<Change> {
+        let (start, end) = (section.start, section.end);
+        let text = section.display(false);
+        if buffer.get(start..end) == Some(text.as_str()) {
+            return None;
+        }
+        section.end = start + text.len();
+        Some(Change::Replace { start, end, text })
+    }
+}
+
+/// The single line a collapsed section is made of, if it can be rewritten in place on a terminal `width` columns wide
+pub fn single_row(text: &str, width: u16) -> Option<&str> {
+    let line = text.trim_end_matches('\n');
+    (!line.contains('\n') && line.width() < width as usize).then_some(line)
+}
+
+/// A row of the terminal as runs of text of the same color
+pub type Row = Vec<(String, Option<Color>)>;
+
+/// Lays out `buffer` on a terminal `width` columns wide, wrapping like the terminal does
+pub fn wrap(buffer: &str, width: u16, color: impl Fn(usize) -> Option<Color>) -> Vec<Row> {
+    let width = width as usize;
+    let mut rows: Vec<Row> = vec![Vec::new()];
+    let mut col = 0;
+    for (i, c) in buffer.char_indices() {
+        let w = c.width().unwrap_or(0);
+        if c == '\n' || col + w > width {
+            rows.push(Vec::new());
+            col = 0;
+            if c == '\n' { continue; }
+        }
+        col += w;
+
+        let color = color(i);
+        let Some(row) = rows.last_mut() else { continue; };
+        match row.last_mut() {
+            Some((run, run_color)) if *run_color == color => run.push(c),
+            _ => row.push((c.to_string(), color)),
+        }
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Applies the changes like the terminal does to its output buffer
+    fn apply(buffer: &mut String, reasoning: &mut Reasoning, changes: impl IntoIterator<Item = Change>) {
+        for change in changes {
+            match change {
+                Change::Append(text) => buffer.push_str(&text),
+                Change::Replace { start, end, text } => {
+                    buffer.replace_range(start..end, &text);
+                    let delta = text.len() as isize - (end - start) as isize;
+                    reasoning.shift(end, |idx| (idx as isize + delta) as usize);
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn display() {
+        let mut section = ReasoningSection::new(0);
+        section.text.push_str("The user wants\na haiku");
+        assert_eq!(section.display(false), "▸ reasoning… 5 words (Tab to expand)\n");
+        assert_eq!(section.display(true), "▾ reasoning (Tab to collapse)\nThe user wants\na haiku");
+
+        section.closed = true;
+        assert_eq!(section.display(false), "▸ reasoning 5 words (Tab to expand)\n");
+        assert!(section.display(true).ends_with("a haiku\n"));
+    }
+
+    #[test]
+    fn collapsed() {
+        let mut reasoning = Reasoning::default();
+        let mut buffer = String::from("Hi");
+        let change = reasoning.push("The user", &buffer);
+        assert_eq!(change, Some(Change::Append(String::from("\n▸ reasoning… 2 words (Tab to expand)\n"))));
+        apply(&mut buffer, &mut reasoning, change);
+
+        // the same line while the count doesn't change
+        assert_eq!(reasoning.push("", &buffer), None);
+        let change = reasoning.push(" wants a haiku", &buffer);
+        assert_eq!(change, Some(Change::Replace { start: 3, end: buffer.len(), text: String::from("▸ reasoning… 5 words (Tab to expand)\n") }));
+        apply(&mut buffer, &mut reasoning, change);
+
+        let change = reasoning.close(&buffer);
+        apply(&mut buffer, &mut reasoning, change);
+        buffer.push_str("Roses");
+        assert_eq!(buffer, "Hi\n▸ reasoning 5 words (Tab to expand)\nRoses");
+        assert_eq!(reasoning.ranges().collect::<Vec<_>>(), [(3, buffer.len() - "Roses".len())]);
+        assert!(reasoning.contains(3) && !reasoning.contains(0) && !reasoning.contains(buffer.len() - 1));
+    }
+
+    #[test]
+    fn expand_and_collapse() {
+        let mut reasoning = Reasoning::default();
+        let mut buffer = String::new();
+        for step in ["look first", "then answer"] {
+            let change = reasoning.push(step, &buffer);
+            apply(&mut buffer, &mut reasoning, change);
+            let change = reasoning.close(&buffer);
+            apply(&mut buffer, &mut reasoning, change);
+            buffer.push_str("done\n");
+        }
+        let collapsed = "▸ reasoning 2 words (Tab to expand)\ndone\n".repeat(2);
+        assert_eq!(buffer, collapsed);
+
+        let changes = reasoning.toggle();
+        apply(&mut buffer, &mut reasoning, changes);
+        assert_eq!(buffer, "▾ reasoning (Tab to collapse)\nlook first\ndone\n▾ reasoning (Tab to collapse)\nthen answer\ndone\n");
+        for (start, end) in reasoning.ranges() {
+            assert!(buffer[start..end].starts_with("▾ reasoning") && buffer[end..].starts_with("done"));
+        }
+
+        let changes = reasoning.toggle();
+        apply(&mut buffer, &mut reasoning, changes);
+        assert_eq!(buffer, collapsed);
+    }
+
+    #[test]
+    fn expanded_while_streaming() {
+        let mut reasoning = Reasoning::default();
+        let changes = reasoning.toggle();
+        assert!(changes.is_empty());
+
+        let mut buffer = String::new();
+        for text in ["Hmm", ", right"] {
+            let change = reasoning.push(text, &buffer);
+            apply(&mut buffer, &mut reasoning, change);
+        }
+        assert_eq!(reasoning.close(&buffer), Some(Change::Append(String::from("\n"))));
+        assert_eq!(buffer, "▾ reasoning (Tab to collapse)\nHmm, right");
+    }
+
+    #[test]
+    fn wrapping() {
+        let rows = wrap("ab\ncdef", 3, |i| (i < 2).then_some(Color::DarkGrey));
+        assert_eq!(rows, [
+            vec![(String::from("ab"), Some(Color::DarkGrey))],
+            vec![(String::from("cde"), None)],
+            vec![(String::from("f"), None)],
+        ]);
+        // wide characters move to the next row whole
+        assert_eq!(wrap("a界", 2, |_| None), [vec![(String::from("a"), None)], vec![(String::from("界"), None)]]);
+
+        assert_eq!(single_row("▸ reasoning 5 words (Tab to expand)\n", 80), Some("▸ reasoning 5 words (Tab to expand)"));
+        assert_eq!(single_row("▸ reasoning 5 words (Tab to expand)\n", 20), None);
+    }
+}
@@ -7,7 +7,7 @@ pub trait StrExt {
 
 impl StrExt for str {
     fn wrapped_width(&self, t_width: u16) -> (u32, usize) {
-        if self.len() == 0 {
+        if self.is_empty() {
             return (0, 0);
         }
 
@@ -33,7 +33,7 @@ mod tests {
 
     #[test]
     fn wrapped_width() {
-        const TEST_STR_1: &'static str = "Once upon a time
+        const TEST_STR_1: &str = "Once upon a time
 A lazy person decided to...
 
 ";
@@ -42,7 +42,7 @@ A lazy person decided to...
         assert_eq!(w, 0);
         assert_eq!(nl, 4);
 
-        const TEST_STR_2: &'static str = "Once upon a time
+        const TEST_STR_2: &str = "Once upon a time
 
     ";
         let (nl, w) = TEST_STR_2.wrapped_width(96);
@@ -50,7 +50,7 @@ A lazy person decided to...
         assert_eq!(w, 4);
         assert_eq!(nl, 3);
 
-        const TEST_STR_3: &'static str = "Once upon a time
+        const TEST_STR_3: &str = "Once upon a time
     x";
         let (nl, w) = TEST_STR_3.wrapped_width(96);
         println!("{TEST_STR_3:?}");
@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+use llm_int::{Message, Usage};
+
+pub const USAGE_FILE_NAME: &str = "usage.jsonl";
+/// Images and files are billed by size, this is about what a medium sized image costs
+const ATTACHMENT_TOKENS: u64 = 1_000;
+
+/// What a single session spent, one per line in the usage file
+#[derive(Serialize, Deserialize)]
+pub struct UsageRecord {
+    /// Seconds since the unix epoch at the end of the session
+    pub timestamp: u64,
+    pub provider: String,
+    pub model: String,
+    #[serde(flatten)]
+    pub usage: Usage,
+}
+
+impl UsageRecord {
+    pub fn new(provider: String, model: String, usage: Usage) -> Self {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        Self { timestamp, provider, model, usage }
+    }
+}
+
+pub fn append_record(path: &Path, record: &UsageRecord) -> std::io::Result<()> {
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(line.as_bytes())
+}
+
+/// Sums every recorded session per provider and model, lines that can't be read are skipped
+pub fn read_totals(path: &Path) -> std::io::Result<BTreeMap<(String, String), (u32, Usage)>> {
+    let mut totals: BTreeMap<(String, String), (u32, Usage)> = BTreeMap::new();
+    let content = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(totals),
+        Err(e) => return Err(e),
+    };
+
+    for record in content.lines().filter_map(|l| serde_json::from_str::<UsageRecord>(l).ok()) {
+        let (sessions, usage) = totals.entry((record.provider, record.model)).or_default();
+        *sessions += 1;
+        usage.add(&record.usage);
+    }
+    Ok(totals)
+}
+
+/// ie: `1,200 in (1,024 cached), 300 out (256 reasoning)`
+pub fn describe(usage: &Usage) -> String {
+    let mut s = format!("{} in", group_digits(usage.prompt_tokens));
+    if usage.cached_tokens > 0 {
+        s.push_str(&format!(" ({} cached)", group_digits(usage.cached_tokens)));
+    }
+    s.push_str(&format!(", {} out", group_digits(usage.completion_tokens)));
+    if usage.reasoning_tokens > 0 {
+        s.push_str(&format!(" ({} reasoning)", group_digits(usage.reasoning_tokens)));
+    }
+    s
+}
+
+/// Four decimals below a dollar, cents are meaningless for a single answer
+pub fn describe_cost(dollars: f64) -> String {
+    if dollars < 1.0 { format!("${dollars:.4}") } else { format!("${dollars:.2}") }
+}
+
+/// Rough count of the tokens a prompt will be billed for, tokenizers average about four characters per token.
+/// A few tokens are added per message for the role and delimiters.
+pub fn estimate_prompt_tokens(messages: &[Message]) -> u64 {
+    messages.iter()
+        .map(|m| {
+            let attachments = m.content.iter().filter(|p| p.text().is_none()).count() as u64;
+            (m.text().chars().count() as u64).div_ceil(4) + attachments * ATTACHMENT_TOKENS + 4
+        })
+        .sum()
+}
+
+fn group_digits(n: u64) -> String {
+    let digits = n.to_string();
+    let mut s = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            s.push(',');
+        }
+        s.push(c);
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use llm_int::{ContentPart, Role};
+
+    #[test]
+    fn describe_usage() {
+        let usage = Usage { prompt_tokens: 1200, completion_tokens: 300, cached_tokens: 1024, reasoning_tokens: 0 };
+        assert_eq!(describe(&usage), "1,200 in (1,024 cached), 300 out");
+        assert_eq!(group_digits(0), "0");
+        assert_eq!(group_digits(999), "999");
+        assert_eq!(group_digits(1_234_567), "1,234,567");
+    }
+
+    #[test]
+    fn estimates() {
+        assert_eq!(describe_cost(0.00123), "$0.0012");
+        assert_eq!(describe_cost(12.345), "$12.35");
+        let messages = vec![Message::new(Role::User, String::from("abcdefghi"))];
+        assert_eq!(estimate_prompt_tokens(&messages), 3 + 4);
+
+        let image = ContentPart::Image { mime_type: String::from("image/png"), data: Vec::new() };
+        let messages = vec![Message::with_parts(Role::User, vec![ContentPart::Text(String::from("abcd")), image])];
+        assert_eq!(estimate_prompt_tokens(&messages), 1 + ATTACHMENT_TOKENS + 4);
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Serialize, Deserialize};
+use zeroize::{Zeroize, Zeroizing};
+
+pub const VAULT_FILE_NAME: &str = "vault.json";
+/// Read instead of prompting for the passphrase, for scripts
+pub const PASSPHRASE_VAR: &str = "HELLO_VAULT_PASSPHRASE";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// The vault as written on disk, hex encoded. The entries are sealed with ChaCha20-Poly1305
+/// under a key derived from the passphrase by Argon2id with its default parameters.
+#[derive(Serialize, Deserialize)]
+struct SealedVault {
+    salt: String,
+    /// Fresh every time the vault is written
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Keys encrypted at rest, by name. Everything secret is zeroized when dropped.
+pub struct Vault {
+    path: PathBuf,
+    salt: [u8; SALT_LEN],
+    /// Kept to seal the vault again once changed
+    key: Zeroizing<[u8; 32]>,
+    entries: BTreeMap<String, String>,
+}
+
+impl Vault {
+    pub fn exists(path: &Path) -> bool {
+        path.is_file()
+    }
+
+    /// Nothing is written until `save`
+    pub fn create_with(path: PathBuf, passphrase: &str) -> Result<Self, String> {
+        let mut salt = [0; SALT_LEN];
+        getrandom::getrandom(&mut salt).map_err(|e| format!("unable to generate a salt: {e}"))?;
+        let key = derive_key(passphrase, &salt)?;
+        Ok(Self { path, salt, key, entries: BTreeMap::new() })
+    }
+
+    pub fn unlock_with(path: PathBuf, passphrase: &str) -> Result<Self, String> {
+        let content = fs::read_to_string(&path).map_err(|e| format!("unable to read the vault: {e}"))?;
+        let sealed: SealedVault = serde_json::from_str(&content).map_err(|_| String::from("the vault is damaged"))?;
+        let (Some(salt), Some(nonce), Some(ciphertext)) = (unhex(&sealed.salt), unhex(&sealed.nonce), unhex(&sealed.ciphertext)) else {
+            return Err(String::from("the vault is damaged"));
+        };
+        let (Ok(salt), true) = (<[u8; SALT_LEN]>::try_from(salt), nonce.len() == NONCE_LEN) else {
+            return Err(String::from("the vault is damaged"));
+        };
+
+        let key = derive_key(passphrase, &salt)?;
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(&*key));
+        let plaintext = Zeroizing::new(cipher.decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| String::from("wrong passphrase, or the vault is damaged"))?);
+        let entries = serde_json::from_slice(&plaintext).map_err(|_| String::from("the vault is damaged"))?;
+        Ok(Self { path, salt, key, entries })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries.get(name).map(String::as_str)
+    }
+
+    pub fn insert(&mut self, name: String, key: String) {
+        if let Some(mut old) = self.entries.insert(name, key) {
+            old.zeroize();
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        if let Some(mut old) = self.entries.remove(name) {
+            old.zeroize();
+        }
+    }
+
+    /// Replaces the file as a whole so that a failed write can't lose the keys, only the owner can read it
+    pub fn save(&self) -> Result<(), String> {
+        let mut nonce = [0; NONCE_LEN];
+        getrandom::getrandom(&mut nonce).map_err(|e| format!("unable to generate a nonce: {e}"))?;
+        let plaintext = Zeroizing::new(serde_json::to_vec(&self.entries).map_err(|e| format!("unable to write the vault: {e}"))?);
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(&*self.key));
+        let ciphertext = cipher.encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+            .map_err(|_| String::from("unable to encrypt the vault"))?;
+        let sealed = SealedVault { salt: hex(&self.salt), nonce: hex(&nonce), ciphertext: hex(&ciphertext) };
+        let json = serde_json::to_string(&sealed).map_err(|e| format!("unable to write the vault: {e}"))?;
+
+        let tmp_path = self.path.with_extension("tmp");
+        let write = || -> std::io::Result<()> {
+            let mut file = OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(&tmp_path)?;
+            file.write_all(json.as_bytes())?;
+            file.sync_all()?;
+            fs::rename(&tmp_path, &self.path)
+        };
+        write().map_err(|e| format!("unable to write the vault: {e}"))
+    }
+}
+
+impl Drop for Vault {
+    fn drop(&mut self) {
+        for key in self.entries.values_mut() {
+            key.zeroize();
+        }
+    }
+}
+
+/// From $HELLO_VAULT_PASSPHRASE, or else typed on the terminal even when stdin is piped.
+/// A new passphrase is asked twice.
+pub fn read_passphrase(confirm: bool) -> Result<Zeroizing<String>, String> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_VAR) {
+        return Ok(Zeroizing::new(passphrase));
+    }
+
+    let no_tty = |e| format!("unable to ask for the vault passphrase, set ${PASSPHRASE_VAR} when not in a terminal: {e}");
+    let passphrase = Zeroizing::new(rpassword::prompt_password("Vault passphrase: ").map_err(no_tty)?);
+    if confirm {
+        if passphrase.is_empty() {
+            return Err(String::from("the vault passphrase can't be empty"));
+        }
+        let again = Zeroizing::new(rpassword::prompt_password("Once more: ").map_err(no_tty)?);
+        if *again != *passphrase {
+            return Err(String::from("the passphrases don't match"));
+        }
+    }
+    Ok(passphrase)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>, String> {
+    let mut key = Zeroizing::new([0; 32]);
+    Argon2::default().hash_password_into(passphrase.as_bytes(), salt, &mut *key)
+        .map_err(|e| format!("unable to derive the vault key: {e}"))?;
+    Ok(key)
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_round_trip() {
+        assert_eq!(hex(&[0, 0xab, 0x10]), "00ab10");
+        assert_eq!(unhex("00ab10"), Some(vec![0, 0xab, 0x10]));
+        assert_eq!(unhex("abc"), None);
+        assert_eq!(unhex("zz"), None);
+    }
+
+    #[test]
+    fn seal_and_unlock() {
+        let path = env::temp_dir().join(format!("hello-vault-{}.json", std::process::id()));
+        let mut vault = Vault::create_with(path.clone(), "correct horse").unwrap();
+        vault.insert(String::from("openai"), String::from("sk-vaulted"));
+        vault.save().unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains("sk-vaulted"));
+
+        let mut vault = Vault::unlock_with(path.clone(), "correct horse").unwrap();
+        assert_eq!(vault.get("openai"), Some("sk-vaulted"));
+        vault.remove("openai");
+        vault.save().unwrap();
+        assert_eq!(Vault::unlock_with(path.clone(), "correct horse").unwrap().get("openai"), None);
+        assert!(Vault::unlock_with(path.clone(), "wrong horse").is_err());
+        let _ = fs::remove_file(&path);
+    }
+}
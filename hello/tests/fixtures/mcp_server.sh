@@ -0,0 +1,38 @@
+#!/bin/sh
+# A tiny MCP server answering with canned responses, only good enough for the tests
+while IFS= read -r line; do
+    id=$(printf '%s' "$line" | sed -n 's/.*"id":\([0-9]*\).*/\1/p')
+    method=$(printf '%s' "$line" | sed -n 's/.*"method":"\([^"]*\)".*/\1/p')
+    [ -z "$id" ] && continue
+    case "$method" in
+        initialize)
+            result='{"protocolVersion":"2025-06-18","capabilities":{"tools":{},"resources":{},"prompts":{}},"serverInfo":{"name":"fixture","version":"0.1.0"}}' ;;
+        tools/list)
+            # also checks that requests from the server are answered
+            printf '{"jsonrpc":"2.0","id":"srv","method":"ping"}\n'
+            result='{"tools":[{"name":"echo","description":"Echoes text","inputSchema":{"type":"object","properties":{"text":{"type":"string"}}},"annotations":{"readOnlyHint":true}}],"nextCursor":"2"}'
+            case "$line" in *'"cursor":"2"'*)
+                result='{"tools":[{"name":"delete","description":"Deletes things","inputSchema":{"type":"object"}}]}' ;;
+            esac ;;
+        resources/list)
+            result='{"resources":[{"uri":"fixture://readme","name":"readme","description":"The readme"}]}' ;;
+        prompts/list)
+            result='{"prompts":[{"name":"review","description":"Reviews code","arguments":[{"name":"lang","required":false}]}]}' ;;
+        tools/call)
+            case "$line" in
+                *'"name":"echo"'*)
+                    text=$(printf '%s' "$line" | sed -n 's/.*"text":"\([^"]*\)".*/\1/p')
+                    result="{\"content\":[{\"type\":\"text\",\"text\":\"echo: $text\"}]}" ;;
+                *)
+                    result='{"content":[{"type":"text","text":"refusing to delete"}],"isError":true}' ;;
+            esac ;;
+        resources/read)
+            result='{"contents":[{"uri":"fixture://readme","text":"fixture readme"}]}' ;;
+        prompts/get)
+            result='{"messages":[{"role":"user","content":{"type":"text","text":"review this"}}]}' ;;
+        *)
+            printf '{"jsonrpc":"2.0","id":%s,"error":{"code":-32601,"message":"method not found"}}\n' "$id"
+            continue ;;
+    esac
+    printf '{"jsonrpc":"2.0","id":%s,"result":%s}\n' "$id" "$result"
+done
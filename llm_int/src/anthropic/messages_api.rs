@@ -0,0 +1,545 @@
+use serde::{Deserialize, Serialize};
+use crate::{LLMApi, LLMEvent, ContentPart, GenerationOptions, Message, Role, Tool, Error, Result};
+use crate::stream::Decoder;
+use http::Request;
+
+const API_VERSION: &str = "2023-06-01";
+
+pub struct ApiContext {
+    model: String,
+    key: String,
+}
+
+impl ApiContext {
+    pub fn new(model: String, key: String) -> Self {
+        Self {
+            model,
+            key
+        }
+    }
+}
+
+impl LLMApi for ApiContext {
+    fn build_request(&self, messages: Vec<Message>, tools: &[Tool], options: &GenerationOptions) -> Result<Request<Vec<u8>>> {
+        // The Messages API has no developer role, instructions go in a top level field instead
+        let mut system = String::new();
+        let mut messages_tx: Vec<MessageTx> = Vec::new();
+        for message in messages {
+            let mut content = Vec::new();
+            // empty text blocks are rejected
+            if message.role != Role::Tool {
+                content.extend(message.content.iter().filter(|p| p.text() != Some("")).map(ContentBlockTx::from_part));
+            }
+
+            let role = match message.role {
+                Role::Developer => {
+                    if !system.is_empty() { system.push('\n'); }
+                    system.push_str(&message.text());
+                    continue;
+                },
+                Role::User => MessageRole::User,
+                Role::Assistant => {
+                    // with extended thinking, the thinking that led to tool calls must be sent back untouched and first
+                    if let Some(reasoning) = message.reasoning {
+                        let redacted = reasoning.redacted.into_iter().map(|data| ContentBlockTx::RedactedThinking { data });
+                        content.splice(0..0, redacted);
+                        if let Some(signature) = reasoning.signature {
+                            content.insert(0, ContentBlockTx::Thinking { thinking: reasoning.text, signature });
+                        }
+                    }
+                    for call in message.tool_calls {
+                        // the arguments were written by the model, they can be broken
+                        let input = serde_json::from_str(&call.arguments).unwrap_or(serde_json::json!({}));
+                        content.push(ContentBlockTx::ToolUse { id: call.id, name: call.name, input });
+                    }
+                    MessageRole::Assistant
+                },
+                // results go back as user content
+                Role::Tool => {
+                    content.push(ContentBlockTx::ToolResult {
+                        tool_use_id: message.tool_call_id.clone().unwrap_or_default(),
+                        content: message.text(),
+                    });
+                    MessageRole::User
+                },
+            };
+
+            // a message without any block is rejected, e.g. an assistant turn with no text nor calls
+            if content.is_empty() {
+                continue;
+            }
+
+            // roles must alternate, consecutive tool results especially have to be sent as a single message
+            match messages_tx.last_mut() {
+                Some(last) if last.role == role => last.content.extend(content),
+                _ => messages_tx.push(MessageTx { role, content }),
+            }
+        }
+
+        // There is no response format to ask for, the schema can only be given as instructions
+        if let Some(format) = &options.response_format {
+            if !system.is_empty() { system.push('\n'); }
+            system.push_str(&format.instructions());
+        }
+
+        // Extended thinking comes out of max_tokens and doesn't let the sampling be changed
+        let thinking = options.reasoning_effort.map(|e| Thinking { thinking_type: "enabled", budget_tokens: e.budget_tokens() });
+        let (max_tokens, temperature, top_p) = match &thinking {
+            Some(thinking) => (options.max_tokens() + thinking.budget_tokens, None, None),
+            None => (options.max_tokens(), options.temperature, options.top_p),
+        };
+
+        let body = RequestBody {
+            model: self.model.clone(),
+            system: if system.is_empty() { None } else { Some(system) },
+            messages: messages_tx,
+            tools: tools.iter()
+                .map(|t| ToolTx { name: t.name.clone(), description: t.description.clone(), input_schema: t.parameters.clone() })
+                .collect(),
+            max_tokens,
+            temperature,
+            top_p,
+            stop_sequences: options.stop.clone(),
+            thinking,
+            stream: true,
+        };
+
+        Ok(Request::post("https://api.anthropic.com/v1/messages")
+            .header("Content-Type", "application/json")
+            .header("x-api-key", self.key.as_str())
+            .header("anthropic-version", API_VERSION)
+            .body(serde_json::to_vec(&body).map_err(|e| Error::InvalidRequest(e.to_string()))?)?)
+    }
+
+    fn build_response(&self, decoder: &mut Decoder, data: &[u8]) -> Result<(usize, Vec<LLMEvent>)> {
+        // Every event carries its type in the json payload as well, so the `event:` field can be ignored
+        let mut events = Vec::new();
+        for event in decoder.events(data) {
+            match serde_json::from_str(event.data.as_str())? {
+                StreamEvent::MessageStart { message } => {
+                    if let Some(usage) = message.usage {
+                        events.push(LLMEvent::Usage(usage.into()));
+                    }
+                },
+                StreamEvent::ContentBlockStart { index, content_block: ContentBlock::ToolUse { id, name } } => {
+                    events.push(LLMEvent::ToolCallDelta { index, id: Some(id), name: Some(name), arguments: String::new() });
+                },
+                // comes whole, there are no deltas for it
+                StreamEvent::ContentBlockStart { content_block: ContentBlock::RedactedThinking { data }, .. } => {
+                    events.push(LLMEvent::RedactedReasoning(data));
+                },
+                StreamEvent::ContentBlockDelta { index, delta } => match delta {
+                    Delta::Text { text } => events.push(LLMEvent::TextDelta(text)),
+                    Delta::Thinking { thinking } => events.push(LLMEvent::ReasoningDelta(thinking)),
+                    Delta::Signature { signature } => events.push(LLMEvent::ReasoningSignature(signature)),
+                    Delta::InputJson { partial_json } => {
+                        events.push(LLMEvent::ToolCallDelta { index, id: None, name: None, arguments: partial_json });
+                    },
+                    Delta::Citations { citation } => {
+                        if let Some(url) = citation.url {
+                            events.push(LLMEvent::Annotation(crate::Annotation {
+                                title: citation.title.unwrap_or_default(),
+                                url,
+                                start_index: None,
+                                end_index: None,
+                            }));
+                        }
+                    },
+                    Delta::Other => (),
+                },
+                StreamEvent::MessageDelta { delta, usage } => {
+                    if let Some(usage) = usage {
+                        events.push(LLMEvent::Usage(usage.into()));
+                    }
+                    if let Some(reason) = delta.stop_reason {
+                        events.push(LLMEvent::Finish { reason: reason.into() });
+                    }
+                },
+                StreamEvent::Error { error } => return Err(error.into()),
+                _ => (),
+            }
+        }
+
+        Ok((data.len(), events))
+    }
+
+    fn build_error(&self, status: u32, body: &[u8]) -> Error {
+        match serde_json::from_slice::<ErrorResponse>(body) {
+            Ok(ErrorResponse { error }) => Error::from_status(status, Some(error.error_type), Some(error.message)),
+            Err(_) => Error::from_status(status, None, None),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(rename_all="snake_case")]
+enum MessageRole {User, Assistant}
+
+#[derive(Serialize)]
+#[serde(tag="type", rename_all="snake_case")]
+enum ContentBlockTx {
+    Text { text: String },
+    Image { source: SourceTx },
+    /// PDFs and plain text, other files are refused
+    Document { source: SourceTx },
+    Thinking { thinking: String, signature: String },
+    RedactedThinking { data: String },
+    ToolUse { id: String, name: String, input: serde_json::Value },
+    ToolResult { tool_use_id: String, content: String },
+}
+
+impl ContentBlockTx {
+    fn from_part(part: &ContentPart) -> Self {
+        match part {
+            ContentPart::Text(text) => ContentBlockTx::Text { text: text.clone() },
+            ContentPart::ImageUrl(url) => ContentBlockTx::Image { source: SourceTx::Url { url: url.clone() } },
+            ContentPart::Image { mime_type, data } => ContentBlockTx::Image {
+                source: SourceTx::Base64 { media_type: mime_type.clone(), data: crate::content::base64(data) },
+            },
+            ContentPart::File { mime_type, data, .. } => ContentBlockTx::Document {
+                source: SourceTx::Base64 { media_type: mime_type.clone(), data: crate::content::base64(data) },
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag="type", rename_all="snake_case")]
+enum SourceTx {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+}
+
+#[derive(Serialize)]
+struct MessageTx {
+    role: MessageRole,
+    content: Vec<ContentBlockTx>,
+}
+
+#[derive(Serialize)]
+struct ToolTx {
+    name: String,
+    description: String,
+    input_schema: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct RequestBody {
+    model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    messages: Vec<MessageTx>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<ToolTx>,
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop_sequences: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking: Option<Thinking>,
+    stream: bool,
+}
+
+#[derive(Serialize)]
+struct Thinking {
+    #[serde(rename="type")]
+    thinking_type: &'static str,
+    budget_tokens: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all="snake_case")]
+enum StopReason {EndTurn, MaxTokens, StopSequence, ToolUse, PauseTurn, Refusal}
+
+impl From<StopReason> for crate::FinishReason {
+    fn from(r: StopReason) -> Self {
+        match r {
+            StopReason::EndTurn | StopReason::StopSequence => crate::FinishReason::Stop,
+            StopReason::MaxTokens => crate::FinishReason::Length,
+            StopReason::ToolUse => crate::FinishReason::ToolCalls,
+            StopReason::Refusal => crate::FinishReason::Refusal,
+            StopReason::PauseTurn => crate::FinishReason::Other(String::from("pause_turn")),
+        }
+    }
+}
+
+/// Input tokens are reported when the message starts, output tokens as it goes.
+/// Tokens read from or written to the prompt cache are not counted in input_tokens.
+#[derive(Deserialize)]
+struct Usage {
+    #[serde(default)]
+    input_tokens: u64,
+    #[serde(default)]
+    output_tokens: u64,
+    #[serde(default)]
+    cache_creation_input_tokens: u64,
+    #[serde(default)]
+    cache_read_input_tokens: u64,
+}
+
+impl From<Usage> for crate::Usage {
+    fn from(u: Usage) -> Self {
+        crate::Usage {
+            prompt_tokens: u.input_tokens + u.cache_creation_input_tokens + u.cache_read_input_tokens,
+            completion_tokens: u.output_tokens,
+            cached_tokens: u.cache_read_input_tokens,
+            reasoning_tokens: 0,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[allow(unused)]
+struct MessageStart {
+    id: String,
+    model: String,
+    role: MessageRole,
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+#[allow(unused)]
+struct MessageDeltaBody {
+    stop_reason: Option<StopReason>,
+    stop_sequence: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag="type", rename_all="snake_case")]
+#[allow(unused)]
+enum Delta {
+    #[serde(rename = "text_delta")]
+    Text { text: String },
+    #[serde(rename = "input_json_delta")]
+    InputJson { partial_json: String },
+    #[serde(rename = "citations_delta")]
+    Citations { citation: Citation },
+    #[serde(rename = "thinking_delta")]
+    Thinking { thinking: String },
+    #[serde(rename = "signature_delta")]
+    Signature { signature: String },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct Citation {
+    url: Option<String>,
+    title: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag="type", rename_all="snake_case")]
+enum ContentBlock {
+    ToolUse { id: String, name: String },
+    RedactedThinking { data: String },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    #[serde(rename = "type")]
+    error_type: String,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ErrorBody,
+}
+
+impl From<ErrorBody> for Error {
+    fn from(e: ErrorBody) -> Self {
+        match e.error_type.as_str() {
+            "rate_limit_error" => Error::RateLimit { retry_after: None },
+            _ => Error::Provider { status: None, kind: Some(e.error_type), message: e.message },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag="type", rename_all="snake_case")]
+#[allow(unused)]
+enum StreamEvent {
+    MessageStart { message: MessageStart },
+    ContentBlockStart { index: usize, content_block: ContentBlock },
+    ContentBlockDelta { index: usize, delta: Delta },
+    ContentBlockStop { index: usize },
+    MessageDelta { delta: MessageDeltaBody, usage: Option<Usage> },
+    MessageStop,
+    Ping,
+    Error { error: ErrorBody },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STREAM: &str = "event: message_start\n\
+data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"model\":\"claude\",\"role\":\"assistant\",\"usage\":{\"input_tokens\":12,\"output_tokens\":1}}}\n\n\
+event: content_block_delta\n\
+data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Let me look\"}}\n\n\
+event: content_block_start\n\
+data: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"tool_use\",\"id\":\"toolu_1\",\"name\":\"read_file\",\"input\":{}}}\n\n\
+event: content_block_delta\n\
+data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"{\\\"path\\\":\"}}\n\n\
+event: message_delta\n\
+data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"tool_use\",\"stop_sequence\":null},\"usage\":{\"output_tokens\":30}}\n\n";
+
+    #[test]
+    fn build_request_merges_tool_results() {
+        let api = ApiContext::new(String::from("claude"), String::from("key"));
+        let calls = vec![
+            crate::ToolCall { id: String::from("toolu_1"), name: String::from("list_dir"), arguments: String::from("{\"path\":\".\"}") },
+            crate::ToolCall { id: String::from("toolu_2"), name: String::from("list_dir"), arguments: String::new() },
+        ];
+        let messages = vec![
+            Message::new(Role::Developer, String::from("be brief")),
+            Message::new(Role::User, String::from("what's here?")),
+            Message::with_tool_calls(String::from("Let me look"), calls),
+            Message::tool_result(String::from("toolu_1"), String::from("a.txt")),
+            Message::tool_result(String::from("toolu_2"), String::from("b.txt")),
+        ];
+        let req = api.build_request(messages, &[], &GenerationOptions::default()).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+
+        assert_eq!(body["system"], "be brief");
+        assert!(body.get("tools").is_none());
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1]["content"][1], serde_json::json!({"type": "tool_use", "id": "toolu_1", "name": "list_dir", "input": {"path": "."}}));
+        assert_eq!(messages[1]["content"][2]["input"], serde_json::json!({}));
+        assert_eq!(messages[2]["role"], "user");
+        assert_eq!(messages[2]["content"][1], serde_json::json!({"type": "tool_result", "tool_use_id": "toolu_2", "content": "b.txt"}));
+    }
+
+    #[test]
+    fn build_request_skips_empty_answer() {
+        let api = ApiContext::new(String::from("claude"), String::from("key"));
+        let messages = vec![
+            Message::new(Role::User, String::from("hi")),
+            Message::new(Role::Assistant, String::new()),
+            Message::new(Role::User, String::from("again")),
+        ];
+        let req = api.build_request(messages, &[], &GenerationOptions::default()).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+        assert_eq!(body["messages"], serde_json::json!([{"role": "user", "content": [
+            {"type": "text", "text": "hi"},
+            {"type": "text", "text": "again"},
+        ]}]));
+    }
+
+    #[test]
+    fn build_request_with_thinking() {
+        let api = ApiContext::new(String::from("m"), String::from("key"));
+        let options = GenerationOptions {
+            temperature: Some(0.2),
+            max_tokens: Some(1000),
+            stop: vec![String::from("END")],
+            seed: Some(42),
+            reasoning_effort: Some(crate::ReasoningEffort::Minimal),
+            ..Default::default()
+        };
+        let req = api.build_request(Message::new_user_request(String::from("hi")), &[], &options).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+
+        assert_eq!(body["thinking"], serde_json::json!({"type": "enabled", "budget_tokens": 1024}));
+        assert_eq!(body["max_tokens"], 2024);
+        assert_eq!(body["stop_sequences"], serde_json::json!(["END"]));
+        assert!(body.get("temperature").is_none() && body.get("seed").is_none());
+
+        let reasoning = crate::Reasoning { text: String::from("look first"), signature: Some(String::from("sig")), redacted: vec![String::from("enc")] };
+        let call = crate::ToolCall { id: String::from("toolu_1"), name: String::from("list_dir"), arguments: String::new() };
+        let messages = vec![
+            Message::new(Role::User, String::from("what's here?")),
+            Message::with_tool_calls(String::from("Let me look"), vec![call]).with_reasoning(reasoning),
+        ];
+        let req = api.build_request(messages, &[], &options).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+        assert_eq!(body["messages"][1]["content"][0], serde_json::json!({"type": "thinking", "thinking": "look first", "signature": "sig"}));
+        assert_eq!(body["messages"][1]["content"][1], serde_json::json!({"type": "redacted_thinking", "data": "enc"}));
+        assert_eq!(body["messages"][1]["content"][2]["type"], "text");
+    }
+
+    #[test]
+    fn build_request_with_parts() {
+        let api = ApiContext::new(String::from("m"), String::from("key"));
+        let messages = vec![Message::with_parts(Role::User, vec![
+            ContentPart::Text(String::from("what's that?")),
+            ContentPart::Image { mime_type: String::from("image/png"), data: b"png".to_vec() },
+            ContentPart::ImageUrl(String::from("https://example.com/a.jpg")),
+            ContentPart::File { name: String::from("a.pdf"), mime_type: String::from("application/pdf"), data: b"pdf".to_vec() },
+        ])];
+        let req = api.build_request(messages, &[], &GenerationOptions::default()).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+
+        assert_eq!(body["messages"][0]["content"], serde_json::json!([
+            {"type": "text", "text": "what's that?"},
+            {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "cG5n"}},
+            {"type": "image", "source": {"type": "url", "url": "https://example.com/a.jpg"}},
+            {"type": "document", "source": {"type": "base64", "media_type": "application/pdf", "data": "cGRm"}},
+        ]));
+    }
+
+    #[test]
+    fn build_response_thinking() {
+        let api = ApiContext::new(String::from("claude"), String::from("key"));
+        let stream = "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"thinking\",\"thinking\":\"\"}}\n\n\
+data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"thinking_delta\",\"thinking\":\"Hmm\"}}\n\n\
+data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"signature_delta\",\"signature\":\"sig\"}}\n\n\
+data: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"redacted_thinking\",\"data\":\"enc\"}}\n\n";
+        let (_, events) = api.build_response(&mut Decoder::new(), stream.as_bytes()).unwrap();
+        assert_eq!(events, vec![
+            LLMEvent::ReasoningDelta(String::from("Hmm")),
+            LLMEvent::ReasoningSignature(String::from("sig")),
+            LLMEvent::RedactedReasoning(String::from("enc")),
+        ]);
+    }
+
+    #[test]
+    fn build_response_events() {
+        let api = ApiContext::new(String::from("claude"), String::from("key"));
+        let (_, events) = api.build_response(&mut Decoder::new(), STREAM.as_bytes()).unwrap();
+        assert_eq!(events, vec![
+            LLMEvent::Usage(crate::Usage { prompt_tokens: 12, completion_tokens: 1, ..Default::default() }),
+            LLMEvent::TextDelta(String::from("Let me look")),
+            LLMEvent::ToolCallDelta { index: 1, id: Some(String::from("toolu_1")), name: Some(String::from("read_file")), arguments: String::new() },
+            LLMEvent::ToolCallDelta { index: 1, id: None, name: None, arguments: String::from("{\"path\":") },
+            LLMEvent::Usage(crate::Usage { completion_tokens: 30, ..Default::default() }),
+            LLMEvent::Finish { reason: crate::FinishReason::ToolCalls },
+        ]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let api = ApiContext::new(String::from("claude"), String::from("key"));
+        let tools = [Tool::new("read_file", "Reads a file", serde_json::json!({"type": "object"}))];
+        let mut messages = vec![
+            Message::new(Role::Developer, String::from("be brief")),
+            Message::new(Role::User, String::from("what's in a.txt?")),
+        ];
+        let req = api.build_request(messages.clone(), &tools, &GenerationOptions::default()).unwrap();
+        assert_eq!(req.headers()["x-api-key"], "key");
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+        assert_eq!(body["tools"][0], serde_json::json!({"name": "read_file", "description": "Reads a file", "input_schema": {"type": "object"}}));
+        assert_eq!(body["messages"], serde_json::json!([{"role": "user", "content": [{"type": "text", "text": "what's in a.txt?"}]}]));
+
+        // the arguments end up split across deltas
+        let stream = format!("{STREAM}data: {{\"type\":\"content_block_delta\",\"index\":1,\"delta\":{{\"type\":\"input_json_delta\",\"partial_json\":\"\\\"a.txt\\\"}}\"}}}}\n\n");
+        let (_, events) = api.build_response(&mut Decoder::new(), stream.as_bytes()).unwrap();
+        messages.push(crate::tools::answer_message(&events));
+        messages.push(Message::tool_result(String::from("toolu_1"), String::from("hello")));
+
+        let req = api.build_request(messages, &tools, &GenerationOptions::default()).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+        assert_eq!(body["messages"][1], serde_json::json!({"role": "assistant", "content": [
+            {"type": "text", "text": "Let me look"},
+            {"type": "tool_use", "id": "toolu_1", "name": "read_file", "input": {"path": "a.txt"}},
+        ]}));
+        assert_eq!(body["messages"][2], serde_json::json!({"role": "user", "content": [{"type": "tool_result", "tool_use_id": "toolu_1", "content": "hello"}]}));
+    }
+}
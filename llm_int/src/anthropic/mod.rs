@@ -0,0 +1,18 @@
+pub mod messages_api;
+
+use crate::models::{ModelInfo, Pricing};
+
+pub struct Models;
+#[allow(non_upper_case_globals)]
+impl Models {
+    pub const Claude_Sonnet_4: &'static str = "claude-sonnet-4-20250514";
+    pub const Claude_Opus_4: &'static str = "claude-opus-4-20250514";
+    pub const Claude_Haiku_3_5: &'static str = "claude-3-5-haiku-20241022";
+
+    /// Cache writes cost a bit more than regular input, we don't track them separately
+    pub const ALL: &'static [ModelInfo] = &[
+        ModelInfo { name: Self::Claude_Sonnet_4, alias: Some("claude-sonnet-4-0"), pricing: Pricing::new(3.0, 15.0, 0.30), reasoning: true },
+        ModelInfo { name: Self::Claude_Opus_4, alias: Some("claude-opus-4-0"), pricing: Pricing::new(15.0, 75.0, 1.50), reasoning: true },
+        ModelInfo { name: Self::Claude_Haiku_3_5, alias: Some("claude-3-5-haiku-latest"), pricing: Pricing::new(0.80, 4.0, 0.08), reasoning: false },
+    ];
+}
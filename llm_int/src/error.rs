@@ -0,0 +1,72 @@
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub enum Error {
+    /// The request could not be built from what was given
+    InvalidRequest(String),
+    /// The provider could not be reached or the connection dropped
+    Transport(String),
+    /// A non success status without any usable explanation
+    HttpStatus(u32),
+    /// The provider explained what went wrong
+    Provider {
+        status: Option<u32>,
+        kind: Option<String>,
+        message: String,
+    },
+    /// The provider sent something we don't understand
+    Decode(String),
+    /// The model declined to answer or its answer got filtered
+    Refusal(String),
+    RateLimit {
+        retry_after: Option<Duration>,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidRequest(msg) => write!(f, "invalid request: {msg}"),
+            Error::Transport(msg) => write!(f, "network error: {msg}"),
+            Error::HttpStatus(401) => write!(f, "invalid API key"),
+            Error::HttpStatus(403) => write!(f, "access denied, check your API key permissions"),
+            Error::HttpStatus(404) => write!(f, "model or endpoint not found"),
+            Error::HttpStatus(status) if *status >= 500 => write!(f, "the provider is unavailable (HTTP {status})"),
+            Error::HttpStatus(status) => write!(f, "request failed (HTTP {status})"),
+            Error::Provider { message, .. } => write!(f, "{message}"),
+            Error::Decode(msg) => write!(f, "failed to decode the provider response: {msg}"),
+            Error::Refusal(msg) if msg.is_empty() => write!(f, "the model refused to answer"),
+            Error::Refusal(msg) => write!(f, "the model refused to answer: {msg}"),
+            Error::RateLimit { retry_after: Some(d) } => write!(f, "rate limited, retry in {}s", d.as_secs().max(1)),
+            Error::RateLimit { retry_after: None } => write!(f, "rate limited"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    /// Builds the error for a failed request once the provider's own error format has been decoded
+    pub(crate) fn from_status(status: u32, kind: Option<String>, message: Option<String>) -> Self {
+        match (status, message) {
+            (429, _) => Error::RateLimit { retry_after: None },
+            (_, Some(message)) => Error::Provider { status: Some(status), kind, message },
+            (_, None) => Error::HttpStatus(status),
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Decode(e.to_string())
+    }
+}
+
+impl From<http::Error> for Error {
+    fn from(e: http::Error) -> Self {
+        Error::InvalidRequest(e.to_string())
+    }
+}
@@ -0,0 +1,515 @@
+use serde::{Deserialize, Serialize};
+use crate::{LLMApi, LLMEvent, ContentPart, Defaults, GenerationOptions, Message, Role, Tool, Error, Result};
+use crate::stream::Decoder;
+use http::Request;
+
+pub struct ApiContext {
+    model: String,
+    key: String,
+}
+
+impl ApiContext {
+    pub fn new(model: String, key: String) -> Self {
+        Self {
+            model,
+            key
+        }
+    }
+}
+
+impl LLMApi for ApiContext {
+    fn build_request(&self, messages: Vec<Message>, tools: &[Tool], options: &GenerationOptions) -> Result<Request<Vec<u8>>> {
+        // Gemini has no developer role, instructions are passed as a separate top level content
+        let mut system_parts = Vec::new();
+        let mut contents: Vec<Content> = Vec::new();
+        for message in &messages {
+            let mut parts = Vec::new();
+            if message.role != Role::Tool {
+                for part in message.content.iter().filter(|p| p.text() != Some("")) {
+                    parts.push(Part::from_part(part)?);
+                }
+            }
+
+            let role = match message.role {
+                Role::Developer => {
+                    system_parts.extend(parts);
+                    continue;
+                },
+                Role::User => ContentRole::User,
+                Role::Assistant => {
+                    for call in &message.tool_calls {
+                        let args = serde_json::from_str(&call.arguments).unwrap_or(serde_json::json!({}));
+                        parts.push(Part {
+                            function_call: Some(FunctionCall { name: call.name.clone(), args }),
+                            ..Default::default()
+                        });
+                    }
+                    ContentRole::Model
+                },
+                // calls are matched by function name rather than by id, the response has to be an object
+                Role::Tool => {
+                    let result = message.text();
+                    let response = match serde_json::from_str(&result) {
+                        Ok(serde_json::Value::Object(o)) => serde_json::Value::Object(o),
+                        _ => serde_json::json!({ "result": result }),
+                    };
+                    parts.push(Part {
+                        function_response: Some(FunctionResponse {
+                            name: message.tool_name(&messages).unwrap_or_default().to_string(),
+                            response,
+                        }),
+                        ..Default::default()
+                    });
+                    ContentRole::User
+                },
+            };
+
+            // a content without parts is rejected, e.g. an answer with no text nor calls
+            if parts.is_empty() {
+                continue;
+            }
+
+            // all the results of the calls of a turn must come in a single content
+            match contents.last_mut() {
+                Some(last) if last.role.as_ref() == Some(&role) => last.parts.extend(parts),
+                _ => contents.push(Content { role: Some(role), parts }),
+            }
+        }
+
+        let tools = if tools.is_empty() {
+            Vec::new()
+        } else {
+            vec![ToolTx {
+                function_declarations: tools.iter()
+                    .map(|t| FunctionDeclaration {
+                        name: t.name.clone(),
+                        description: t.description.clone(),
+                        parameters_json_schema: t.parameters.clone(),
+                    })
+                    .collect(),
+            }]
+        };
+
+        let body = RequestBody {
+            contents,
+            system_instruction: if system_parts.is_empty() { None } else { Some(Content { role: None, parts: system_parts }) },
+            tools,
+            generation_config: GenerationConfig {
+                max_output_tokens: options.max_tokens(),
+                candidate_count: Defaults::NUM_GENS,
+                temperature: options.temperature,
+                top_p: options.top_p,
+                stop_sequences: options.stop.clone(),
+                seed: options.seed,
+                presence_penalty: options.presence_penalty,
+                frequency_penalty: options.frequency_penalty,
+                thinking_config: options.reasoning_effort.map(|e| ThinkingConfig { thinking_budget: e.budget_tokens(), include_thoughts: true }),
+                response_mime_type: options.response_format.as_ref().map(|_| "application/json"),
+                response_json_schema: options.response_format.as_ref().map(|f| f.schema.clone()),
+            },
+        };
+
+        Ok(Request::post(format!("https://generativelanguage.googleapis.com/v1beta/models/{}:streamGenerateContent?alt=sse", self.model))
+            .header("Content-Type", "application/json")
+            .header("x-goog-api-key", self.key.as_str())
+            .body(serde_json::to_vec(&body).map_err(|e| Error::InvalidRequest(e.to_string()))?)?)
+    }
+
+    fn build_response(&self, decoder: &mut Decoder, data: &[u8]) -> Result<(usize, Vec<LLMEvent>)> {
+        let mut events = Vec::new();
+        for event in decoder.events(data) {
+            let data_parsed: Response = serde_json::from_str(event.data.as_str())?;
+            if let Some(block_reason) = data_parsed.prompt_feedback.and_then(|f| f.block_reason) {
+                return Err(Error::Refusal(format!("the prompt was blocked ({block_reason})")));
+            }
+
+            if let Some(candidate) = data_parsed.candidates.into_iter().next() {
+                for part in candidate.content.map(|c| c.parts).unwrap_or_default() {
+                    if let Some(text) = part.text.filter(|t| !t.is_empty()) {
+                        if part.thought == Some(true) {
+                            events.push(LLMEvent::ReasoningDelta(text));
+                        } else {
+                            events.push(LLMEvent::TextDelta(text));
+                        }
+                    }
+                    // function calls are never split across chunks
+                    if let Some(call) = part.function_call {
+                        events.push(LLMEvent::ToolCallDelta {
+                            index: decoder.next_index(),
+                            id: None,
+                            name: Some(call.name),
+                            arguments: call.args.to_string(),
+                        });
+                    }
+                }
+                if let Some(reason) = candidate.finish_reason {
+                    // a turn that called functions still stops with STOP
+                    let reason = match reason {
+                        FinishReason::Stop if decoder.indexed() > 0 => crate::FinishReason::ToolCalls,
+                        reason => reason.into(),
+                    };
+                    events.push(LLMEvent::Finish { reason });
+                }
+            }
+
+            if let Some(usage) = data_parsed.usage_metadata {
+                // thinking tokens are billed as output but not counted in the candidates
+                events.push(LLMEvent::Usage(crate::Usage {
+                    prompt_tokens: usage.prompt_token_count,
+                    completion_tokens: usage.candidates_token_count + usage.thoughts_token_count,
+                    cached_tokens: usage.cached_content_token_count,
+                    reasoning_tokens: usage.thoughts_token_count,
+                }));
+            }
+        }
+
+        Ok((data.len(), events))
+    }
+
+    fn build_error(&self, status: u32, body: &[u8]) -> Error {
+        // The streaming endpoint wraps its error in an array
+        let error = serde_json::from_slice::<ErrorResponse>(body).ok()
+            .or_else(|| serde_json::from_slice::<Vec<ErrorResponse>>(body).ok().and_then(|v| v.into_iter().next()));
+        match error {
+            Some(ErrorResponse { error }) => Error::from_status(status, error.status, Some(error.message)),
+            None => Error::from_status(status, None, None),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(rename_all="snake_case")]
+enum ContentRole {User, Model}
+
+#[derive(Serialize, Deserialize)]
+struct FunctionCall {
+    name: String,
+    #[serde(default)]
+    args: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FunctionResponse {
+    name: String,
+    response: serde_json::Value,
+}
+
+/// Only one of the fields is ever set
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all="camelCase")]
+struct Part {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    /// The text is a summary of the model's thoughts rather than part of the answer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thought: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    function_call: Option<FunctionCall>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    function_response: Option<FunctionResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inline_data: Option<Blob>,
+}
+
+impl Part {
+    fn text(text: String) -> Self {
+        Self { text: Some(text), ..Default::default() }
+    }
+
+    /// Images and files go inline, anything fetched by Gemini has to be uploaded to it first
+    fn from_part(part: &ContentPart) -> Result<Self> {
+        let (mime_type, data) = match part {
+            ContentPart::Text(text) => return Ok(Part::text(text.clone())),
+            ContentPart::ImageUrl(url) => return Err(Error::InvalidRequest(format!("gemini can't fetch {url}, images must be sent as data"))),
+            ContentPart::Image { mime_type, data } | ContentPart::File { mime_type, data, .. } => (mime_type, data),
+        };
+        Ok(Self {
+            inline_data: Some(Blob { mime_type: mime_type.clone(), data: crate::content::base64(data) }),
+            ..Default::default()
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all="camelCase")]
+struct Blob {
+    mime_type: String,
+    data: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all="camelCase")]
+struct FunctionDeclaration {
+    name: String,
+    description: String,
+    parameters_json_schema: serde_json::Value,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all="camelCase")]
+struct ToolTx {
+    function_declarations: Vec<FunctionDeclaration>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Content {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<ContentRole>,
+    #[serde(default)]
+    parts: Vec<Part>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all="camelCase")]
+struct GenerationConfig {
+    max_output_tokens: u32,
+    candidate_count: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop_sequences: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking_config: Option<ThinkingConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_mime_type: Option<&'static str>,
+    /// Takes plain JSON Schema, unlike responseSchema which only knows a subset of OpenAPI
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_json_schema: Option<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all="camelCase")]
+struct ThinkingConfig {
+    thinking_budget: u32,
+    include_thoughts: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all="camelCase")]
+struct RequestBody {
+    contents: Vec<Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<Content>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<ToolTx>,
+    generation_config: GenerationConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all="SCREAMING_SNAKE_CASE")]
+enum FinishReason {
+    Stop,
+    MaxTokens,
+    Safety,
+    Recitation,
+    #[serde(other)]
+    Other,
+}
+
+impl From<FinishReason> for crate::FinishReason {
+    fn from(r: FinishReason) -> Self {
+        match r {
+            FinishReason::Stop => crate::FinishReason::Stop,
+            FinishReason::MaxTokens => crate::FinishReason::Length,
+            FinishReason::Safety | FinishReason::Recitation => crate::FinishReason::ContentFilter,
+            FinishReason::Other => crate::FinishReason::Other(String::from("other")),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all="camelCase")]
+#[allow(unused)]
+struct Candidate {
+    content: Option<Content>,
+    finish_reason: Option<FinishReason>,
+    index: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all="camelCase")]
+struct UsageMetadata {
+    #[serde(default)]
+    prompt_token_count: u64,
+    #[serde(default)]
+    candidates_token_count: u64,
+    #[serde(default)]
+    cached_content_token_count: u64,
+    #[serde(default)]
+    thoughts_token_count: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all="camelCase")]
+struct PromptFeedback {
+    block_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    message: String,
+    status: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ErrorBody,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all="camelCase")]
+#[allow(unused)]
+struct Response {
+    #[serde(default)]
+    candidates: Vec<Candidate>,
+    usage_metadata: Option<UsageMetadata>,
+    prompt_feedback: Option<PromptFeedback>,
+    model_version: Option<String>,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STREAM: &str = "data: {\"candidates\":[{\"content\":{\"parts\":[{\"text\":\"Bonjour \"}],\"role\":\"model\"},\"index\":0}],\"modelVersion\":\"gemini-2.5-flash\"}\r\n\r\n\
+data: {\"candidates\":[{\"content\":{\"parts\":[{\"text\":\"à toi\"}],\"role\":\"model\"},\"finishReason\":\"STOP\",\"index\":0}],\"modelVersion\":\"gemini-2.5-flash\"}\r\n\r\n";
+
+    #[test]
+    fn build_request() {
+        let api = ApiContext::new(String::from("gemini-2.5-flash"), String::from("key"));
+        let messages = vec![
+            Message::new(Role::Developer, String::from("be brief")),
+            Message::new(Role::User, String::from("hi")),
+            Message::new(Role::Assistant, String::from("Bonjour")),
+            Message::new(Role::User, String::from("again")),
+        ];
+        let req = api.build_request(messages, &[], &GenerationOptions::default()).unwrap();
+        assert_eq!(req.uri(), "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.5-flash:streamGenerateContent?alt=sse");
+        assert_eq!(req.headers()["x-goog-api-key"], "key");
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+        assert_eq!(body["systemInstruction"], serde_json::json!({"parts": [{"text": "be brief"}]}));
+        assert_eq!(body["contents"], serde_json::json!([
+            {"role": "user", "parts": [{"text": "hi"}]},
+            {"role": "model", "parts": [{"text": "Bonjour"}]},
+            {"role": "user", "parts": [{"text": "again"}]},
+        ]));
+    }
+
+    #[test]
+    fn build_request_skips_empty_answer() {
+        let api = ApiContext::new(String::from("gemini-2.5-flash"), String::from("key"));
+        let messages = vec![
+            Message::new(Role::User, String::from("hi")),
+            Message::new(Role::Assistant, String::new()),
+            Message::new(Role::User, String::from("again")),
+        ];
+        let req = api.build_request(messages, &[], &GenerationOptions::default()).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+        assert_eq!(body["contents"], serde_json::json!([{"role": "user", "parts": [{"text": "hi"}, {"text": "again"}]}]));
+    }
+
+    #[test]
+    fn build_response_split_at_every_offset() {
+        let api = ApiContext::new(String::from("gemini-2.5-flash"), String::from("key"));
+        let bytes = STREAM.as_bytes();
+        for i in 0..=bytes.len() {
+            let mut decoder = Decoder::new();
+            let (_, mut events) = api.build_response(&mut decoder, &bytes[..i]).unwrap();
+            events.extend(api.build_response(&mut decoder, &bytes[i..]).unwrap().1);
+
+            let text: String = events.iter()
+                .filter_map(|e| match e { LLMEvent::TextDelta(t) => Some(t.as_str()), _ => None })
+                .collect();
+            assert_eq!(text, "Bonjour à toi", "split at {i}");
+            assert_eq!(events.last(), Some(&LLMEvent::Finish { reason: crate::FinishReason::Stop }), "split at {i}");
+        }
+    }
+
+    #[test]
+    fn build_response_blocked() {
+        let api = ApiContext::new(String::from("gemini-2.5-flash"), String::from("key"));
+        let chunk = "data: {\"candidates\":[{\"finishReason\":\"SAFETY\",\"index\":0}]}\r\n\r\n";
+        let (_, events) = api.build_response(&mut Decoder::new(), chunk.as_bytes()).unwrap();
+        assert_eq!(events, vec![LLMEvent::Finish { reason: crate::FinishReason::ContentFilter }]);
+
+        let chunk = "data: {\"promptFeedback\":{\"blockReason\":\"PROHIBITED_CONTENT\"}}\r\n\r\n";
+        assert!(matches!(api.build_response(&mut Decoder::new(), chunk.as_bytes()), Err(Error::Refusal(_))));
+    }
+
+    #[test]
+    fn build_response_usage() {
+        let api = ApiContext::new(String::from("gemini-2.5-flash"), String::from("key"));
+        let chunk = "data: {\"candidates\":[],\"usageMetadata\":{\"promptTokenCount\":1200,\"candidatesTokenCount\":300,\"cachedContentTokenCount\":1024,\"thoughtsTokenCount\":256}}\r\n\r\n";
+        let (_, events) = api.build_response(&mut Decoder::new(), chunk.as_bytes()).unwrap();
+        assert_eq!(events, vec![LLMEvent::Usage(crate::Usage {
+            prompt_tokens: 1200,
+            completion_tokens: 556,
+            cached_tokens: 1024,
+            reasoning_tokens: 256,
+        })]);
+    }
+
+    #[test]
+    fn build_error() {
+        let api = ApiContext::new(String::from("gemini-2.5-flash"), String::from("key"));
+        let body = br#"[{"error": {"code": 400, "message": "API key not valid. Please pass a valid API key.", "status": "INVALID_ARGUMENT"}}]"#;
+        let Error::Provider { status, kind, message } = api.build_error(400, body) else { panic!("expected a provider error"); };
+        assert_eq!(status, Some(400));
+        assert_eq!(kind.as_deref(), Some("INVALID_ARGUMENT"));
+        assert_eq!(message, "API key not valid. Please pass a valid API key.");
+        assert!(matches!(api.build_error(429, br#"{"error": {"code": 429, "message": "Quota exceeded", "status": "RESOURCE_EXHAUSTED"}}"#), Error::RateLimit { .. }));
+    }
+
+    const TOOL_CALL_STREAM: &str = "data: {\"candidates\":[{\"content\":{\"parts\":[{\"text\":\"Need the file\",\"thought\":true}],\"role\":\"model\"},\"index\":0}],\"modelVersion\":\"gemini-2.5-flash\"}\r\n\r\n\
+data: {\"candidates\":[{\"content\":{\"parts\":[{\"text\":\"Let me look\"}],\"role\":\"model\"},\"index\":0}],\"modelVersion\":\"gemini-2.5-flash\"}\r\n\r\n\
+data: {\"candidates\":[{\"content\":{\"parts\":[{\"functionCall\":{\"name\":\"read_file\",\"args\":{\"path\":\"a.txt\"}}}],\"role\":\"model\"},\"finishReason\":\"STOP\",\"index\":0}],\
+\"usageMetadata\":{\"promptTokenCount\":40,\"candidatesTokenCount\":12,\"thoughtsTokenCount\":8},\"modelVersion\":\"gemini-2.5-flash\"}\r\n\r\n";
+
+    #[test]
+    fn round_trip() {
+        let api = ApiContext::new(String::from("gemini-2.5-flash"), String::from("key"));
+        let tools = [Tool::new("read_file", "Reads a file", serde_json::json!({"type": "object"}))];
+        let mut messages = vec![
+            Message::new(Role::Developer, String::from("be brief")),
+            Message::new(Role::User, String::from("what's in a.txt?")),
+        ];
+        let req = api.build_request(messages.clone(), &tools, &GenerationOptions::default()).unwrap();
+        assert_eq!(req.uri(), "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.5-flash:streamGenerateContent?alt=sse");
+        assert_eq!(req.headers()["x-goog-api-key"], "key");
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+        assert_eq!(body["systemInstruction"], serde_json::json!({"parts": [{"text": "be brief"}]}));
+        assert_eq!(body["tools"], serde_json::json!([{"functionDeclarations": [{"name": "read_file", "description": "Reads a file", "parametersJsonSchema": {"type": "object"}}]}]));
+        assert_eq!(body["contents"], serde_json::json!([{"role": "user", "parts": [{"text": "what's in a.txt?"}]}]));
+
+        let (_, events) = api.build_response(&mut Decoder::new(), TOOL_CALL_STREAM.as_bytes()).unwrap();
+        assert_eq!(events[0], LLMEvent::ReasoningDelta(String::from("Need the file")));
+        assert_eq!(events[events.len() - 2], LLMEvent::Finish { reason: crate::FinishReason::ToolCalls });
+        assert_eq!(events[events.len() - 1], LLMEvent::Usage(crate::Usage { prompt_tokens: 40, completion_tokens: 20, reasoning_tokens: 8, ..Default::default() }));
+
+        let answer = crate::tools::answer_message(&events);
+        assert_eq!(answer.tool_calls.len(), 1);
+        let id = answer.tool_calls[0].id.clone();
+        messages.push(answer);
+        messages.push(Message::tool_result(id, String::from("hello")));
+
+        let req = api.build_request(messages, &tools, &GenerationOptions::default()).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+        assert_eq!(body["contents"][1], serde_json::json!({"role": "model", "parts": [
+            {"text": "Let me look"},
+            {"functionCall": {"name": "read_file", "args": {"path": "a.txt"}}},
+        ]}));
+        // results are matched to calls by the name of the function and must be objects
+        assert_eq!(body["contents"][2], serde_json::json!({"role": "user", "parts": [
+            {"functionResponse": {"name": "read_file", "response": {"result": "hello"}}},
+        ]}));
+    }
+}
@@ -0,0 +1,18 @@
+pub mod generate_content_api;
+
+use crate::models::{ModelInfo, Pricing};
+
+pub struct Models;
+#[allow(non_upper_case_globals)]
+impl Models {
+    pub const Gemini_2_5_Flash: &'static str = "gemini-2.5-flash";
+    pub const Gemini_2_5_Pro: &'static str = "gemini-2.5-pro";
+    pub const Gemini_2_0_Flash: &'static str = "gemini-2.0-flash";
+
+    /// Gemini 2.5 Pro costs more past 200k prompt tokens, this is the price below
+    pub const ALL: &'static [ModelInfo] = &[
+        ModelInfo { name: Self::Gemini_2_5_Flash, alias: None, pricing: Pricing::new(0.30, 2.50, 0.075), reasoning: true },
+        ModelInfo { name: Self::Gemini_2_5_Pro, alias: None, pricing: Pricing::new(1.25, 10.0, 0.31), reasoning: true },
+        ModelInfo { name: Self::Gemini_2_0_Flash, alias: None, pricing: Pricing::new(0.10, 0.40, 0.025), reasoning: false },
+    ];
+}
@@ -1,4 +1,18 @@
 pub mod openai;
+pub mod anthropic;
+pub mod ollama;
+pub mod gemini;
+pub mod stream;
+pub mod models;
+pub mod tools;
+pub mod options;
+mod content;
+mod error;
+
+pub use error::{Error, Result};
+pub use content::{ContentPart, image_mime_type};
+pub use tools::{Tool, ToolCall, ToolCallAccumulator};
+pub use options::{GenerationOptions, JsonSchema, ReasoningEffort};
 
 use serde::{Serialize, Deserialize};
 use http::Request;
@@ -7,15 +21,134 @@ use std::sync::Arc;
 pub struct Defaults;
 impl Defaults {
     const MAX_COMPLETION_TOKENS: u32 = 4096;
+    /// Leaves room for the reasoning, which counts as completion tokens
+    const MAX_REASONING_COMPLETION_TOKENS: u32 = 25_000;
     const NUM_GENS: u32 = 1;
 }
 
-#[derive(Serialize, Deserialize, Hash, Eq, PartialEq)]
+#[derive(Clone, Debug)]
 pub enum Provider {
     OpenAi,
+    Anthropic,
+    Gemini,
+    Ollama { host: String },
+    /// OpenAI models served from an Azure deployment
+    Azure {
+        resource: String,
+        deployment: String,
+        api_version: String,
+    },
+    /// Any server implementing OpenAI's chat completion API
+    OpenAiCompatible {
+        base_url: String,
+        headers: Vec<(String, String)>,
+        key: Option<String>,
+        /// Whether the server takes `stream_options` to report the usage, only chat completions look at it
+        stream_usage: bool,
+    },
+}
+
+impl Provider {
+    /// The model used when none has been explicitly picked.
+    /// There is no way to guess what an arbitrary compatible server or deployment is serving.
+    pub fn default_model(&self) -> Option<&'static str> {
+        match self {
+            Provider::OpenAi => Some(openai::Models::GPT_4_1_Mini),
+            Provider::Anthropic => Some(anthropic::Models::Claude_Sonnet_4),
+            Provider::Gemini => Some(gemini::Models::Gemini_2_5_Flash),
+            Provider::Ollama { .. } => Some(ollama::Models::Llama_3_2),
+            Provider::Azure { .. } | Provider::OpenAiCompatible { .. } => None,
+        }
+    }
+
+    /// Local providers can be reached without any credentials, compatible servers carry their own
+    pub fn requires_key(&self) -> bool {
+        matches!(self, Provider::OpenAi | Provider::Anthropic | Provider::Gemini | Provider::Azure { .. })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FinishReason {
+    Stop,
+    /// Ran out of tokens
+    Length,
+    ContentFilter,
+    ToolCalls,
+    Refusal,
+    Other(String),
+}
+
+/// Tokens spent by a request. Cached tokens are part of the prompt tokens and reasoning
+/// tokens part of the completion tokens, they are only broken out because they are billed differently.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cached_tokens: u64,
+    pub reasoning_tokens: u64,
+}
+
+impl Usage {
+    /// Usage can be reported several times during a stream and some providers split it across
+    /// events. Counts are cumulative so keeping the highest of each is always right.
+    pub fn merge(&mut self, other: &Usage) {
+        self.prompt_tokens = self.prompt_tokens.max(other.prompt_tokens);
+        self.completion_tokens = self.completion_tokens.max(other.completion_tokens);
+        self.cached_tokens = self.cached_tokens.max(other.cached_tokens);
+        self.reasoning_tokens = self.reasoning_tokens.max(other.reasoning_tokens);
+    }
+
+    /// Sums the usage of separate requests
+    pub fn add(&mut self, other: &Usage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.cached_tokens += other.cached_tokens;
+        self.reasoning_tokens += other.reasoning_tokens;
+    }
+
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total_tokens() == 0
+    }
 }
 
-pub struct LLMResponse(pub String);
+/// A web source the answer relies on, indices are in bytes of the answer text when known
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    pub title: String,
+    pub url: String,
+    pub start_index: Option<usize>,
+    pub end_index: Option<usize>,
+}
+
+/// Everything a provider can stream back, in order of arrival
+#[derive(Debug, Clone, PartialEq)]
+pub enum LLMEvent {
+    TextDelta(String),
+    RefusalDelta(String),
+    /// What a reasoning model thinks before answering, or a summary of it, not part of the answer
+    ReasoningDelta(String),
+    /// Proves the reasoning wasn't tampered with, some providers want it back along with tool results
+    ReasoningSignature(String),
+    /// Reasoning the provider encrypted rather than show it, only good to be sent back along with tool results
+    RedactedReasoning(String),
+    /// Pieces of a tool call, `id` and `name` only come with the first piece of each call
+    ToolCallDelta {
+        index: usize,
+        id: Option<String>,
+        name: Option<String>,
+        arguments: String,
+    },
+    Annotation(Annotation),
+    Usage(Usage),
+    Finish {
+        reason: FinishReason,
+    },
+}
 
 // A thin wrapper over provider specific API contexts
 // I find it more convenient to have a vtable over passing generic arguments and pollute other
@@ -26,58 +159,173 @@ pub struct LLMContext {
 }
 
 impl LLMContext {
-    pub fn new(provider: Provider, model: String, key: String) -> Self {
+    /// OpenAI models go through the API that suits them best, see `openai::Api::for_model`
+    pub fn new(provider: Provider, model: String, key: Option<String>) -> Self {
+        let openai_api = openai::Api::for_model(&model);
+        Self::with_openai_api(provider, model, key, openai_api)
+    }
+
+    /// `openai_api` is only looked at for OpenAI and compatible servers, azure deployments are served by chat completions
+    pub fn with_openai_api(provider: Provider, model: String, key: Option<String>, openai_api: openai::Api) -> Self {
+        match (provider, openai_api) {
+            (Provider::OpenAi, openai::Api::Responses { builtin_tools, store }) => {
+                let api = Arc::new(openai::responses_api::ApiContext::new(model, key.unwrap_or_default(), builtin_tools).with_store(store));
+                Self { api }
+            },
+            (Provider::OpenAiCompatible { base_url, headers, key: endpoint_key, .. }, openai::Api::Responses { builtin_tools, store }) => {
+                let api = Arc::new(openai::responses_api::ApiContext::with_base_url(model, key.or(endpoint_key), base_url, headers, builtin_tools).with_store(store));
+                Self { api }
+            },
+            (provider, _) => Self::with_default_api(provider, model, key),
+        }
+    }
+
+    fn with_default_api(provider: Provider, model: String, key: Option<String>) -> Self {
         match provider {
             Provider::OpenAi => {
-                let api = Arc::new(openai::chat_completion_api::ApiContext::new(model, key));
+                let api = Arc::new(openai::chat_completion_api::ApiContext::new(model, key.unwrap_or_default()));
+                Self { api }
+            },
+            Provider::Anthropic => {
+                let api = Arc::new(anthropic::messages_api::ApiContext::new(model, key.unwrap_or_default()));
+                Self { api }
+            },
+            Provider::Gemini => {
+                let api = Arc::new(gemini::generate_content_api::ApiContext::new(model, key.unwrap_or_default()));
                 Self { api }
-            }        
+            },
+            Provider::Ollama { host } => {
+                let api = Arc::new(ollama::chat_api::ApiContext::new(model, host));
+                Self { api }
+            },
+            Provider::Azure { resource, deployment, api_version } => {
+                let api = Arc::new(openai::chat_completion_api::ApiContext::azure(model, key.unwrap_or_default(), &resource, &deployment, &api_version));
+                Self { api }
+            },
+            Provider::OpenAiCompatible { base_url, headers, key: endpoint_key, stream_usage } => {
+                let api = Arc::new(openai::chat_completion_api::ApiContext::with_base_url(model, key.or(endpoint_key), base_url, headers).with_stream_usage(stream_usage));
+                Self { api }
+            },
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
 #[serde(rename_all="snake_case")]
-pub enum Role {Assistant, User, Developer}
+pub enum Role {Assistant, User, Developer, Tool}
 
-#[derive(Serialize)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Message {
     pub role: Role,
-    pub content: String,
+    /// Empty for an assistant message made only of tool calls
+    pub content: Vec<ContentPart>,
+    /// Calls the assistant asked for in this message
+    pub tool_calls: Vec<ToolCall>,
+    /// For `Role::Tool` messages, the call this is the result of
+    pub tool_call_id: Option<String>,
+    /// What the assistant thought before making its tool calls
+    pub reasoning: Option<Reasoning>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Reasoning {
+    pub text: String,
+    pub signature: Option<String>,
+    /// Encrypted pieces of reasoning, in the order they came
+    pub redacted: Vec<String>,
+}
+
+impl Reasoning {
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty() && self.signature.is_none() && self.redacted.is_empty()
+    }
 }
 
 impl Message {
+    pub fn new(role: Role, content: String) -> Self {
+        let content = if content.is_empty() { Vec::new() } else { vec![ContentPart::Text(content)] };
+        Self::with_parts(role, content)
+    }
+
+    pub fn with_parts(role: Role, content: Vec<ContentPart>) -> Self {
+        Self {
+            role,
+            content,
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+            reasoning: None,
+        }
+    }
+
+    /// An assistant turn that requested tool calls, it must be sent back along with their results
+    pub fn with_tool_calls(content: String, tool_calls: Vec<ToolCall>) -> Self {
+        Self {
+            tool_calls,
+            ..Self::new(Role::Assistant, content)
+        }
+    }
+
+    pub fn with_reasoning(mut self, reasoning: Reasoning) -> Self {
+        self.reasoning = Some(reasoning);
+        self
+    }
+
+    pub fn tool_result(tool_call_id: String, content: String) -> Self {
+        Self {
+            tool_call_id: Some(tool_call_id),
+            ..Self::new(Role::Tool, content)
+        }
+    }
+
     // Returns a vec of a single message intended for
     // an initial request. 
     pub fn new_user_request(content: String) -> Vec<Self> {
-        vec![Message {
-            role: Role::User,
-            content,
-        }]
+        vec![Message::new(Role::User, content)]
     } 
 
-    pub fn from_history(content: &Vec<(Role, String)>) -> Vec<Self> {
+    pub fn from_history(content: &[(Role, String)]) -> Vec<Self> {
         content.iter()
-            .map(|(r, s)| {
-                Message {
-                    role: r.clone(),
-                    content: s.clone(),
-                }
-            })
+            .map(|(r, s)| Message::new(r.clone(), s.clone()))
             .collect()
     }
+
+    /// The text parts joined, for whatever can't carry anything else
+    pub fn text(&self) -> String {
+        self.content.iter().filter_map(ContentPart::text).collect()
+    }
+
+    /// Only text, which most providers accept as a plain string
+    pub(crate) fn is_text(&self) -> bool {
+        self.content.iter().all(|p| p.text().is_some())
+    }
+
+    /// Name of the function that produced this tool result, some providers want it instead of the id
+    pub(crate) fn tool_name<'a>(&self, messages: &'a [Message]) -> Option<&'a str> {
+        let id = self.tool_call_id.as_deref()?;
+        messages.iter()
+            .flat_map(|m| m.tool_calls.iter())
+            .find(|c| c.id == id)
+            .map(|c| c.name.as_str())
+    }
 }
 
 pub trait LLMApi {
-    fn build_request(&self, messages: Vec<Message>) -> Request<Vec<u8>>; 
-    fn build_response(&self, data: &[u8]) -> (usize, LLMResponse);
+    /// `tools` are the functions the model is allowed to call, it answers with text only if empty
+    fn build_request(&self, messages: Vec<Message>, tools: &[Tool], options: &GenerationOptions) -> Result<Request<Vec<u8>>>;
+    /// `decoder` holds whatever was left incomplete by previous chunks of the same response
+    fn build_response(&self, decoder: &mut stream::Decoder, data: &[u8]) -> Result<(usize, Vec<LLMEvent>)>;
+    /// Turns the body of a non success response into something meaningful
+    fn build_error(&self, status: u32, body: &[u8]) -> Error;
 }
 
 impl LLMApi for LLMContext {
-   fn build_request(&self, messages: Vec<Message>) -> Request<Vec<u8>> {
-        self.api.build_request(messages)
-    } 
-    fn build_response(&self, data: &[u8]) -> (usize, LLMResponse) {
-        self.api.build_response(data)
+    fn build_request(&self, messages: Vec<Message>, tools: &[Tool], options: &GenerationOptions) -> Result<Request<Vec<u8>>> {
+        self.api.build_request(messages, tools, options)
+    }
+    fn build_response(&self, decoder: &mut stream::Decoder, data: &[u8]) -> Result<(usize, Vec<LLMEvent>)> {
+        self.api.build_response(decoder, data)
+    }
+    fn build_error(&self, status: u32, body: &[u8]) -> Error {
+        self.api.build_error(status, body)
     }
 }
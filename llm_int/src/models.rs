@@ -0,0 +1,69 @@
+use serde::{Serialize, Deserialize};
+use crate::{Usage, openai, anthropic, gemini, ollama};
+
+/// Dollars per million tokens
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Pricing {
+    pub input: f64,
+    pub output: f64,
+    /// Prompt tokens read from the provider's cache
+    pub cached: f64,
+}
+
+impl Pricing {
+    pub const FREE: Pricing = Pricing { input: 0.0, output: 0.0, cached: 0.0 };
+
+    pub const fn new(input: f64, output: f64, cached: f64) -> Self {
+        Self { input, output, cached }
+    }
+
+    /// Cost in dollars, reasoning tokens are billed as output and already counted in completion tokens
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        let uncached = usage.prompt_tokens.saturating_sub(usage.cached_tokens);
+        (uncached as f64 * self.input
+            + usage.cached_tokens as f64 * self.cached
+            + usage.completion_tokens as f64 * self.output) / 1_000_000.0
+    }
+
+    /// Cost in dollars of sending `tokens` uncached prompt tokens
+    pub fn prompt_cost(&self, tokens: u64) -> f64 {
+        tokens as f64 * self.input / 1_000_000.0
+    }
+}
+
+pub struct ModelInfo {
+    pub name: &'static str,
+    /// Name that always points to the latest snapshot of the model
+    pub alias: Option<&'static str>,
+    pub pricing: Pricing,
+    /// Thinks before answering, OpenAI's reasoning models reject the sampling parameters
+    pub reasoning: bool,
+}
+
+/// Every model we know the price of, across all providers
+pub fn all() -> impl Iterator<Item = &'static ModelInfo> {
+    openai::Models::ALL.iter()
+        .chain(anthropic::Models::ALL)
+        .chain(gemini::Models::ALL)
+        .chain(ollama::Models::ALL)
+}
+
+pub fn find(model: &str) -> Option<&'static ModelInfo> {
+    all().find(|m| m.name == model || m.alias == Some(model))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_and_cost() {
+        let info = find("gpt-4.1-mini").unwrap();
+        assert_eq!(info.name, openai::Models::GPT_4_1_Mini);
+        assert!(find("gpt-4.1").is_none());
+
+        let usage = Usage { prompt_tokens: 1_000_000, completion_tokens: 500_000, cached_tokens: 200_000, reasoning_tokens: 0 };
+        let cost = info.pricing.cost(&usage);
+        assert!((cost - (0.8 * 0.40 + 0.2 * 0.10 + 0.5 * 1.60)).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,363 @@
+use serde::{Deserialize, Serialize};
+use crate::{LLMApi, LLMEvent, ContentPart, GenerationOptions, Message, Role, Tool, Error, Result};
+use crate::stream::Decoder;
+use http::Request;
+
+pub struct ApiContext {
+    model: String,
+    host: String,
+}
+
+impl ApiContext {
+    pub fn new(model: String, host: String) -> Self {
+        Self {
+            model,
+            host
+        }
+    }
+}
+
+impl LLMApi for ApiContext {
+    fn build_request(&self, messages: Vec<Message>, tools: &[Tool], options: &GenerationOptions) -> Result<Request<Vec<u8>>> {
+        // Ollama only runs local models, which are given nothing but text and inline images
+        let unsupported = messages.iter()
+            .flat_map(|m| m.content.iter())
+            .find(|p| matches!(p, ContentPart::ImageUrl(_) | ContentPart::File { .. }));
+        match unsupported {
+            Some(ContentPart::ImageUrl(url)) => return Err(Error::InvalidRequest(format!("ollama can't fetch {url}, images must be sent as data"))),
+            Some(ContentPart::File { name, .. }) => return Err(Error::InvalidRequest(format!("ollama doesn't take files such as {name}"))),
+            _ => (),
+        }
+
+        let messages_tx = messages.iter()
+            .map(|m| MessageTx {
+                role: match m.role {
+                    Role::Developer => MessageRole::System,
+                    Role::User => MessageRole::User,
+                    Role::Assistant => MessageRole::Assistant,
+                    Role::Tool => MessageRole::Tool,
+                },
+                content: m.text(),
+                images: m.content.iter()
+                    .filter_map(|p| match p {
+                        ContentPart::Image { data, .. } => Some(crate::content::base64(data)),
+                        _ => None,
+                    })
+                    .collect(),
+                tool_calls: m.tool_calls.iter()
+                    .map(|c| ToolCallTx {
+                        function: FunctionTx {
+                            name: c.name.clone(),
+                            arguments: serde_json::from_str(&c.arguments).unwrap_or(serde_json::json!({})),
+                        },
+                    })
+                    .collect(),
+                tool_name: m.tool_name(&messages).map(String::from),
+            })
+            .collect();
+
+        let body = RequestBody {
+            model: self.model.clone(),
+            messages: messages_tx,
+            tools: tools.iter()
+                .map(|t| ToolTx {
+                    tool_type: "function",
+                    function: FunctionDefinition {
+                        name: t.name.clone(),
+                        description: t.description.clone(),
+                        parameters: t.parameters.clone(),
+                    },
+                })
+                .collect(),
+            stream: true,
+            // thinking models only have an on/off switch
+            think: options.reasoning_effort.map(|_| true),
+            format: options.response_format.as_ref().map(|f| f.schema.clone()),
+            options: Options {
+                num_predict: options.max_tokens(),
+                temperature: options.temperature,
+                top_p: options.top_p,
+                stop: options.stop.clone(),
+                seed: options.seed,
+                presence_penalty: options.presence_penalty,
+                frequency_penalty: options.frequency_penalty,
+            },
+        };
+
+        Ok(Request::post(format!("{}/api/chat", self.host.trim_end_matches('/')))
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_vec(&body).map_err(|e| Error::InvalidRequest(e.to_string()))?)?)
+    }
+
+    fn build_response(&self, decoder: &mut Decoder, data: &[u8]) -> Result<(usize, Vec<LLMEvent>)> {
+        // The stream is newline delimited JSON, one whole object per line
+        let mut events = Vec::new();
+        for line in decoder.lines(data) {
+            if line.trim().is_empty() { continue; }
+
+            let chunk: Response = serde_json::from_str(line.as_str())?;
+            if let Some(error) = chunk.error {
+                return Err(Error::Provider { status: None, kind: None, message: error });
+            }
+            if let Some(message) = chunk.message {
+                if !message.thinking.is_empty() {
+                    events.push(LLMEvent::ReasoningDelta(message.thinking));
+                }
+                if !message.content.is_empty() {
+                    events.push(LLMEvent::TextDelta(message.content));
+                }
+                // calls come whole, arguments already parsed
+                for call in message.tool_calls.unwrap_or_default() {
+                    events.push(LLMEvent::ToolCallDelta {
+                        index: decoder.next_index(),
+                        id: None,
+                        name: Some(call.function.name),
+                        arguments: call.function.arguments.to_string(),
+                    });
+                }
+            }
+
+            if chunk.done {
+                events.push(LLMEvent::Usage(crate::Usage {
+                    prompt_tokens: chunk.prompt_eval_count.unwrap_or_default(),
+                    completion_tokens: chunk.eval_count.unwrap_or_default(),
+                    ..Default::default()
+                }));
+                let reason = match chunk.done_reason.as_deref() {
+                    Some("stop") | None => crate::FinishReason::Stop,
+                    Some("length") => crate::FinishReason::Length,
+                    Some(other) => crate::FinishReason::Other(other.to_string()),
+                };
+                events.push(LLMEvent::Finish { reason });
+            }
+        }
+
+        Ok((data.len(), events))
+    }
+
+    fn build_error(&self, status: u32, body: &[u8]) -> Error {
+        match serde_json::from_slice::<ErrorResponse>(body) {
+            Ok(ErrorResponse { error }) => Error::from_status(status, None, Some(error)),
+            Err(_) => Error::from_status(status, None, None),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all="snake_case")]
+enum MessageRole {System, User, Assistant, Tool}
+
+#[derive(Serialize, Deserialize)]
+struct FunctionTx {
+    name: String,
+    arguments: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ToolCallTx {
+    function: FunctionTx,
+}
+
+#[derive(Serialize)]
+struct MessageTx {
+    role: MessageRole,
+    content: String,
+    /// Base64 without the data URL prefix
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    images: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<ToolCallTx>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_name: Option<String>,
+}
+
+#[derive(Serialize)]
+struct FunctionDefinition {
+    name: String,
+    description: String,
+    parameters: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct ToolTx {
+    #[serde(rename = "type")]
+    tool_type: &'static str,
+    function: FunctionDefinition,
+}
+
+#[derive(Serialize)]
+struct Options {
+    num_predict: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+}
+
+#[derive(Serialize)]
+struct RequestBody {
+    model: String,
+    messages: Vec<MessageTx>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<ToolTx>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    think: Option<bool>,
+    /// Either "json" or a JSON Schema the answer is constrained to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<serde_json::Value>,
+    options: Options,
+}
+
+#[derive(Deserialize)]
+#[allow(unused)]
+struct MessageRx {
+    role: MessageRole,
+    #[serde(default)]
+    content: String,
+    /// Only sent by thinking models when asked to think
+    #[serde(default)]
+    thinking: String,
+    tool_calls: Option<Vec<ToolCallTx>>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+#[derive(Deserialize)]
+#[allow(unused)]
+struct Response {
+    /// Errors happening mid stream are reported in place of a chunk
+    error: Option<String>,
+    #[serde(default)]
+    model: String,
+    #[serde(default)]
+    created_at: String,
+    message: Option<MessageRx>,
+    #[serde(default)]
+    done: bool,
+    done_reason: Option<String>,
+    prompt_eval_count: Option<u64>,
+    eval_count: Option<u64>,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STREAM: &str = "{\"model\":\"llama3.2\",\"created_at\":\"2025-06-01T10:00:00Z\",\"message\":{\"role\":\"assistant\",\"content\":\"Bonjour \"},\"done\":false}\n\
+{\"model\":\"llama3.2\",\"created_at\":\"2025-06-01T10:00:01Z\",\"message\":{\"role\":\"assistant\",\"content\":\"à toi\"},\"done\":false}\n\
+{\"model\":\"llama3.2\",\"created_at\":\"2025-06-01T10:00:02Z\",\"message\":{\"role\":\"assistant\",\"content\":\"\"},\"done\":true,\"done_reason\":\"stop\",\"prompt_eval_count\":40,\"eval_count\":12}\n";
+
+    #[test]
+    fn build_request() {
+        let api = ApiContext::new(String::from("llama3.2"), String::from("http://localhost:11434/"));
+        let messages = vec![
+            Message::new(Role::Developer, String::from("be brief")),
+            Message::new(Role::User, String::from("hi")),
+        ];
+        let req = api.build_request(messages, &[], &GenerationOptions::default()).unwrap();
+        assert_eq!(req.uri(), "http://localhost:11434/api/chat");
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+        assert_eq!(body["stream"], true);
+        assert_eq!(body["messages"], serde_json::json!([
+            {"role": "system", "content": "be brief"},
+            {"role": "user", "content": "hi"},
+        ]));
+    }
+
+    #[test]
+    fn build_response_split_at_every_offset() {
+        let api = ApiContext::new(String::from("llama3.2"), String::from("http://localhost:11434"));
+        let bytes = STREAM.as_bytes();
+        for i in 0..=bytes.len() {
+            let mut decoder = Decoder::new();
+            let (_, mut events) = api.build_response(&mut decoder, &bytes[..i]).unwrap();
+            events.extend(api.build_response(&mut decoder, &bytes[i..]).unwrap().1);
+
+            let text: String = events.iter()
+                .filter_map(|e| match e { LLMEvent::TextDelta(t) => Some(t.as_str()), _ => None })
+                .collect();
+            assert_eq!(text, "Bonjour à toi", "split at {i}");
+            assert_eq!(events.last(), Some(&LLMEvent::Finish { reason: crate::FinishReason::Stop }), "split at {i}");
+        }
+    }
+
+    #[test]
+    fn build_response_error() {
+        let api = ApiContext::new(String::from("llama3.2"), String::from("http://localhost:11434"));
+        let res = api.build_response(&mut Decoder::new(), b"{\"error\":\"model runner has unexpectedly stopped\"}\n");
+        assert!(matches!(res, Err(Error::Provider { message, .. }) if message == "model runner has unexpectedly stopped"));
+    }
+
+    #[test]
+    fn build_response_usage() {
+        let api = ApiContext::new(String::from("llama3.2"), String::from("http://localhost:11434"));
+        let (_, events) = api.build_response(&mut Decoder::new(), STREAM.as_bytes()).unwrap();
+        assert!(events.contains(&LLMEvent::Usage(crate::Usage { prompt_tokens: 40, completion_tokens: 12, ..Default::default() })));
+    }
+
+    #[test]
+    fn build_error() {
+        let api = ApiContext::new(String::from("llama3.2"), String::from("http://localhost:11434"));
+        let Error::Provider { status, message, .. } = api.build_error(404, br#"{"error":"model \"llama9\" not found, try pulling it first"}"#) else { panic!("expected a provider error"); };
+        assert_eq!(status, Some(404));
+        assert_eq!(message, "model \"llama9\" not found, try pulling it first");
+        assert!(matches!(api.build_error(502, b"Bad gateway"), Error::HttpStatus(502)));
+    }
+
+    const TOOL_CALL_STREAM: &str = "{\"model\":\"qwen3\",\"created_at\":\"2025-06-01T10:00:00Z\",\"message\":{\"role\":\"assistant\",\"content\":\"\",\"thinking\":\"Need the file\"},\"done\":false}\n\
+{\"model\":\"qwen3\",\"created_at\":\"2025-06-01T10:00:01Z\",\"message\":{\"role\":\"assistant\",\"content\":\"Let me look\"},\"done\":false}\n\
+{\"model\":\"qwen3\",\"created_at\":\"2025-06-01T10:00:02Z\",\"message\":{\"role\":\"assistant\",\"content\":\"\",\"tool_calls\":[{\"function\":{\"name\":\"read_file\",\"arguments\":{\"path\":\"a.txt\"}}}]},\"done\":false}\n\
+{\"model\":\"qwen3\",\"created_at\":\"2025-06-01T10:00:03Z\",\"message\":{\"role\":\"assistant\",\"content\":\"\"},\"done\":true,\"done_reason\":\"stop\",\"prompt_eval_count\":40,\"eval_count\":12}\n";
+
+    #[test]
+    fn round_trip() {
+        let api = ApiContext::new(String::from("qwen3"), String::from("http://localhost:11434/"));
+        let tools = [Tool::new("read_file", "Reads a file", serde_json::json!({"type": "object"}))];
+        let options = GenerationOptions { reasoning_effort: Some(crate::ReasoningEffort::Low), ..Default::default() };
+        let mut messages = vec![
+            Message::new(Role::Developer, String::from("be brief")),
+            Message::new(Role::User, String::from("what's in a.txt?")),
+        ];
+        let req = api.build_request(messages.clone(), &tools, &options).unwrap();
+        assert_eq!(req.uri(), "http://localhost:11434/api/chat");
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+        assert_eq!(body["think"], true);
+        assert_eq!(body["tools"][0], serde_json::json!({"type": "function", "function": {"name": "read_file", "description": "Reads a file", "parameters": {"type": "object"}}}));
+        assert_eq!(body["messages"], serde_json::json!([
+            {"role": "system", "content": "be brief"},
+            {"role": "user", "content": "what's in a.txt?"},
+        ]));
+
+        let (_, events) = api.build_response(&mut Decoder::new(), TOOL_CALL_STREAM.as_bytes()).unwrap();
+        assert_eq!(events[0], LLMEvent::ReasoningDelta(String::from("Need the file")));
+        assert_eq!(events[events.len() - 2], LLMEvent::Usage(crate::Usage { prompt_tokens: 40, completion_tokens: 12, ..Default::default() }));
+        assert_eq!(events[events.len() - 1], LLMEvent::Finish { reason: crate::FinishReason::Stop });
+
+        let answer = crate::tools::answer_message(&events);
+        assert_eq!(answer.tool_calls.len(), 1);
+        let id = answer.tool_calls[0].id.clone();
+        messages.push(answer);
+        messages.push(Message::tool_result(id, String::from("hello")));
+
+        let req = api.build_request(messages, &tools, &options).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+        assert_eq!(body["messages"][2], serde_json::json!({"role": "assistant", "content": "Let me look", "tool_calls": [
+            {"function": {"name": "read_file", "arguments": {"path": "a.txt"}}},
+        ]}));
+        // results are matched to calls by the name of the tool
+        assert_eq!(body["messages"][3], serde_json::json!({"role": "tool", "content": "hello", "tool_name": "read_file"}));
+    }
+}
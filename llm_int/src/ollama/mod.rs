@@ -0,0 +1,20 @@
+pub mod chat_api;
+
+use crate::models::{ModelInfo, Pricing};
+
+pub const DEFAULT_HOST: &str = "http://localhost:11434";
+
+/// Ollama serves whatever has been pulled locally, these are only sensible defaults
+pub struct Models;
+#[allow(non_upper_case_globals)]
+impl Models {
+    pub const Llama_3_2: &'static str = "llama3.2";
+    pub const Qwen_2_5: &'static str = "qwen2.5";
+    pub const Mistral: &'static str = "mistral";
+
+    pub const ALL: &'static [ModelInfo] = &[
+        ModelInfo { name: Self::Llama_3_2, alias: None, pricing: Pricing::FREE, reasoning: false },
+        ModelInfo { name: Self::Qwen_2_5, alias: None, pricing: Pricing::FREE, reasoning: false },
+        ModelInfo { name: Self::Mistral, alias: None, pricing: Pricing::FREE, reasoning: false },
+    ];
+}
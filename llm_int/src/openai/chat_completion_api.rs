@@ -1,74 +1,194 @@
 use serde::{Deserialize, Serialize};
-use crate::{LLMApi, LLMResponse, Defaults, Message, Role};
+use crate::{LLMApi, LLMEvent, ContentPart, Defaults, GenerationOptions, Message, Role, Tool, Error, Result};
+use crate::stream::Decoder;
 use http::Request;
 
+const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
 pub struct ApiContext {
     model: String,
-    key: String,
+    url: String,
+    /// Extra headers including authentication, which differs between flavours
+    headers: Vec<(String, String)>,
+    /// Ask for the usage at the end of the stream, some compatible servers reject the option
+    stream_usage: bool,
+    /// Send instructions with the developer role, which only OpenAI itself knows about
+    developer_role: bool,
 }
 
 impl ApiContext {
     pub fn new(model: String, key: String) -> Self {
+        Self {
+            developer_role: true,
+            ..Self::with_base_url(model, Some(key), OPENAI_BASE_URL.to_string(), Vec::new()).with_stream_usage(true)
+        }
+    }
+
+    /// For servers speaking the same wire format as OpenAI (vLLM, llama.cpp, OpenRouter...).
+    /// `base_url` is expected to include the version segment, ie: `http://localhost:8000/v1`
+    pub fn with_base_url(model: String, key: Option<String>, base_url: String, mut headers: Vec<(String, String)>) -> Self {
+        if let Some(key) = key {
+            headers.push((String::from("Authorization"), format!("Bearer {key}")));
+        }
+
         Self {
             model,
-            key
+            url: format!("{}/chat/completions", base_url.trim_end_matches('/')),
+            headers,
+            stream_usage: false,
+            developer_role: false,
+        }
+    }
+
+    /// For the servers known to accept `stream_options`, without it they send no usage while streaming
+    pub fn with_stream_usage(mut self, stream_usage: bool) -> Self {
+        self.stream_usage = stream_usage;
+        self
+    }
+
+    /// Azure routes requests per deployment rather than per model and authenticates with its own header.
+    /// `resource` is the resource endpoint, ie: `https://my-resource.openai.azure.com`
+    pub fn azure(model: String, key: String, resource: &str, deployment: &str, api_version: &str) -> Self {
+        Self {
+            model,
+            url: format!("{}/openai/deployments/{deployment}/chat/completions?api-version={api_version}", resource.trim_end_matches('/')),
+            headers: vec![(String::from("api-key"), key)],
+            // every api version since 2024-09-01 takes it
+            stream_usage: true,
+            // the GA api versions predate the developer role
+            developer_role: false,
         }
     }
 }
 
 impl LLMApi for ApiContext {
-    fn build_request(&self, messages: Vec<Message>) -> Request<Vec<u8>> {
+    fn build_request(&self, messages: Vec<Message>, tools: &[Tool], options: &GenerationOptions) -> Result<Request<Vec<u8>>> {
+        let messages = messages.into_iter()
+            .map(|m| MessageTx {
+                role: match m.role {
+                    Role::Developer if self.developer_role => MessageRole::Developer,
+                    Role::Developer => MessageRole::System,
+                    Role::User => MessageRole::User,
+                    Role::Assistant => MessageRole::Assistant,
+                    Role::Tool => MessageRole::Tool,
+                },
+                // an assistant message with tool calls may have no content at all
+                content: if m.content.is_empty() && !m.tool_calls.is_empty() { None } else { Some(ContentTx::from_message(&m)) },
+                tool_calls: m.tool_calls.into_iter()
+                    .map(|c| ToolCallTx {
+                        id: c.id,
+                        call_type: "function",
+                        function: FunctionTx { name: c.name, arguments: c.arguments },
+                    })
+                    .collect(),
+                tool_call_id: m.tool_call_id,
+            })
+            .collect();
+        let tools = tools.iter()
+            .map(|t| ToolTx {
+                tool_type: "function",
+                function: FunctionDefinition {
+                    name: t.name.clone(),
+                    description: t.description.clone(),
+                    parameters: t.parameters.clone(),
+                },
+            })
+            .collect();
+
+        // Reasoning models refuse the sampling parameters, and their reasoning tokens come out of the completion budget
+        let reasoning = super::is_reasoning(&self.model);
+        let sampling = |value: Option<f32>| if reasoning { None } else { value };
+        let max_completion_tokens = match options.max_tokens {
+            None if reasoning => Defaults::MAX_REASONING_COMPLETION_TOKENS,
+            _ => options.max_tokens(),
+        };
+
         let body = RequestBody {
             model: self.model.clone(),
             messages,
-            max_completion_tokens: Defaults::MAX_COMPLETION_TOKENS,
+            tools,
+            max_completion_tokens,
             n: Defaults::NUM_GENS,
+            temperature: sampling(options.temperature),
+            top_p: sampling(options.top_p),
+            stop: options.stop.clone(),
+            seed: options.seed,
+            presence_penalty: sampling(options.presence_penalty),
+            frequency_penalty: sampling(options.frequency_penalty),
+            reasoning_effort: options.reasoning_effort.map(|e| e.as_str()),
+            response_format: options.response_format.as_ref().map(|f| ResponseFormat {
+                format_type: "json_schema",
+                json_schema: JsonSchemaTx { name: f.name.clone(), schema: f.schema.clone(), strict: false },
+            }),
             stream: true,
+            stream_options: self.stream_usage.then_some(StreamOptions { include_usage: true }),
         };
 
-        Request::post("https://api.openai.com/v1/chat/completions")
-            .header("Content-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", self.key).as_str())
-            .body(
-                serde_json::to_string(&body)
-                    .expect("Failed to build valid JSON from body")
-                    .as_bytes()
-                    .to_vec()
-            )
-            .unwrap()
-    }
-
-    fn build_response(&self, data: &[u8]) -> (usize, LLMResponse) {
-        let data_str = std::str::from_utf8(&data).unwrap();
-
-        // Nasty parsing of SSE
-        let mut json_data = String::new();
-        let mut piece = String::new();
-        data_str.lines().for_each(|line| {
-            if line.trim() == "data: [DONE]" {/* ignore end message */}
-            else if line.starts_with("data:") {
-                // a whole json object might come in multiple data lines
-                let clean = line.strip_prefix("data:").unwrap_or(line).trim();
-                json_data.push_str(clean);
-            }
-            else if line.is_empty() && !json_data.is_empty() {
-                // lol that unwrap is dangerous
-                let data_parsed: Response = serde_json::from_str(json_data.trim()).unwrap();
-
-                if let Some(choice) = data_parsed.choices.get(0) {
-                    if let Some(message) = choice.message.content_or_refusal() {
-                        // maybe there are multiple json messages in the data ?
-                        // we'll keep iterating
-                        piece.push_str(message.as_str());
-                    }
+        let mut req = Request::post(self.url.as_str())
+            .header("Content-Type", "application/json");
+        for (name, value) in &self.headers {
+            req = req.header(name.as_str(), value.as_str());
+        }
+
+        Ok(req.body(serde_json::to_vec(&body).map_err(|e| Error::InvalidRequest(e.to_string()))?)?)
+    }
+
+    fn build_response(&self, decoder: &mut Decoder, data: &[u8]) -> Result<(usize, Vec<LLMEvent>)> {
+        let mut events = Vec::new();
+        for event in decoder.events(data) {
+            if event.data.trim() == "[DONE]" { continue; }
+
+            let data_parsed: Response = serde_json::from_str(event.data.as_str())?;
+            // we only ever ask for a single generation
+            if let Some(choice) = data_parsed.choices.into_iter().next() {
+                let message = choice.message;
+                if let Some(content) = message.content.filter(|c| !c.is_empty()) {
+                    events.push(LLMEvent::TextDelta(content));
+                }
+                if let Some(reasoning) = message.reasoning_content.or(message.reasoning).filter(|r| !r.is_empty()) {
+                    events.push(LLMEvent::ReasoningDelta(reasoning));
+                }
+                if let Some(refusal) = message.refusal.filter(|r| !r.is_empty()) {
+                    events.push(LLMEvent::RefusalDelta(refusal));
+                }
+                for tool_call in message.tool_calls.unwrap_or_default() {
+                    events.push(LLMEvent::ToolCallDelta {
+                        index: tool_call.index,
+                        id: tool_call.id,
+                        name: tool_call.function.name,
+                        arguments: tool_call.function.arguments.unwrap_or_default(),
+                    });
                 }
+                for annotation in message.annotations.unwrap_or_default() {
+                    events.push(LLMEvent::Annotation(crate::Annotation {
+                        title: annotation.url_citation.title,
+                        url: annotation.url_citation.url,
+                        start_index: Some(annotation.url_citation.start_index),
+                        end_index: Some(annotation.url_citation.end_index),
+                    }));
+                }
+                if let Some(reason) = choice.finish_reason {
+                    events.push(LLMEvent::Finish { reason: reason.into() });
+                }
+            }
 
-                json_data.clear();
+            if let Some(usage) = data_parsed.usage {
+                events.push(LLMEvent::Usage(usage.into()));
             }
+        }
 
-        });
+        Ok((data.len(), events))
+    }
 
-        (data.len(), LLMResponse(piece))
+    fn build_error(&self, status: u32, body: &[u8]) -> Error {
+        match serde_json::from_slice::<ErrorResponse>(body) {
+            // An exhausted quota also comes as a 429 but retrying won't help
+            Ok(ErrorResponse { error }) if error.code.as_deref() == Some("insufficient_quota") => {
+                Error::Provider { status: Some(status), kind: error.code, message: error.message }
+            },
+            Ok(ErrorResponse { error }) => Error::from_status(status, error.code.or(error.error_type), Some(error.message)),
+            Err(_) => Error::from_status(status, None, None),
+        }
     }
 }
 
@@ -87,37 +207,51 @@ struct Annotation {
     url_citation: UrlCitation,
 }
 
+#[derive(Deserialize)]
+struct FunctionChunk {
+    name: Option<String>,
+    arguments: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[allow(unused)]
+struct ToolCallChunk {
+    index: usize,
+    id: Option<String>,
+    #[serde(rename = "type")]
+    call_type: Option<String>,
+    function: FunctionChunk,
+}
+
 #[derive(Deserialize)]
 #[allow(unused)]
 struct MessageRx {
     role: Option<Role>,
     content: Option<String>,
     refusal: Option<String>,
+    /// OpenAI keeps the reasoning to itself but compatible servers stream it under either name
+    reasoning_content: Option<String>,
+    reasoning: Option<String>,
+    tool_calls: Option<Vec<ToolCallChunk>>,
     annotations: Option<Vec<Annotation>>,
 }
 
-#[allow(unused)]
-impl MessageRx {
-    fn is_refusal(&self) -> bool {
-        return self.refusal.is_some();
-    }
-
-    fn content_or_refusal(&self) -> Option<String> {
-       if let Some(_) = &self.refusal {
-            self.refusal.clone()
-        } else if let Some(_) = &self.content {
-            self.content.clone()
-        } else {
-            None
+#[derive(Deserialize)]
+#[serde(rename_all="snake_case")]
+enum FinishReason {Stop, Length, ContentFilter, ToolCalls, FunctionCall}
+
+impl From<FinishReason> for crate::FinishReason {
+    fn from(r: FinishReason) -> Self {
+        match r {
+            FinishReason::Stop => crate::FinishReason::Stop,
+            FinishReason::Length => crate::FinishReason::Length,
+            FinishReason::ContentFilter => crate::FinishReason::ContentFilter,
+            FinishReason::ToolCalls | FinishReason::FunctionCall => crate::FinishReason::ToolCalls,
         }
     }
 }
 
-#[derive(Deserialize)]
-#[serde(rename_all="snake_case")]
-enum FinishReason {Stop, Length, ContentFilter, ToolCalls, FunctionCalls}
-
-/// Left empty for now out of laziness
+/// Log probabilities are never requested, the field is only accepted
 #[derive(Deserialize)]
 struct Logprobs {}
 
@@ -131,9 +265,38 @@ struct Choice {
     message: MessageRx,
 }
 
-/// Left empty for now out of laziness
 #[derive(Deserialize)]
-struct Usage {}
+#[allow(unused)]
+struct Usage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    total_tokens: u64,
+    prompt_tokens_details: Option<PromptTokensDetails>,
+    completion_tokens_details: Option<CompletionTokensDetails>,
+}
+
+#[derive(Deserialize)]
+struct PromptTokensDetails {
+    #[serde(default)]
+    cached_tokens: u64,
+}
+
+#[derive(Deserialize)]
+struct CompletionTokensDetails {
+    #[serde(default)]
+    reasoning_tokens: u64,
+}
+
+impl From<Usage> for crate::Usage {
+    fn from(u: Usage) -> Self {
+        crate::Usage {
+            prompt_tokens: u.prompt_tokens,
+            completion_tokens: u.completion_tokens,
+            cached_tokens: u.prompt_tokens_details.map_or(0, |d| d.cached_tokens),
+            reasoning_tokens: u.completion_tokens_details.map_or(0, |d| d.reasoning_tokens),
+        }
+    }
+}
 
 #[derive(Deserialize)]
 #[allow(unused)]
@@ -144,15 +307,397 @@ struct Response {
     created: u64,
     model: String,
     service_tier: Option<String>,
-    system_fingerprint: String,
+    system_fingerprint: Option<String>,
     usage: Option<Usage>,
 }
 
+#[derive(Deserialize)]
+struct ErrorBody {
+    message: String,
+    #[serde(rename = "type")]
+    error_type: Option<String>,
+    code: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ErrorBody,
+}
+
+#[derive(Serialize)]
+struct FunctionTx {
+    name: String,
+    arguments: String,
+}
+
+#[derive(Serialize)]
+struct ToolCallTx {
+    id: String,
+    #[serde(rename = "type")]
+    call_type: &'static str,
+    function: FunctionTx,
+}
+
+/// A plain string unless there is more than text
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ContentTx {
+    Text(String),
+    Parts(Vec<PartTx>),
+}
+
+impl ContentTx {
+    fn from_message(message: &Message) -> Self {
+        if message.is_text() {
+            return ContentTx::Text(message.text());
+        }
+
+        let parts = message.content.iter()
+            .map(|part| match part {
+                ContentPart::Text(text) => PartTx::Text { text: text.clone() },
+                ContentPart::ImageUrl(url) => PartTx::ImageUrl { image_url: ImageUrlTx { url: url.clone() } },
+                ContentPart::Image { mime_type, data } => PartTx::ImageUrl { image_url: ImageUrlTx { url: ContentPart::data_url(mime_type, data) } },
+                ContentPart::File { name, mime_type, data } => PartTx::File { file: FileTx { filename: name.clone(), file_data: ContentPart::data_url(mime_type, data) } },
+            })
+            .collect();
+        ContentTx::Parts(parts)
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum PartTx {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrlTx },
+    File { file: FileTx },
+}
+
+#[derive(Serialize)]
+struct ImageUrlTx {
+    url: String,
+}
+
+#[derive(Serialize)]
+struct FileTx {
+    filename: String,
+    file_data: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all="snake_case")]
+enum MessageRole {System, Developer, User, Assistant, Tool}
+
+#[derive(Serialize)]
+struct MessageTx {
+    role: MessageRole,
+    content: Option<ContentTx>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<ToolCallTx>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+}
+
+#[derive(Serialize)]
+struct FunctionDefinition {
+    name: String,
+    description: String,
+    parameters: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct ToolTx {
+    #[serde(rename = "type")]
+    tool_type: &'static str,
+    function: FunctionDefinition,
+}
+
 #[derive(Serialize)]
 struct RequestBody {
     model: String,
-    messages: Vec<Message>,
+    messages: Vec<MessageTx>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<ToolTx>,
     max_completion_tokens: u32,
     n: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+    /// Only accepted by reasoning models
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reasoning_effort: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<ResponseFormat>,
     stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+}
+
+#[derive(Serialize)]
+struct ResponseFormat {
+    #[serde(rename = "type")]
+    format_type: &'static str,
+    json_schema: JsonSchemaTx,
+}
+
+/// Strict mode only takes schemas forbidding additional properties everywhere, the answer is validated by the caller instead
+#[derive(Serialize)]
+struct JsonSchemaTx {
+    name: String,
+    schema: serde_json::Value,
+    strict: bool,
+}
+
+/// Without this the usage is never sent when streaming, it comes in a last chunk with no choices
+#[derive(Serialize)]
+struct StreamOptions {
+    include_usage: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = "data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"system_fingerprint\":\"fp\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"Bonjour \"},\"finish_reason\":null}]}
+
+data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"system_fingerprint\":\"fp\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"à toi ✨\"},\"finish_reason\":\"stop\"}]}
+
+data: [DONE]
+
+";
+
+    /// An answer calling a tool, its arguments split across chunks
+    const TOOL_CALL_STREAM: &str = "data: {\"id\":\"c2\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"Let me look\"},\"finish_reason\":null}]}\n\n\
+data: {\"id\":\"c2\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"type\":\"function\",\"function\":{\"name\":\"read_file\",\"arguments\":\"\"}}]},\"finish_reason\":null}]}\n\n\
+data: {\"id\":\"c2\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"{\\\"path\\\":\"}}]},\"finish_reason\":null}]}\n\n\
+data: {\"id\":\"c2\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"\\\"a.txt\\\"}\"}}]},\"finish_reason\":\"tool_calls\"}]}\n\n\
+data: [DONE]\n\n";
+
+    /// Asks with a tool, reads the streamed answer and asks again with the result of the call, returns the second request body and the events
+    fn round_trip(api: &ApiContext, stream: &str) -> (serde_json::Value, Vec<LLMEvent>) {
+        let tools = [Tool::new("read_file", "Reads a file", serde_json::json!({"type": "object"}))];
+        let mut messages = vec![
+            Message::new(Role::Developer, String::from("be brief")),
+            Message::new(Role::User, String::from("what's in a.txt?")),
+        ];
+        let req = api.build_request(messages.clone(), &tools, &GenerationOptions::default()).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+        assert_eq!(body["tools"][0], serde_json::json!({"type": "function", "function": {"name": "read_file", "description": "Reads a file", "parameters": {"type": "object"}}}));
+        assert_eq!(body["messages"], serde_json::json!([
+            {"role": "system", "content": "be brief"},
+            {"role": "user", "content": "what's in a.txt?"},
+        ]));
+
+        let (_, events) = api.build_response(&mut Decoder::new(), stream.as_bytes()).unwrap();
+        messages.push(crate::tools::answer_message(&events));
+        messages.push(Message::tool_result(String::from("call_1"), String::from("hello")));
+
+        let req = api.build_request(messages, &tools, &GenerationOptions::default()).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+        assert_eq!(body["messages"][2], serde_json::json!({"role": "assistant", "content": "Let me look", "tool_calls": [
+            {"id": "call_1", "type": "function", "function": {"name": "read_file", "arguments": "{\"path\":\"a.txt\"}"}},
+        ]}));
+        assert_eq!(body["messages"][3], serde_json::json!({"role": "tool", "content": "hello", "tool_call_id": "call_1"}));
+        (body, events)
+    }
+
+    #[test]
+    fn build_response_split_at_every_offset() {
+        let api = ApiContext::new(String::from("m"), String::new());
+        let bytes = FIXTURE.as_bytes();
+        for i in 0..=bytes.len() {
+            let mut decoder = Decoder::new();
+            let (_, mut events) = api.build_response(&mut decoder, &bytes[..i]).unwrap();
+            events.extend(api.build_response(&mut decoder, &bytes[i..]).unwrap().1);
+
+            let text: String = events.iter()
+                .filter_map(|e| match e { LLMEvent::TextDelta(t) => Some(t.as_str()), _ => None })
+                .collect();
+            assert_eq!(text, "Bonjour à toi ✨", "split at {i}");
+            assert_eq!(events.last(), Some(&LLMEvent::Finish { reason: crate::FinishReason::Stop }), "split at {i}");
+        }
+    }
+
+    #[test]
+    fn build_request_with_tools() {
+        let api = ApiContext::new(String::from("m"), String::from("key"));
+        let call = crate::ToolCall { id: String::from("call_1"), name: String::from("read_file"), arguments: String::from("{\"path\":\"a.txt\"}") };
+        let messages = vec![
+            Message::new(Role::User, String::from("what's in a.txt?")),
+            Message::with_tool_calls(String::new(), vec![call]),
+            Message::tool_result(String::from("call_1"), String::from("hello")),
+        ];
+        let tools = [Tool::new("read_file", "Reads a file", serde_json::json!({"type": "object"}))];
+        let req = api.build_request(messages, &tools, &GenerationOptions::default()).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+
+        assert_eq!(body["tools"][0]["function"]["name"], "read_file");
+        assert_eq!(body["max_completion_tokens"], Defaults::MAX_COMPLETION_TOKENS);
+        assert!(body.get("temperature").is_none() && body.get("stop").is_none());
+        assert_eq!(body["messages"][1]["content"], serde_json::Value::Null);
+        assert_eq!(body["messages"][1]["tool_calls"][0]["function"]["arguments"], "{\"path\":\"a.txt\"}");
+        assert_eq!(body["messages"][2], serde_json::json!({"role": "tool", "content": "hello", "tool_call_id": "call_1"}));
+    }
+
+    #[test]
+    fn build_request_with_options() {
+        let api = ApiContext::new(String::from("m"), String::from("key"));
+        let options = GenerationOptions {
+            temperature: Some(0.0),
+            max_tokens: Some(100),
+            stop: vec![String::from("END")],
+            seed: Some(42),
+            reasoning_effort: Some(crate::ReasoningEffort::Low),
+            ..Default::default()
+        };
+        let req = api.build_request(Message::new_user_request(String::from("hi")), &[], &options).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+
+        assert_eq!(body["temperature"], 0.0);
+        assert_eq!(body["max_completion_tokens"], 100);
+        assert_eq!(body["stop"], serde_json::json!(["END"]));
+        assert_eq!(body["seed"], 42);
+        assert_eq!(body["reasoning_effort"], "low");
+        assert!(body.get("top_p").is_none());
+        assert_eq!(body["stream_options"]["include_usage"], true);
+
+        let api = ApiContext::with_base_url(String::from("m"), None, String::from("http://localhost:8000/v1"), Vec::new());
+        let req = api.build_request(Message::new_user_request(String::from("hi")), &[], &options).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+        assert!(body.get("stream_options").is_none());
+    }
+
+    #[test]
+    fn build_request_with_schema() {
+        let api = ApiContext::new(String::from("m"), String::from("key"));
+        let schema = serde_json::json!({"type": "object", "properties": {"name": {"type": "string"}}});
+        let options = GenerationOptions {
+            response_format: Some(crate::JsonSchema::new("person.schema", schema.clone())),
+            ..Default::default()
+        };
+        let req = api.build_request(Message::new_user_request(String::from("hi")), &[], &options).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+
+        assert_eq!(body["response_format"]["type"], "json_schema");
+        assert_eq!(body["response_format"]["json_schema"]["name"], "personschema");
+        assert_eq!(body["response_format"]["json_schema"]["schema"], schema);
+    }
+
+    #[test]
+    fn build_request_with_parts() {
+        let api = ApiContext::new(String::from("m"), String::from("key"));
+        let messages = vec![
+            Message::new(Role::Developer, String::from("be brief")),
+            Message::with_parts(Role::User, vec![
+                ContentPart::Text(String::from("what's that?")),
+                ContentPart::Image { mime_type: String::from("image/png"), data: b"png".to_vec() },
+                ContentPart::File { name: String::from("a.pdf"), mime_type: String::from("application/pdf"), data: b"pdf".to_vec() },
+            ]),
+        ];
+        let req = api.build_request(messages, &[], &GenerationOptions::default()).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+
+        // text only messages stay plain strings
+        assert_eq!(body["messages"][0]["content"], "be brief");
+        assert_eq!(body["messages"][1]["content"], serde_json::json!([
+            {"type": "text", "text": "what's that?"},
+            {"type": "image_url", "image_url": {"url": "data:image/png;base64,cG5n"}},
+            {"type": "file", "file": {"filename": "a.pdf", "file_data": "data:application/pdf;base64,cGRm"}},
+        ]));
+    }
+
+    #[test]
+    fn reasoning_model() {
+        let api = ApiContext::new(String::from("o4-mini"), String::from("key"));
+        let options = GenerationOptions { temperature: Some(0.2), seed: Some(1), ..Default::default() };
+        let req = api.build_request(Message::new_user_request(String::from("hi")), &[], &options).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+        assert!(body.get("temperature").is_none());
+        assert_eq!(body["seed"], 1);
+        assert_eq!(body["max_completion_tokens"], Defaults::MAX_REASONING_COMPLETION_TOKENS);
+
+        let chunk = "data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"reasoning_content\":\"Hmm\"},\"finish_reason\":null}]}\n\n";
+        let (_, events) = api.build_response(&mut Decoder::new(), chunk.as_bytes()).unwrap();
+        assert_eq!(events, vec![LLMEvent::ReasoningDelta(String::from("Hmm"))]);
+
+        // missing from the registry, known by its name
+        for (model, reasoning) in [("o3-mini-2025-01-31", true), ("openai/gpt-5-mini", true), ("gpt-5-chat-latest", false), ("gpt-4o", false)] {
+            let api = ApiContext::new(String::from(model), String::from("key"));
+            let req = api.build_request(Message::new_user_request(String::from("hi")), &[], &options).unwrap();
+            let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+            assert_eq!(body.get("temperature").is_none(), reasoning, "{model}");
+        }
+    }
+
+    #[test]
+    fn build_response_usage() {
+        let api = ApiContext::new(String::from("m"), String::new());
+        let chunk = "data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[],\
+\"usage\":{\"prompt_tokens\":1200,\"completion_tokens\":300,\"total_tokens\":1500,\
+\"prompt_tokens_details\":{\"cached_tokens\":1024},\"completion_tokens_details\":{\"reasoning_tokens\":256}}}\n\n";
+        let (_, events) = api.build_response(&mut Decoder::new(), chunk.as_bytes()).unwrap();
+        assert_eq!(events, vec![LLMEvent::Usage(crate::Usage {
+            prompt_tokens: 1200,
+            completion_tokens: 300,
+            cached_tokens: 1024,
+            reasoning_tokens: 256,
+        })]);
+    }
+
+    #[test]
+    fn build_error() {
+        let api = ApiContext::new(String::from("m"), String::new());
+
+        let body = br#"{"error": {"message": "Incorrect API key provided: sk-abc.", "type": "invalid_request_error", "param": null, "code": "invalid_api_key"}}"#;
+        let Error::Provider { status, kind, message } = api.build_error(401, body) else { panic!("expected a provider error"); };
+        assert_eq!(status, Some(401));
+        assert_eq!(kind.as_deref(), Some("invalid_api_key"));
+        assert_eq!(message, "Incorrect API key provided: sk-abc.");
+
+        let body = br#"{"error": {"message": "Rate limit reached", "type": "requests", "param": null, "code": "rate_limit_exceeded"}}"#;
+        assert!(matches!(api.build_error(429, body), Error::RateLimit { .. }));
+
+        let body = br#"{"error": {"message": "You exceeded your current quota", "type": "insufficient_quota", "param": null, "code": "insufficient_quota"}}"#;
+        assert!(matches!(api.build_error(429, body), Error::Provider { .. }));
+
+        assert!(matches!(api.build_error(502, b"<html>Bad gateway</html>"), Error::HttpStatus(502)));
+    }
+
+    #[test]
+    fn compatible_round_trip() {
+        let headers = vec![(String::from("HTTP-Referer"), String::from("https://example.com"))];
+        let api = ApiContext::with_base_url(String::from("m"), Some(String::from("key")), String::from("http://localhost:8000/v1/"), headers);
+        let req = api.build_request(Message::new_user_request(String::from("hi")), &[], &GenerationOptions::default()).unwrap();
+        assert_eq!(req.uri(), "http://localhost:8000/v1/chat/completions");
+        assert_eq!(req.headers()["Authorization"], "Bearer key");
+        assert_eq!(req.headers()["HTTP-Referer"], "https://example.com");
+
+        let (body, events) = round_trip(&api, TOOL_CALL_STREAM);
+        assert!(body.get("stream_options").is_none());
+        assert_eq!(events.last(), Some(&LLMEvent::Finish { reason: crate::FinishReason::ToolCalls }));
+    }
+
+    #[test]
+    fn azure_round_trip() {
+        let api = ApiContext::azure(String::from("gpt-4.1"), String::from("key"), "https://my-resource.openai.azure.com/", "prod", "2024-10-21");
+        let req = api.build_request(Message::new_user_request(String::from("hi")), &[], &GenerationOptions::default()).unwrap();
+        assert_eq!(req.uri(), "https://my-resource.openai.azure.com/openai/deployments/prod/chat/completions?api-version=2024-10-21");
+        assert_eq!(req.headers()["api-key"], "key");
+        assert!(req.headers().get("Authorization").is_none());
+
+        // the usage comes last, in a chunk of its own
+        let usage = "data: {\"id\":\"c2\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[],\"usage\":{\"prompt_tokens\":40,\"completion_tokens\":12,\"total_tokens\":52}}\n\n";
+        let stream = TOOL_CALL_STREAM.replace("data: [DONE]", &format!("{usage}data: [DONE]"));
+        let (body, events) = round_trip(&api, &stream);
+        assert_eq!(body["stream_options"]["include_usage"], true);
+        assert_eq!(events.last(), Some(&LLMEvent::Usage(crate::Usage { prompt_tokens: 40, completion_tokens: 12, ..Default::default() })));
+    }
 }
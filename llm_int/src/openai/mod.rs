@@ -1,8 +1,76 @@
 pub mod chat_completion_api;
+pub mod responses_api;
+
+use serde::{Serialize, Deserialize};
+use crate::models::{self, ModelInfo, Pricing};
+
+/// Latest GA version of the Azure OpenAI data plane API
+pub const AZURE_API_VERSION: &str = "2024-10-21";
 
 pub struct Models;
 #[allow(non_upper_case_globals)]
 impl Models {
     pub const GPT_4_1_Mini: &'static str = "gpt-4.1-mini-2025-04-14";
     pub const GPT_O_4_Mini: &'static str = "o4-mini-2025-04-16";
+    pub const GPT_5: &'static str = "gpt-5-2025-08-07";
+    pub const GPT_O_3_Pro: &'static str = "o3-pro-2025-06-10";
+
+    /// Models that only work, or work best, with the Responses API
+    pub const RESPONSES: &'static [&'static str] = &[Self::GPT_5, Self::GPT_O_3_Pro];
+
+    pub const ALL: &'static [ModelInfo] = &[
+        ModelInfo { name: Self::GPT_4_1_Mini, alias: Some("gpt-4.1-mini"), pricing: Pricing::new(0.40, 1.60, 0.10), reasoning: false },
+        ModelInfo { name: Self::GPT_O_4_Mini, alias: Some("o4-mini"), pricing: Pricing::new(1.10, 4.40, 0.275), reasoning: true },
+        ModelInfo { name: Self::GPT_5, alias: Some("gpt-5"), pricing: Pricing::new(1.25, 10.0, 0.125), reasoning: true },
+        // no discount on cached input
+        ModelInfo { name: Self::GPT_O_3_Pro, alias: Some("o3-pro"), pricing: Pricing::new(20.0, 80.0, 20.0), reasoning: true },
+    ];
+}
+
+/// Names OpenAI gives its reasoning models, for the ones missing from the registry
+const REASONING_PREFIXES: &[&str] = &["o1", "o3", "o4", "gpt-5"];
+
+/// Whether the model reasons before answering, from the registry or else from its name.
+/// Routers prefix the name with the vendor (ie: `openai/o3`), gpt-5-chat is the exception that doesn't reason.
+pub(crate) fn is_reasoning(model: &str) -> bool {
+    if let Some(info) = models::find(model) {
+        return info.reasoning;
+    }
+    let name = model.rsplit('/').next().unwrap_or(model);
+    REASONING_PREFIXES.iter().any(|p| name.starts_with(p)) && !name.starts_with("gpt-5-chat")
+}
+
+/// Tools run by OpenAI itself, only available through the Responses API
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all="snake_case")]
+pub enum BuiltinTool {
+    WebSearch,
+    CodeInterpreter,
+}
+
+/// Which of OpenAI's APIs a model is used through
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(tag="api", rename_all="snake_case")]
+pub enum Api {
+    #[default]
+    ChatCompletion,
+    Responses {
+        #[serde(default)]
+        builtin_tools: Vec<BuiltinTool>,
+        /// Lets OpenAI keep the responses so that only what follows the last one is sent, see `ApiContext::with_store`
+        #[serde(default)]
+        store: bool,
+    },
+}
+
+impl Api {
+    /// Chat completions unless the model is known to need the Responses API
+    pub fn for_model(model: &str) -> Self {
+        let name = models::find(model).map_or(model, |m| m.name);
+        if Models::RESPONSES.contains(&name) {
+            Api::Responses { builtin_tools: Vec::new(), store: false }
+        } else {
+            Api::ChatCompletion
+        }
+    }
 }
@@ -0,0 +1,571 @@
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use crate::{LLMApi, LLMEvent, ContentPart, Defaults, GenerationOptions, Message, Role, Tool, Error, Result};
+use crate::stream::Decoder;
+use http::Request;
+use super::BuiltinTool;
+
+const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// The last completed response, to only send what came after it next time
+struct Chain {
+    response_id: String,
+    /// Its text output and the tools it called, to recognize the assistant message made of it
+    text: String,
+    call_ids: Vec<String>,
+}
+
+pub struct ApiContext {
+    model: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    builtin_tools: Vec<BuiltinTool>,
+    store: bool,
+    chain: Mutex<Option<Chain>>,
+}
+
+impl ApiContext {
+    pub fn new(model: String, key: String, builtin_tools: Vec<BuiltinTool>) -> Self {
+        Self::with_base_url(model, Some(key), OPENAI_BASE_URL.to_string(), Vec::new(), builtin_tools)
+    }
+
+    /// For servers implementing the Responses API as well, `base_url` includes the version segment
+    pub fn with_base_url(model: String, key: Option<String>, base_url: String, mut headers: Vec<(String, String)>, builtin_tools: Vec<BuiltinTool>) -> Self {
+        if let Some(key) = key {
+            headers.push((String::from("Authorization"), format!("Bearer {key}")));
+        }
+
+        Self {
+            model,
+            url: format!("{}/responses", base_url.trim_end_matches('/')),
+            headers,
+            builtin_tools,
+            store: false,
+            chain: Mutex::new(None),
+        }
+    }
+
+    /// Off by default, the whole conversation is sent every time and nothing is kept by OpenAI.
+    /// On, the responses are stored for 30 days and a conversation continuing the last one only sends what came after it.
+    pub fn with_store(mut self, store: bool) -> Self {
+        self.store = store;
+        self
+    }
+
+    /// The id of the last response if the last assistant message of `messages` is its output, along with what came after it.
+    /// The response is kept by OpenAI along with what it was generated from, so none of that is sent again.
+    fn continuation<'a>(&self, messages: &'a [Message]) -> Option<(String, &'a [Message])> {
+        if !self.store { return None; }
+        let chain = self.chain.lock().ok()?;
+        let chain = chain.as_ref()?;
+        let last = messages.iter().rposition(|m| m.role == Role::Assistant)?;
+        let output = &messages[last];
+
+        let same_output = if chain.call_ids.is_empty() {
+            output.tool_calls.is_empty() && output.text() == chain.text
+        } else {
+            output.tool_calls.iter().map(|c| &c.id).eq(chain.call_ids.iter())
+        };
+        let rest = &messages[last + 1..];
+        if !same_output || rest.is_empty() { return None; }
+        Some((chain.response_id.clone(), rest))
+    }
+}
+
+impl LLMApi for ApiContext {
+    fn build_request(&self, messages: Vec<Message>, tools: &[Tool], options: &GenerationOptions) -> Result<Request<Vec<u8>>> {
+        // instructions are not carried over by previous_response_id, they are sent every time
+        let instructions: Vec<String> = messages.iter()
+            .filter(|m| m.role == Role::Developer)
+            .map(Message::text)
+            .collect();
+
+        let (previous_response_id, new_messages) = match self.continuation(&messages) {
+            Some((id, rest)) => (Some(id), rest),
+            None => (None, messages.as_slice()),
+        };
+
+        let mut input = Vec::new();
+        for m in new_messages {
+            match m.role {
+                Role::Developer => (),
+                Role::User | Role::Assistant => {
+                    if !m.content.is_empty() || m.tool_calls.is_empty() {
+                        input.push(InputItem::Message { role: m.role.clone(), content: InputContent::from_message(m) });
+                    }
+                    for call in &m.tool_calls {
+                        input.push(InputItem::FunctionCall { call_id: call.id.clone(), name: call.name.clone(), arguments: call.arguments.clone() });
+                    }
+                },
+                Role::Tool => input.push(InputItem::FunctionCallOutput {
+                    call_id: m.tool_call_id.clone().unwrap_or_default(),
+                    output: m.text(),
+                }),
+            }
+        }
+
+        let mut tools_tx: Vec<ToolTx> = self.builtin_tools.iter()
+            .map(|t| match t {
+                BuiltinTool::WebSearch => ToolTx::WebSearchPreview,
+                BuiltinTool::CodeInterpreter => ToolTx::CodeInterpreter { container: Container { container_type: "auto" } },
+            })
+            .collect();
+        tools_tx.extend(tools.iter().map(|t| ToolTx::Function {
+            name: t.name.clone(),
+            description: t.description.clone(),
+            parameters: t.parameters.clone(),
+            // strict schemas need every property required, ours don't
+            strict: false,
+        }));
+
+        // same restrictions as with chat completions, stop sequences, seeds and penalties don't exist here
+        let reasoning_model = super::is_reasoning(&self.model);
+        let sampling = |value: Option<f32>| if reasoning_model { None } else { value };
+        let max_output_tokens = match options.max_tokens {
+            None if reasoning_model => Defaults::MAX_REASONING_COMPLETION_TOKENS,
+            _ => options.max_tokens(),
+        };
+
+        let body = RequestBody {
+            model: self.model.clone(),
+            instructions: if instructions.is_empty() { None } else { Some(instructions.join("\n")) },
+            input,
+            tools: tools_tx,
+            previous_response_id,
+            max_output_tokens,
+            temperature: sampling(options.temperature),
+            top_p: sampling(options.top_p),
+            reasoning: options.reasoning_effort.map(|e| ReasoningTx { effort: e.as_str(), summary: "auto" }),
+            text: options.response_format.as_ref().map(|f| TextTx {
+                format: TextFormatTx { format_type: "json_schema", name: f.name.clone(), schema: f.schema.clone(), strict: false },
+            }),
+            stream: true,
+            store: self.store,
+        };
+
+        let mut req = Request::post(self.url.as_str())
+            .header("Content-Type", "application/json");
+        for (name, value) in &self.headers {
+            req = req.header(name.as_str(), value.as_str());
+        }
+
+        Ok(req.body(serde_json::to_vec(&body).map_err(|e| Error::InvalidRequest(e.to_string()))?)?)
+    }
+
+    fn build_response(&self, decoder: &mut Decoder, data: &[u8]) -> Result<(usize, Vec<LLMEvent>)> {
+        let mut events = Vec::new();
+        for event in decoder.events(data) {
+            match serde_json::from_str(event.data.as_str())? {
+                StreamEvent::OutputTextDelta { delta } => events.push(LLMEvent::TextDelta(delta)),
+                StreamEvent::RefusalDelta { delta } => events.push(LLMEvent::RefusalDelta(delta)),
+                StreamEvent::ReasoningSummaryTextDelta { delta } => events.push(LLMEvent::ReasoningDelta(delta)),
+                // summaries come in several parts
+                StreamEvent::ReasoningSummaryPartAdded { summary_index } if summary_index > 0 => {
+                    events.push(LLMEvent::ReasoningDelta(String::from("\n\n")));
+                },
+                StreamEvent::OutputItemAdded { output_index, item: OutputItem::FunctionCall { call_id, name } } => {
+                    events.push(LLMEvent::ToolCallDelta { index: output_index, id: Some(call_id), name: Some(name), arguments: String::new() });
+                },
+                StreamEvent::FunctionCallArgumentsDelta { output_index, delta } => {
+                    events.push(LLMEvent::ToolCallDelta { index: output_index, id: None, name: None, arguments: delta });
+                },
+                StreamEvent::OutputTextAnnotationAdded { annotation: AnnotationRx::UrlCitation { url, title, start_index, end_index } } => {
+                    events.push(LLMEvent::Annotation(crate::Annotation { title, url, start_index, end_index }));
+                },
+                StreamEvent::Completed { response } => {
+                    let calls_tools = response.output.iter().any(|item| matches!(item, OutputItem::FunctionCall { .. }));
+                    if self.store {
+                        if let Ok(mut chain) = self.chain.lock() {
+                            *chain = Some(Chain::from_response(&response));
+                        }
+                    }
+                    if let Some(usage) = response.usage {
+                        events.push(LLMEvent::Usage(usage.into()));
+                    }
+                    let reason = if calls_tools { crate::FinishReason::ToolCalls } else { crate::FinishReason::Stop };
+                    events.push(LLMEvent::Finish { reason });
+                },
+                StreamEvent::Incomplete { response } => {
+                    if let Some(usage) = response.usage {
+                        events.push(LLMEvent::Usage(usage.into()));
+                    }
+                    let reason = match response.incomplete_details.map(|d| d.reason).as_deref() {
+                        Some("max_output_tokens") => crate::FinishReason::Length,
+                        Some("content_filter") => crate::FinishReason::ContentFilter,
+                        Some(other) => crate::FinishReason::Other(other.to_string()),
+                        None => crate::FinishReason::Other(String::from("incomplete")),
+                    };
+                    events.push(LLMEvent::Finish { reason });
+                },
+                StreamEvent::Failed { response } => {
+                    let error = response.error.unwrap_or(ErrorBody { message: String::from("the response failed"), error_type: None, code: None });
+                    return Err(Error::Provider { status: None, kind: error.code, message: error.message });
+                },
+                StreamEvent::Error { code, message } => {
+                    return Err(Error::Provider { status: None, kind: code, message });
+                },
+                _ => (),
+            }
+        }
+
+        Ok((data.len(), events))
+    }
+
+    fn build_error(&self, status: u32, body: &[u8]) -> Error {
+        // the previous response may be gone, start over next time
+        if let Ok(mut chain) = self.chain.lock() {
+            *chain = None;
+        }
+        match serde_json::from_slice::<ErrorResponse>(body) {
+            Ok(ErrorResponse { error }) if error.code.as_deref() == Some("insufficient_quota") => {
+                Error::Provider { status: Some(status), kind: error.code, message: error.message }
+            },
+            Ok(ErrorResponse { error }) => Error::from_status(status, error.code.or(error.error_type), Some(error.message)),
+            Err(_) => Error::from_status(status, None, None),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag="type", rename_all="snake_case")]
+enum InputItem {
+    Message { role: Role, content: InputContent },
+    FunctionCall { call_id: String, name: String, arguments: String },
+    FunctionCallOutput { call_id: String, output: String },
+}
+
+/// A plain string unless there is more than text, assistant messages only ever have text
+#[derive(Serialize)]
+#[serde(untagged)]
+enum InputContent {
+    Text(String),
+    Parts(Vec<InputPart>),
+}
+
+impl InputContent {
+    fn from_message(message: &Message) -> Self {
+        if message.is_text() {
+            return InputContent::Text(message.text());
+        }
+
+        let parts = message.content.iter()
+            .map(|part| match part {
+                ContentPart::Text(text) => InputPart::Text { text: text.clone() },
+                ContentPart::ImageUrl(url) => InputPart::Image { image_url: url.clone() },
+                ContentPart::Image { mime_type, data } => InputPart::Image { image_url: ContentPart::data_url(mime_type, data) },
+                ContentPart::File { name, mime_type, data } => InputPart::File { filename: name.clone(), file_data: ContentPart::data_url(mime_type, data) },
+            })
+            .collect();
+        InputContent::Parts(parts)
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag="type")]
+enum InputPart {
+    #[serde(rename = "input_text")]
+    Text { text: String },
+    #[serde(rename = "input_image")]
+    Image { image_url: String },
+    #[serde(rename = "input_file")]
+    File { filename: String, file_data: String },
+}
+
+#[derive(Serialize)]
+struct Container {
+    #[serde(rename = "type")]
+    container_type: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(tag="type", rename_all="snake_case")]
+enum ToolTx {
+    Function { name: String, description: String, parameters: serde_json::Value, strict: bool },
+    WebSearchPreview,
+    CodeInterpreter { container: Container },
+}
+
+#[derive(Serialize)]
+struct ReasoningTx {
+    effort: &'static str,
+    /// Without it the reasoning is never streamed
+    summary: &'static str,
+}
+
+#[derive(Serialize)]
+struct RequestBody {
+    model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instructions: Option<String>,
+    input: Vec<InputItem>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<ToolTx>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous_response_id: Option<String>,
+    max_output_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reasoning: Option<ReasoningTx>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<TextTx>,
+    stream: bool,
+    /// Needed for previous_response_id to work, otherwise OpenAI keeps nothing
+    store: bool,
+}
+
+#[derive(Serialize)]
+struct TextTx {
+    format: TextFormatTx,
+}
+
+/// Unlike chat completions the schema is not nested in its own object
+#[derive(Serialize)]
+struct TextFormatTx {
+    #[serde(rename = "type")]
+    format_type: &'static str,
+    name: String,
+    schema: serde_json::Value,
+    strict: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(tag="type", rename_all="snake_case")]
+enum OutputItem {
+    Message {
+        #[serde(default)]
+        content: Vec<OutputContent>,
+    },
+    FunctionCall { call_id: String, name: String },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+#[serde(tag="type", rename_all="snake_case")]
+enum OutputContent {
+    OutputText { text: String },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+#[serde(tag="type", rename_all="snake_case")]
+enum AnnotationRx {
+    UrlCitation {
+        url: String,
+        #[serde(default)]
+        title: String,
+        start_index: Option<usize>,
+        end_index: Option<usize>,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct InputTokensDetails {
+    #[serde(default)]
+    cached_tokens: u64,
+}
+
+#[derive(Deserialize)]
+struct OutputTokensDetails {
+    #[serde(default)]
+    reasoning_tokens: u64,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    input_tokens: u64,
+    output_tokens: u64,
+    input_tokens_details: Option<InputTokensDetails>,
+    output_tokens_details: Option<OutputTokensDetails>,
+}
+
+impl From<Usage> for crate::Usage {
+    fn from(u: Usage) -> Self {
+        crate::Usage {
+            prompt_tokens: u.input_tokens,
+            completion_tokens: u.output_tokens,
+            cached_tokens: u.input_tokens_details.map_or(0, |d| d.cached_tokens),
+            reasoning_tokens: u.output_tokens_details.map_or(0, |d| d.reasoning_tokens),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct IncompleteDetails {
+    reason: String,
+}
+
+#[derive(Deserialize)]
+struct ResponseRx {
+    id: String,
+    #[serde(default)]
+    output: Vec<OutputItem>,
+    usage: Option<Usage>,
+    incomplete_details: Option<IncompleteDetails>,
+    error: Option<ErrorBody>,
+}
+
+impl Chain {
+    fn from_response(response: &ResponseRx) -> Self {
+        let mut chain = Chain { response_id: response.id.clone(), text: String::new(), call_ids: Vec::new() };
+        for item in &response.output {
+            match item {
+                OutputItem::Message { content } => {
+                    for part in content {
+                        if let OutputContent::OutputText { text } = part {
+                            chain.text.push_str(text);
+                        }
+                    }
+                },
+                OutputItem::FunctionCall { call_id, .. } => chain.call_ids.push(call_id.clone()),
+                OutputItem::Other => (),
+            }
+        }
+        chain
+    }
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    message: String,
+    #[serde(rename = "type")]
+    error_type: Option<String>,
+    code: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ErrorBody,
+}
+
+/// Only the events we make something of, there are many more
+#[derive(Deserialize)]
+#[serde(tag="type")]
+enum StreamEvent {
+    #[serde(rename = "response.output_text.delta")]
+    OutputTextDelta { delta: String },
+    #[serde(rename = "response.refusal.delta")]
+    RefusalDelta { delta: String },
+    #[serde(rename = "response.reasoning_summary_text.delta")]
+    ReasoningSummaryTextDelta { delta: String },
+    #[serde(rename = "response.reasoning_summary_part.added")]
+    ReasoningSummaryPartAdded { summary_index: usize },
+    #[serde(rename = "response.output_item.added")]
+    OutputItemAdded { output_index: usize, item: OutputItem },
+    #[serde(rename = "response.function_call_arguments.delta")]
+    FunctionCallArgumentsDelta { output_index: usize, delta: String },
+    #[serde(rename = "response.output_text.annotation.added")]
+    OutputTextAnnotationAdded { annotation: AnnotationRx },
+    #[serde(rename = "response.completed")]
+    Completed { response: ResponseRx },
+    #[serde(rename = "response.incomplete")]
+    Incomplete { response: ResponseRx },
+    #[serde(rename = "response.failed")]
+    Failed { response: ResponseRx },
+    #[serde(rename = "error")]
+    Error { code: Option<String>, message: String },
+    #[serde(other)]
+    Other,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STREAM: &str = "event: response.created\n\
+data: {\"type\":\"response.created\",\"response\":{\"id\":\"resp_1\",\"output\":[]}}\n\n\
+event: response.reasoning_summary_text.delta\n\
+data: {\"type\":\"response.reasoning_summary_text.delta\",\"item_id\":\"rs_1\",\"output_index\":0,\"summary_index\":0,\"delta\":\"Looking\"}\n\n\
+event: response.output_text.delta\n\
+data: {\"type\":\"response.output_text.delta\",\"item_id\":\"msg_1\",\"output_index\":1,\"content_index\":0,\"delta\":\"Let me check\"}\n\n\
+event: response.output_item.added\n\
+data: {\"type\":\"response.output_item.added\",\"output_index\":2,\"item\":{\"type\":\"function_call\",\"id\":\"fc_1\",\"call_id\":\"call_1\",\"name\":\"read_file\",\"arguments\":\"\"}}\n\n\
+event: response.function_call_arguments.delta\n\
+data: {\"type\":\"response.function_call_arguments.delta\",\"item_id\":\"fc_1\",\"output_index\":2,\"delta\":\"{}\"}\n\n\
+event: response.completed\n\
+data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"output\":[{\"type\":\"reasoning\"},{\"type\":\"message\",\"content\":[{\"type\":\"output_text\",\"text\":\"Let me check\"}]},{\"type\":\"function_call\",\"call_id\":\"call_1\",\"name\":\"read_file\"}],\
+\"usage\":{\"input_tokens\":100,\"input_tokens_details\":{\"cached_tokens\":64},\"output_tokens\":50,\"output_tokens_details\":{\"reasoning_tokens\":20},\"total_tokens\":150}}}\n\n";
+
+    #[test]
+    fn build_response_events() {
+        let api = ApiContext::new(String::from("gpt-5"), String::from("key"), Vec::new());
+        let (_, events) = api.build_response(&mut Decoder::new(), STREAM.as_bytes()).unwrap();
+        assert_eq!(events, vec![
+            LLMEvent::ReasoningDelta(String::from("Looking")),
+            LLMEvent::TextDelta(String::from("Let me check")),
+            LLMEvent::ToolCallDelta { index: 2, id: Some(String::from("call_1")), name: Some(String::from("read_file")), arguments: String::new() },
+            LLMEvent::ToolCallDelta { index: 2, id: None, name: None, arguments: String::from("{}") },
+            LLMEvent::Usage(crate::Usage { prompt_tokens: 100, completion_tokens: 50, cached_tokens: 64, reasoning_tokens: 20 }),
+            LLMEvent::Finish { reason: crate::FinishReason::ToolCalls },
+        ]);
+    }
+
+    #[test]
+    fn selected_per_model() {
+        assert_eq!(crate::openai::Api::for_model("gpt-5"), crate::openai::Api::Responses { builtin_tools: Vec::new(), store: false });
+        assert_eq!(crate::openai::Api::for_model(crate::openai::Models::GPT_O_3_Pro), crate::openai::Api::Responses { builtin_tools: Vec::new(), store: false });
+        assert_eq!(crate::openai::Api::for_model("gpt-4.1-mini"), crate::openai::Api::ChatCompletion);
+    }
+
+    #[test]
+    fn chains_previous_response() {
+        let api = ApiContext::new(String::from("gpt-5"), String::from("key"), vec![BuiltinTool::WebSearch]).with_store(true);
+        let call = crate::ToolCall { id: String::from("call_1"), name: String::from("read_file"), arguments: String::from("{}") };
+        let mut messages = vec![
+            Message::new(Role::Developer, String::from("be brief")),
+            Message::new(Role::User, String::from("what's in a.txt?")),
+        ];
+        let tools = [Tool::new("read_file", "Reads a file", serde_json::json!({"type": "object"}))];
+
+        let req = api.build_request(messages.clone(), &tools, &GenerationOptions::default()).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+        assert_eq!(body["instructions"], "be brief");
+        assert_eq!(body["input"], serde_json::json!([{"type": "message", "role": "user", "content": "what's in a.txt?"}]));
+        assert_eq!(body["tools"][0], serde_json::json!({"type": "web_search_preview"}));
+        assert_eq!(body["tools"][1]["name"], "read_file");
+        assert!(body.get("previous_response_id").is_none());
+        assert_eq!(body["store"], true);
+
+        api.build_response(&mut Decoder::new(), STREAM.as_bytes()).unwrap();
+        messages.push(Message::with_tool_calls(String::from("Let me check"), vec![call]));
+        messages.push(Message::tool_result(String::from("call_1"), String::from("hello")));
+
+        let req = api.build_request(messages.clone(), &tools, &GenerationOptions::default()).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+        assert_eq!(body["previous_response_id"], "resp_1");
+        assert_eq!(body["instructions"], "be brief");
+        assert_eq!(body["input"], serde_json::json!([{"type": "function_call_output", "call_id": "call_1", "output": "hello"}]));
+
+        // a failed request breaks the chain
+        api.build_error(400, b"{\"error\":{\"message\":\"Previous response not found\",\"type\":\"invalid_request_error\",\"code\":\"previous_response_not_found\"}}");
+        let req = api.build_request(messages, &tools, &GenerationOptions::default()).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+        assert!(body.get("previous_response_id").is_none());
+        assert_eq!(body["input"][1], serde_json::json!({"type": "message", "role": "assistant", "content": "Let me check"}));
+        assert_eq!(body["input"][2]["type"], "function_call");
+    }
+
+    #[test]
+    fn sends_everything_without_store() {
+        let api = ApiContext::new(String::from("gpt-5"), String::from("key"), Vec::new());
+        let call = crate::ToolCall { id: String::from("call_1"), name: String::from("read_file"), arguments: String::from("{}") };
+        api.build_response(&mut Decoder::new(), STREAM.as_bytes()).unwrap();
+        let messages = vec![
+            Message::new(Role::User, String::from("what's in a.txt?")),
+            Message::with_tool_calls(String::from("Let me check"), vec![call]),
+            Message::tool_result(String::from("call_1"), String::from("hello")),
+        ];
+
+        let req = api.build_request(messages, &[], &GenerationOptions::default()).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(req.body()).unwrap();
+        assert_eq!(body["store"], false);
+        assert!(body.get("previous_response_id").is_none());
+        assert_eq!(body["input"].as_array().map(Vec::len), Some(4));
+    }
+}
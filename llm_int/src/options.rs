@@ -0,0 +1,110 @@
+use std::str::FromStr;
+use serde::{Serialize, Deserialize};
+use crate::Defaults;
+
+/// How hard reasoning models think before answering, providers without levels get a token budget instead
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all="snake_case")]
+pub enum ReasoningEffort {Minimal, Low, Medium, High}
+
+impl ReasoningEffort {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReasoningEffort::Minimal => "minimal",
+            ReasoningEffort::Low => "low",
+            ReasoningEffort::Medium => "medium",
+            ReasoningEffort::High => "high",
+        }
+    }
+
+    /// Tokens the model may spend thinking, for providers that want a budget
+    pub fn budget_tokens(&self) -> u32 {
+        match self {
+            // the lowest budget Anthropic accepts
+            ReasoningEffort::Minimal => 1024,
+            ReasoningEffort::Low => 2048,
+            ReasoningEffort::Medium => 8192,
+            ReasoningEffort::High => 24576,
+        }
+    }
+}
+
+impl FromStr for ReasoningEffort {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "minimal" => Ok(ReasoningEffort::Minimal),
+            "low" => Ok(ReasoningEffort::Low),
+            "medium" => Ok(ReasoningEffort::Medium),
+            "high" => Ok(ReasoningEffort::High),
+            _ => Err(format!("unknown reasoning effort \"{s}\", expected one of: minimal, low, medium, high")),
+        }
+    }
+}
+
+/// The answer has to be a JSON value matching `schema`, sent as a `json_schema` response format or whatever the provider has instead
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonSchema {
+    /// Some providers want the schema named, only letters, digits, underscores and dashes are kept
+    pub name: String,
+    pub schema: serde_json::Value,
+}
+
+impl JsonSchema {
+    pub fn new(name: &str, schema: serde_json::Value) -> Self {
+        let name: String = name.chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+            .take(64)
+            .collect();
+        Self {
+            name: if name.is_empty() { String::from("answer") } else { name },
+            schema,
+        }
+    }
+
+    /// For providers that can't be told about the schema other than in the prompt
+    pub fn instructions(&self) -> String {
+        format!("Answer only with a JSON value, without markdown or any other text, matching this JSON Schema:\n{}", self.schema)
+    }
+}
+
+/// Sampling parameters of a request, anything left unset is up to the provider.
+/// Each provider maps these to its own fields and ignores the ones it has no equivalent for.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenerationOptions {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    /// Defaults to `Defaults::MAX_COMPLETION_TOKENS`
+    pub max_tokens: Option<u32>,
+    pub stop: Vec<String>,
+    pub seed: Option<i64>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    pub reasoning_effort: Option<ReasoningEffort>,
+    /// Given per invocation, never read from the config
+    #[serde(skip)]
+    pub response_format: Option<JsonSchema>,
+}
+
+impl GenerationOptions {
+    pub fn max_tokens(&self) -> u32 {
+        self.max_tokens.unwrap_or(Defaults::MAX_COMPLETION_TOKENS)
+    }
+
+    /// Fills whatever is unset here with `defaults`
+    pub fn or(self, defaults: &GenerationOptions) -> Self {
+        Self {
+            temperature: self.temperature.or(defaults.temperature),
+            top_p: self.top_p.or(defaults.top_p),
+            max_tokens: self.max_tokens.or(defaults.max_tokens),
+            stop: if self.stop.is_empty() { defaults.stop.clone() } else { self.stop },
+            seed: self.seed.or(defaults.seed),
+            presence_penalty: self.presence_penalty.or(defaults.presence_penalty),
+            frequency_penalty: self.frequency_penalty.or(defaults.frequency_penalty),
+            reasoning_effort: self.reasoning_effort.or(defaults.reasoning_effort),
+            response_format: self.response_format.or(defaults.response_format.clone()),
+        }
+    }
+}
@@ -0,0 +1,161 @@
+/// A single server sent event, as dispatched by a blank line
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Event {
+    pub event: Option<String>,
+    pub id: Option<String>,
+    pub data: String,
+}
+
+/// Incremental decoder for streamed responses.
+/// curl hands us chunks cut at arbitrary byte offsets so everything that isn't a complete line
+/// (including half of a multibyte character) is kept around until the next chunk arrives.
+/// One decoder must be created per request.
+#[derive(Default)]
+pub struct Decoder {
+    pending: Vec<u8>,
+    /// Last chunk ended on a '\r', a '\n' starting the next one belongs to the same line ending
+    skip_lf: bool,
+    event: Event,
+    has_data: bool,
+    next_index: usize,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Numbers items of the response for providers that don't, ie: tool calls that each come whole
+    pub fn next_index(&mut self) -> usize {
+        self.next_index += 1;
+        self.next_index - 1
+    }
+
+    /// How many items were numbered by `next_index` so far
+    pub fn indexed(&self) -> usize {
+        self.next_index
+    }
+
+    /// Returns every complete line contained in the data received so far, without line endings.
+    /// Suitable as is for newline delimited JSON streams.
+    pub fn lines(&mut self, data: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for &b in data {
+            if self.skip_lf {
+                self.skip_lf = false;
+                if b == b'\n' { continue; }
+            }
+
+            match b {
+                b'\n' | b'\r' => {
+                    self.skip_lf = b == b'\r';
+                    lines.push(String::from_utf8_lossy(&self.pending).into_owned());
+                    self.pending.clear();
+                },
+                _ => self.pending.push(b),
+            }
+        }
+        lines
+    }
+
+    /// Returns every event fully received so far
+    pub fn events(&mut self, data: &[u8]) -> Vec<Event> {
+        let mut events = Vec::new();
+        for line in self.lines(data) {
+            if line.is_empty() {
+                // the event is dispatched even without data but there's nothing to do with it
+                if self.has_data {
+                    events.push(std::mem::take(&mut self.event));
+                } else {
+                    self.event = Event::default();
+                }
+                self.has_data = false;
+                continue;
+            }
+
+            // comment, usually a keep alive
+            if line.starts_with(':') { continue; }
+
+            let (field, value) = match line.split_once(':') {
+                Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+                None => (line.as_str(), ""),
+            };
+
+            match field {
+                "event" => self.event.event = Some(value.to_string()),
+                "id" => self.event.id = Some(value.to_string()),
+                "data" => {
+                    if self.has_data { self.event.data.push('\n'); }
+                    self.event.data.push_str(value);
+                    self.has_data = true;
+                },
+                _ => { /* retry and unknown fields are ignored */ }
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SSE_FIXTURE: &str = ": keep-alive\r\n\
+event: message_start\r\n\
+id: 1\r\n\
+data: {\"text\":\"héllo \"}\r\n\
+\r\n\
+data: {\"text\":\r\n\
+data: \"wörld 🌍\"}\r\n\
+\r\n\
+event: ping\n\
+\n\
+data: [DONE]\n\n";
+
+    fn expected() -> Vec<Event> {
+        vec![
+            Event { event: Some(String::from("message_start")), id: Some(String::from("1")), data: String::from("{\"text\":\"héllo \"}") },
+            Event { event: None, id: None, data: String::from("{\"text\":\n\"wörld 🌍\"}") },
+            Event { event: None, id: None, data: String::from("[DONE]") },
+        ]
+    }
+
+    #[test]
+    fn events_whole() {
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.events(SSE_FIXTURE.as_bytes()), expected());
+    }
+
+    #[test]
+    fn events_split_at_every_offset() {
+        let bytes = SSE_FIXTURE.as_bytes();
+        for i in 0..=bytes.len() {
+            let mut decoder = Decoder::new();
+            let mut events = decoder.events(&bytes[..i]);
+            events.extend(decoder.events(&bytes[i..]));
+            assert_eq!(events, expected(), "split at {i}");
+        }
+    }
+
+    #[test]
+    fn events_byte_by_byte() {
+        let mut decoder = Decoder::new();
+        let events: Vec<Event> = SSE_FIXTURE.as_bytes()
+            .iter()
+            .flat_map(|b| decoder.events(std::slice::from_ref(b)))
+            .collect();
+        assert_eq!(events, expected());
+    }
+
+    #[test]
+    fn lines_split_at_every_offset() {
+        const NDJSON: &str = "{\"a\":\"ü\"}\n{\"b\":2}\r\n{\"c\"";
+        let bytes = NDJSON.as_bytes();
+        for i in 0..=bytes.len() {
+            let mut decoder = Decoder::new();
+            let mut lines = decoder.lines(&bytes[..i]);
+            lines.extend(decoder.lines(&bytes[i..]));
+            assert_eq!(lines, vec!["{\"a\":\"ü\"}", "{\"b\":2}"], "split at {i}");
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+use serde::de::DeserializeOwned;
+use crate::{LLMEvent, Result};
+
+/// A function the model may ask the application to call
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tool {
+    pub name: String,
+    pub description: String,
+    /// JSON schema of the arguments, must describe an object
+    pub parameters: serde_json::Value,
+}
+
+impl Tool {
+    pub fn new(name: &str, description: &str, parameters: serde_json::Value) -> Self {
+        Self {
+            name: name.to_string(),
+            description: description.to_string(),
+            parameters,
+        }
+    }
+}
+
+/// A complete call requested by the model
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolCall {
+    /// Has to be sent back with the result of the call
+    pub id: String,
+    pub name: String,
+    /// JSON object, as written by the model so not necessarily valid
+    pub arguments: String,
+}
+
+impl ToolCall {
+    pub fn parse_arguments<T: DeserializeOwned>(&self) -> Result<T> {
+        // some models send nothing at all for functions without parameters
+        let arguments = if self.arguments.trim().is_empty() { "{}" } else { self.arguments.as_str() };
+        Ok(serde_json::from_str(arguments)?)
+    }
+}
+
+/// Stitches the `ToolCallDelta` events of a response back into whole calls.
+/// Deltas for a given call share its index, only the first one carries the id and name.
+#[derive(Default)]
+pub struct ToolCallAccumulator {
+    calls: BTreeMap<usize, ToolCall>,
+}
+
+impl ToolCallAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true if the event was a tool call delta
+    pub fn push(&mut self, event: &LLMEvent) -> bool {
+        let LLMEvent::ToolCallDelta { index, id, name, arguments } = event else {
+            return false;
+        };
+
+        let call = self.calls.entry(*index).or_insert_with(|| ToolCall {
+            id: String::new(),
+            name: String::new(),
+            arguments: String::new(),
+        });
+        if let Some(id) = id {
+            call.id.clone_from(id);
+        }
+        if let Some(name) = name {
+            call.name.push_str(name);
+        }
+        call.arguments.push_str(arguments);
+        true
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    /// The calls in the order the model made them, calls it didn't give an id get one
+    pub fn finish(self) -> Vec<ToolCall> {
+        self.calls.into_iter()
+            .map(|(index, mut call)| {
+                if call.id.is_empty() {
+                    call.id = format!("call_{index}");
+                }
+                call
+            })
+            .collect()
+    }
+}
+
+/// The assistant message a streamed answer would be sent back as, its text along with its calls
+#[cfg(test)]
+pub(crate) fn answer_message(events: &[LLMEvent]) -> crate::Message {
+    let mut text = String::new();
+    let mut calls = ToolCallAccumulator::new();
+    for event in events {
+        if let LLMEvent::TextDelta(delta) = event {
+            text.push_str(delta);
+        }
+        calls.push(event);
+    }
+    crate::Message::with_tool_calls(text, calls.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delta(index: usize, id: Option<&str>, name: Option<&str>, arguments: &str) -> LLMEvent {
+        LLMEvent::ToolCallDelta {
+            index,
+            id: id.map(String::from),
+            name: name.map(String::from),
+            arguments: arguments.to_string(),
+        }
+    }
+
+    #[test]
+    fn accumulate_interleaved_calls() {
+        let mut acc = ToolCallAccumulator::new();
+        assert!(!acc.push(&LLMEvent::TextDelta(String::from("hmm"))));
+        acc.push(&delta(1, Some("call_a"), Some("read_file"), ""));
+        acc.push(&delta(2, None, Some("list_dir"), "{\"path\""));
+        acc.push(&delta(1, None, None, "{\"path\":"));
+        acc.push(&delta(1, None, None, "\"src/main.rs\"}"));
+        acc.push(&delta(2, None, None, ":\".\"}"));
+
+        let calls = acc.finish();
+        assert_eq!(calls, vec![
+            ToolCall { id: String::from("call_a"), name: String::from("read_file"), arguments: String::from("{\"path\":\"src/main.rs\"}") },
+            ToolCall { id: String::from("call_2"), name: String::from("list_dir"), arguments: String::from("{\"path\":\".\"}") },
+        ]);
+
+        let args: serde_json::Value = calls[0].parse_arguments().unwrap();
+        assert_eq!(args["path"], "src/main.rs");
+    }
+}